@@ -0,0 +1,272 @@
+//! Remove the results and logs of old runs, and empty backup directories.
+use crate::config::UpConfig;
+use crate::opts::CleanOptions;
+use crate::tasks::runs;
+use crate::utils::files;
+use crate::utils::time::parse_duration;
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use color_eyre::eyre::Context;
+use color_eyre::eyre::Result;
+use color_eyre::eyre::ensure;
+use color_eyre::eyre::eyre;
+use std::fs;
+use std::io;
+use std::io::IsTerminal;
+use std::io::Write;
+use std::time::Duration;
+use std::time::SystemTime;
+use tracing::debug;
+use tracing::info;
+use tracing::trace;
+
+/// Number of runs to keep if the up config doesn't say otherwise.
+const DEFAULT_KEEP_RUNS: usize = 20;
+
+/// Name of the directory inside the up temp dir that contains backups.
+const BACKUP_DIR_NAME: &str = "backup";
+
+/// Which runs to keep.
+#[derive(Debug, Clone, Copy)]
+struct RetentionPolicy {
+    /// Number of most recent runs to keep.
+    keep_runs: usize,
+    /// Also keep runs newer than this.
+    keep_for: Option<Duration>,
+}
+
+impl RetentionPolicy {
+    /// Work out the retention policy from the up config, with overrides from the command line.
+    fn new(config: &UpConfig, keep_runs: Option<usize>, keep_for: Option<&str>) -> Result<Self> {
+        let retention = config.config_yaml.retention.clone().unwrap_or_default();
+        let keep_for = keep_for
+            .or(retention.keep_for.as_deref())
+            .map(parse_duration)
+            .transpose()
+            .wrap_err("Invalid retention keep_for duration.")?;
+        Ok(Self {
+            keep_runs: keep_runs
+                .or(retention.keep_runs)
+                .unwrap_or(DEFAULT_KEEP_RUNS),
+            keep_for,
+        })
+    }
+
+    /// Whether to keep the `index`th most recent run (starting from 0), last modified `age` ago.
+    fn keep(self, index: usize, age: Duration) -> bool {
+        index < self.keep_runs || self.keep_for.is_some_and(|keep_for| age < keep_for)
+    }
+}
+
+/// Everything that cleaning would remove.
+#[derive(Debug, Default)]
+struct Stale {
+    /// Old run directories.
+    runs: Vec<Utf8PathBuf>,
+    /// Old log files.
+    logs: Vec<Utf8PathBuf>,
+    /// Empty backup directories, children before their parents.
+    backups: Vec<Utf8PathBuf>,
+}
+
+impl Stale {
+    /// Find everything the policy doesn't keep. The current run and its log are always kept, as
+    /// are runs still in progress in other `up` processes (and their logs).
+    fn find(config: &UpConfig, policy: RetentionPolicy) -> Result<Self> {
+        let runs_dir = runs::runs_dir(&config.temp_dir);
+        let current_run = runs::run_dir(&config.temp_dir, &config.start_time);
+        let current_log = files::log_path(&config.start_time)?;
+
+        let is_old_run =
+            |path: &Utf8Path| path != current_run && path.is_dir() && !runs::is_in_progress(path);
+        let has_results = |path: &Utf8Path| path.join(runs::RUN_RESULTS_FILE_NAME).exists();
+        let mut runs = stale_entries(&runs_dir, policy, |path| {
+            is_old_run(path) && has_results(path)
+        })?;
+        // Runs that recorded no results (e.g. dry runs) aren't worth keeping, and don't count
+        // towards the runs kept.
+        let keep_none = RetentionPolicy {
+            keep_runs: 0,
+            keep_for: None,
+        };
+        runs.extend(stale_entries(&runs_dir, keep_none, |path| {
+            is_old_run(path) && !has_results(path)
+        })?);
+        runs.sort_unstable();
+        let logs = stale_entries(&files::log_dir()?, policy, |path| {
+            path != current_log
+                && path.is_file()
+                && path.file_name().is_some_and(|name| {
+                    // Logs are named after the run they're for, e.g. `up_<run>.log`.
+                    name.strip_prefix("up_")
+                        .and_then(|name| name.strip_suffix(".log"))
+                        .is_some_and(|run| !runs::is_in_progress(&runs_dir.join(run)))
+                })
+        })?;
+
+        let mut backups = Vec::new();
+        let backup_dir = config.temp_dir.join(BACKUP_DIR_NAME);
+        if backup_dir.is_dir() {
+            find_empty_dirs(&backup_dir, &mut backups)?;
+        }
+        Ok(Self {
+            runs,
+            logs,
+            backups,
+        })
+    }
+
+    /// Whether there is nothing to remove.
+    const fn is_empty(&self) -> bool {
+        self.runs.is_empty() && self.logs.is_empty() && self.backups.is_empty()
+    }
+
+    /// Remove everything.
+    fn remove(&self) -> Result<()> {
+        for run in &self.runs {
+            debug!("Removing run directory {run}");
+            fs::remove_dir_all(run).wrap_err_with(|| eyre!("Failed to remove run {run}"))?;
+        }
+        for log in &self.logs {
+            debug!("Removing log file {log}");
+            fs::remove_file(log).wrap_err_with(|| eyre!("Failed to remove log {log}"))?;
+        }
+        for backup in &self.backups {
+            debug!("Removing empty backup directory {backup}");
+            fs::remove_dir(backup)
+                .wrap_err_with(|| eyre!("Failed to remove backup directory {backup}"))?;
+        }
+        Ok(())
+    }
+}
+
+/// Run `up clean`.
+pub(crate) fn run(config: &UpConfig, opts: &CleanOptions) -> Result<()> {
+    let policy = RetentionPolicy::new(config, opts.keep_runs, opts.keep_for.as_deref())?;
+    debug!("Cleaning with retention policy {policy:?}");
+    let stale = Stale::find(config, policy)?;
+    if stale.is_empty() {
+        info!("Nothing to clean.");
+        return Ok(());
+    }
+
+    for (description, paths) in [
+        ("Old runs", &stale.runs),
+        ("Old logs", &stale.logs),
+        ("Empty backup directories", &stale.backups),
+    ] {
+        if !paths.is_empty() {
+            println!("{description}:");
+            for path in paths {
+                println!("  {path}");
+            }
+        }
+    }
+
+    let verb = if opts.dry_run {
+        "Would remove"
+    } else if opts.yes || confirm()? {
+        stale.remove()?;
+        "Removed"
+    } else {
+        info!("Not removing anything.");
+        return Ok(());
+    };
+    info!(
+        "{verb} {} runs, {} logs, and {} empty backup directories.",
+        stale.runs.len(),
+        stale.logs.len(),
+        stale.backups.len()
+    );
+    Ok(())
+}
+
+/// Ask the user whether to remove what was listed, failing if we can't ask.
+fn confirm() -> Result<bool> {
+    ensure!(
+        io::stdin().is_terminal(),
+        "Not removing anything without confirmation, pass --yes to remove without asking, or \
+         --dry-run to only list what would be removed."
+    );
+    eprint!("Remove these? [y/N] ");
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes" | "Yes"))
+}
+
+/**
+Remove old runs and logs according to the up config's retention policy.
+
+This runs after every `up run`, and always keeps the most recent run so that
+`up run --failed` still works.
+*/
+pub(crate) fn apply_retention(config: &UpConfig) -> Result<()> {
+    let mut policy = RetentionPolicy::new(config, None, None)?;
+    policy.keep_runs = policy.keep_runs.max(1);
+    let stale = Stale::find(config, policy)?;
+    trace!("Removing according to retention policy {policy:?}: {stale:#?}");
+    stale.remove()
+}
+
+/**
+Find the entries in `dir` that the policy doesn't keep, considering only entries that `include`
+returns true for.
+
+Entries are named after the time they were created, so sorting by name sorts them by time.
+*/
+fn stale_entries(
+    dir: &Utf8Path,
+    policy: RetentionPolicy,
+    include: impl Fn(&Utf8Path) -> bool,
+) -> Result<Vec<Utf8PathBuf>> {
+    if !dir.is_dir() {
+        trace!("Directory {dir} doesn't exist, nothing to clean.");
+        return Ok(Vec::new());
+    }
+    let mut entries = Vec::new();
+    for entry in dir
+        .read_dir_utf8()
+        .wrap_err_with(|| eyre!("Failed to read directory {dir}"))?
+    {
+        let path = entry?.into_path();
+        if include(&path) {
+            entries.push(path);
+        }
+    }
+    entries.sort_unstable();
+
+    let now = SystemTime::now();
+    let mut stale = Vec::new();
+    for (index, path) in entries.into_iter().rev().enumerate() {
+        let modified = path.symlink_metadata()?.modified()?;
+        let age = now.duration_since(modified).unwrap_or_default();
+        if !policy.keep(index, age) {
+            stale.push(path);
+        }
+    }
+    Ok(stale)
+}
+
+/**
+Add `dir`'s subdirectories that contain nothing but empty directories to `empty_dirs`, children
+before their parents, followed by `dir` itself if it is also empty.
+
+Returns whether `dir` was empty.
+*/
+fn find_empty_dirs(dir: &Utf8Path, empty_dirs: &mut Vec<Utf8PathBuf>) -> Result<bool> {
+    let mut is_empty = true;
+    for entry in dir
+        .read_dir_utf8()
+        .wrap_err_with(|| eyre!("Failed to read directory {dir}"))?
+    {
+        let entry = entry?;
+        if !(entry.file_type()?.is_dir() && find_empty_dirs(entry.path(), empty_dirs)?) {
+            is_empty = false;
+        }
+    }
+    if is_empty {
+        empty_dirs.push(dir.to_owned());
+    }
+    Ok(is_empty)
+}
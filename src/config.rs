@@ -6,6 +6,7 @@ use crate::opts::RunOptions;
 use crate::opts::SubCommand;
 use crate::opts::start_time::StartTime;
 use crate::tasks::git;
+use crate::tasks::runs::Rerun;
 use crate::utils::files;
 use camino::Utf8Path;
 use camino::Utf8PathBuf;
@@ -22,6 +23,7 @@ use tracing::info;
 use tracing::trace;
 
 /// Internal state used by subcommands.
+#[allow(clippy::struct_excessive_bools)] // Holds the resolved `up run` flags.
 #[derive(Default, Debug)]
 pub struct UpConfig {
     /// Path to the up config file.
@@ -36,8 +38,20 @@ pub struct UpConfig {
     pub tasks: Option<Vec<String>>,
     /// The list of tasks to not execute.
     pub exclude_tasks: Option<Vec<String>>,
+    /// Only execute tasks using these run libraries.
+    pub run_libs: Option<Vec<String>>,
+    /// Only run tasks that failed in the previous run (overrides `tasks`).
+    pub rerun: Option<Rerun>,
     /// Whether task stdout/stderr should inherit from up's stdout/stderr.
     pub console: Option<bool>,
+    /// Only report what tasks would change.
+    pub dry_run: bool,
+    /// Record every task's terminal session.
+    pub record: bool,
+    /// Skip tasks that need privileges, rather than prompting for sudo.
+    pub no_sudo: bool,
+    /// Pick which of the tasks to run in a terminal UI.
+    pub interactive: bool,
     /// Temporary directory to use for up command execution.
     pub temp_dir: Utf8PathBuf,
     /// Time we started this command execution.
@@ -59,6 +73,39 @@ pub struct ConfigYaml {
     pub inherit_env: Option<Vec<String>>,
     /// List of tasks to run in order in bootstrap mode.
     pub bootstrap_tasks: Option<Vec<String>>,
+    /// How long to keep the results and logs of previous runs.
+    pub retention: Option<RetentionConfig>,
+}
+
+/**
+How long to keep the results and logs of previous runs, applied after every `up run` and by
+`up clean`.
+
+A run is kept if it is one of the `keep_runs` most recent runs, or if it is newer than
+`keep_for`. Runs that recorded no results (e.g. dry runs) aren't kept, or counted.
+*/
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RetentionConfig {
+    /// Number of most recent runs to keep (default 20).
+    pub keep_runs: Option<usize>,
+    /// Also keep runs newer than this, e.g. `30d` or `1w 2d`.
+    pub keep_for: Option<String>,
+}
+
+impl ConfigYaml {
+    /// Parse the contents of an up config file, using the default config if it is empty.
+    pub(crate) fn parse(config_str: &str) -> Result<Self> {
+        debug!("config_str: {config_str:?}");
+        let config_yaml = if config_str.is_empty() {
+            debug!("Yaml file was empty, using default config.");
+            Self::default()
+        } else {
+            serde_yaml::from_str::<Self>(config_str)?
+        };
+        debug!("Config_yaml: {config_yaml:?}");
+        Ok(config_yaml)
+    }
 }
 
 impl UpConfig {
@@ -68,6 +115,8 @@ impl UpConfig {
 
         let run_options = match opts.cmd {
             Some(SubCommand::Run(task_opts) | SubCommand::List(task_opts)) => task_opts,
+            Some(SubCommand::Watch(watch_opts)) => watch_opts.run_options,
+            Some(SubCommand::Graph(graph_opts)) => graph_opts.run_options,
             _ => RunOptions::default(),
         };
 
@@ -94,16 +143,8 @@ impl UpConfig {
         };
 
         let up_yaml_path = if up_yaml_path.exists() {
-            let read_result = fs::read(&up_yaml_path);
-            if let Ok(file_contents) = read_result {
-                let config_str = String::from_utf8_lossy(&file_contents);
-                debug!("config_str: {config_str:?}");
-                if config_str.is_empty() {
-                    debug!("Yaml file was empty, using default config.");
-                } else {
-                    config_yaml = serde_yaml::from_str::<ConfigYaml>(&config_str)?;
-                }
-                debug!("Config_yaml: {config_yaml:?}");
+            if let Ok(file_contents) = fs::read(&up_yaml_path) {
+                config_yaml = ConfigYaml::parse(&String::from_utf8_lossy(&file_contents))?;
             }
             Some(up_yaml_path)
         } else if config_path_explicitly_specified {
@@ -114,6 +155,13 @@ impl UpConfig {
 
         let bootstrap = run_options.bootstrap;
         let keep_going = run_options.keep_going;
+        let rerun = if run_options.last_failed_and_dependents {
+            Some(Rerun::FailedAndDependents)
+        } else if run_options.failed {
+            Some(Rerun::Failed)
+        } else {
+            None
+        };
 
         Ok(Self {
             up_yaml_path,
@@ -123,8 +171,14 @@ impl UpConfig {
             temp_dir: opts.temp_dir.as_ref().to_owned(),
             tasks: run_options.tasks,
             exclude_tasks: run_options.exclude_tasks,
+            run_libs: run_options.run_libs,
+            rerun,
             start_time: opts.start_time,
             console: run_options.console,
+            dry_run: run_options.dry_run,
+            record: run_options.record,
+            no_sudo: run_options.no_sudo,
+            interactive: run_options.interactive,
         })
     }
 
@@ -142,7 +196,7 @@ impl UpConfig {
     ///
     /// If the default is used, the file will be returned, even it the config
     /// path doesn't exist.
-    fn get_up_yaml_path(args_config_path: &str) -> Result<Utf8PathBuf> {
+    pub(crate) fn get_up_yaml_path(args_config_path: &str) -> Result<Utf8PathBuf> {
         debug!("args_config_file: {args_config_path}");
         let mut config_path: Utf8PathBuf;
         if args_config_path == "$XDG_CONFIG_HOME/up/up.yaml" {
@@ -13,7 +13,7 @@ pub(crate) fn run(cmd_opts: DocOptions) -> Result<()> {
     let DocOptions { subcmd } = cmd_opts;
 
     match subcmd {
-        DocSubcommand::Completions(subcmd_opts) => completions::run(subcmd_opts),
+        DocSubcommand::Completions(subcmd_opts) => completions::run(subcmd_opts)?,
         DocSubcommand::Schema(subcmd_opts) => schema::run(subcmd_opts)?,
         DocSubcommand::Manpages(subcmd_opts) => manpages::run(subcmd_opts)?,
         DocSubcommand::Markdown => markdown::run(),
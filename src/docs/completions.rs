@@ -1,10 +1,174 @@
-//! Generates up CLI completions.
+/*!
+Generates up CLI completions.
+
+Static completions are generated by clap from the CLI definition. Dynamic completions call back
+into up (with `COMPLETE=<shell>` set) while completing, so they can also suggest the task names
+from the up config being used, and the names of the run libraries (including external ones).
+*/
+use crate::config::UpConfig;
 use crate::opts::CompletionsOptions;
 use crate::opts::Opts;
+use crate::tasks::external;
+use crate::tasks::run_lib::RunLibs;
+use crate::tasks::task::Task;
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
 use clap::CommandFactory;
+use clap_complete::CompletionCandidate;
+use clap_complete::env::Shells;
+use color_eyre::Result;
+use color_eyre::eyre::eyre;
+use std::collections::BTreeSet;
+use std::env;
+use std::ffi::OsString;
+use std::io;
+use std::path::Path;
+
+/// Env var that makes up print completions instead of running a command.
+pub(crate) const COMPLETE_ENV_NAME: &str = "COMPLETE";
+
+/// Default value of the `--config` option, meaning "work out the config path".
+const DEFAULT_CONFIG_ARG: &str = "$XDG_CONFIG_HOME/up/up.yaml";
 
 /// Run the `up completions` command.
-pub(crate) fn run(cmd_opts: CompletionsOptions) {
-    let CompletionsOptions { shell } = cmd_opts;
-    clap_complete::generate(shell, &mut Opts::command(), "up", &mut std::io::stdout());
+pub(crate) fn run(cmd_opts: CompletionsOptions) -> Result<()> {
+    let CompletionsOptions { shell, dynamic } = cmd_opts;
+    if dynamic {
+        let shell_name = shell.to_string();
+        let shells = Shells::builtins();
+        let completer = shells
+            .completer(&shell_name)
+            .ok_or_else(|| eyre!("Dynamic completions aren't supported for {shell_name}."))?;
+        completer.write_registration(COMPLETE_ENV_NAME, "up", "up", "up", &mut io::stdout())?;
+    } else {
+        clap_complete::generate(shell, &mut Opts::command(), "up", &mut io::stdout());
+    }
+    Ok(())
+}
+
+/**
+Complete task names from the tasks directory of the up config the command line being completed
+would use.
+
+Each task's description (and `run_lib`, if it has one) is shown as help, if the shell supports
+it.
+*/
+pub(crate) fn task_candidates() -> Vec<CompletionCandidate> {
+    let Ok(tasks_dir) = completing_tasks_dir() else {
+        return Vec::new();
+    };
+    let Ok(entries) = tasks_dir.read_dir_utf8() else {
+        return Vec::new();
+    };
+    let mut candidates: Vec<CompletionCandidate> = entries
+        .filter_map(Result::ok)
+        .filter(|entry| entry.path().is_file())
+        .filter_map(|entry| Task::from(entry.path()).ok())
+        .map(|task| {
+            let help = match (task.config.description, task.config.run_lib) {
+                (Some(description), Some(run_lib)) => {
+                    Some(format!("{description} (run_lib: {run_lib})"))
+                }
+                (Some(description), None) => Some(description),
+                (None, Some(run_lib)) => Some(format!("run_lib: {run_lib}")),
+                (None, None) => None,
+            };
+            CompletionCandidate::new(task.name).help(help.map(Into::into))
+        })
+        .collect();
+    candidates.sort_by(|a, b| a.get_value().cmp(b.get_value()));
+    candidates
+}
+
+/**
+Complete the names of the run libraries: those built into up, and the external `up-lib-<name>`
+executables found next to the up config or in `$PATH`.
+*/
+pub(crate) fn run_lib_candidates() -> Vec<CompletionCandidate> {
+    let lib_dirs = completing_up_yaml_path()
+        .ok()
+        .and_then(|path| path.parent().map(Utf8Path::to_owned))
+        .into_iter()
+        .collect::<Vec<_>>();
+    let builtin = RunLibs::builtin();
+    let mut names = builtin
+        .names()
+        .map(ToOwned::to_owned)
+        .collect::<BTreeSet<_>>();
+    names.extend(external::lib_names(&lib_dirs));
+    names.into_iter().map(CompletionCandidate::new).collect()
+}
+
+/// Whether `path` could be an up config file, used to filter `--config` completions.
+pub(crate) fn is_config_file(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension == "yaml" || extension == "yml")
+}
+
+/**
+The up config path for the command line being completed.
+
+While completing, up is run as `up -- up <args being completed>`, so look for a `-c`/`--config`
+option after the `--`, falling back to the default config path.
+*/
+fn completing_up_yaml_path() -> Result<Utf8PathBuf> {
+    let args: Vec<OsString> = env::args_os()
+        .skip_while(|arg| arg != "--")
+        .skip(1)
+        .collect();
+    let config_arg = config_arg(&args).unwrap_or_else(|| DEFAULT_CONFIG_ARG.to_owned());
+    let config_arg = shellexpand::tilde(&config_arg).into_owned();
+    UpConfig::get_up_yaml_path(&config_arg)
+}
+
+/// The tasks directory for the command line being completed.
+fn completing_tasks_dir() -> Result<Utf8PathBuf> {
+    let up_yaml_path = completing_up_yaml_path()?;
+    Ok(up_yaml_path.parent().map_or_else(
+        || Utf8Path::new("tasks").to_owned(),
+        |dir| dir.join("tasks"),
+    ))
+}
+
+/// The value of the last `-c`/`--config` option in `args`, if any.
+fn config_arg(args: &[OsString]) -> Option<String> {
+    let mut config = None;
+    let mut args = args.iter().filter_map(|arg| arg.to_str());
+    while let Some(arg) = args.next() {
+        if arg == "-c" || arg == "--config" {
+            config = args.next().map(ToOwned::to_owned);
+        } else if let Some(value) = arg.strip_prefix("--config=") {
+            config = Some(value.to_owned());
+        } else if let Some(value) = arg.strip_prefix("-c")
+            && !value.is_empty()
+        {
+            config = Some(value.to_owned());
+        }
+    }
+    config
+}
+
+#[cfg(test)]
+mod tests {
+    use color_eyre::Result;
+    use std::ffi::OsString;
+    use testutils::ensure_eq;
+
+    #[test]
+    fn test_config_arg() -> Result<()> {
+        let args = |args: &[&str]| args.iter().map(OsString::from).collect::<Vec<_>>();
+        ensure_eq!(
+            None,
+            super::config_arg(&args(&["up", "run", "--tasks", ""]))
+        );
+        ensure_eq!(
+            Some("a.yaml".to_owned()),
+            super::config_arg(&args(&["up", "-c", "a.yaml", "run"]))
+        );
+        ensure_eq!(
+            Some("b.yaml".to_owned()),
+            super::config_arg(&args(&["up", "--config=a.yaml", "-cb.yaml", "run"]))
+        );
+        Ok(())
+    }
 }
@@ -14,6 +14,10 @@ The `UP_EXIT_CODE_SKIPPED` maps to the exit code that will cause the task to be
 
 This is currently exit code 204 (HTTP 204 means "No Content").
 
+### `UP_CHANGES`
+
+The `UP_CHANGES` maps to a file (different for each task) that tasks can write change records to, one per line, as tab-separated `kind`, `target`, `before`, and `after` fields. The changes are listed in the summary at the end of the run.
+
 ### `UP_HARDWARE_UUID`
 
 (macOS only)
@@ -1,86 +1,237 @@
 /*!
-Run a command in a fake tty
-Copied from <https://github.com/dtolnay/faketty/>, which unfortunately doesn't offer a library (see <https://github.com/dtolnay/faketty/issues/10>).
+Run a command in a fake tty.
+Originally copied from <https://github.com/dtolnay/faketty/>, which unfortunately doesn't offer a
+library (see <https://github.com/dtolnay/faketty/issues/10>).
+
+`up faketty` ([`run`]) proxies a command through pseudo-terminals: one for stdout and one for
+stderr (or a single one for both with `--merge-output`). Terminal size changes and signals are
+passed on to the command, and if up's stdin is a terminal, input is passed through too.
+
+[`run_in_pty`] is used by tasks with `tty: true` to do the same in-process, capturing the output
+to a file, and optionally recording it (see [`asciicast`]).
 */
 
 #![deny(unsafe_op_in_unsafe_fn)]
-#![allow(
-    clippy::empty_enum,
-    clippy::indexing_slicing,
-    clippy::let_underscore_untyped,
-    clippy::missing_docs_in_private_items,
-    clippy::needless_pass_by_value,
-    clippy::undocumented_unsafe_blocks,
-    clippy::uninlined_format_args
-)]
+#![allow(clippy::indexing_slicing, clippy::let_underscore_untyped)]
+
+pub(crate) mod asciicast;
 
+use crate::faketty::asciicast::Recorder;
+use crate::faketty::asciicast::Recording;
 use crate::opts::FakettyOptions;
+use camino::Utf8Path;
 use color_eyre::Result;
+use color_eyre::eyre::eyre;
+use duct::Expression;
+use nix::errno::Errno;
+use nix::fcntl;
+use nix::fcntl::FcntlArg;
+use nix::fcntl::FdFlag;
+use nix::libc;
 use nix::pty;
-use nix::pty::ForkptyResult;
 use nix::pty::Winsize;
-use nix::sys::wait;
-use nix::sys::wait::WaitStatus;
+use nix::sys::signal;
+use nix::sys::signal::Signal;
+use nix::sys::termios;
+use nix::sys::termios::OutputFlags;
+use nix::sys::termios::SetArg;
+use nix::sys::termios::Termios;
 use nix::unistd;
 use nix::unistd::Pid;
-use std::ffi::CString;
+use signal_hook::consts::signal::SIGHUP;
+use signal_hook::consts::signal::SIGINT;
+use signal_hook::consts::signal::SIGQUIT;
+use signal_hook::consts::signal::SIGTERM;
+use signal_hook::consts::signal::SIGUSR1;
+use signal_hook::consts::signal::SIGUSR2;
+use signal_hook::consts::signal::SIGWINCH;
+use signal_hook::iterator::Signals;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::io::Write;
 use std::os::fd::AsFd;
+use std::os::fd::AsRawFd;
 use std::os::fd::BorrowedFd;
-use std::os::unix::ffi::OsStrExt;
+use std::os::fd::OwnedFd;
+use std::os::unix::process::CommandExt;
+use std::os::unix::process::ExitStatusExt;
 use std::process;
+use std::process::Command;
+use std::process::Output;
+use std::thread;
 
-enum Exec {}
-
+// SAFETY: the standard file descriptors are open for the life of the process.
+/// Our stdin.
 const STDIN: BorrowedFd = unsafe { BorrowedFd::borrow_raw(0) };
+// SAFETY: the standard file descriptors are open for the life of the process.
+/// Our stdout.
 const STDOUT: BorrowedFd = unsafe { BorrowedFd::borrow_raw(1) };
+// SAFETY: the standard file descriptors are open for the life of the process.
+/// Our stderr.
 const STDERR: BorrowedFd = unsafe { BorrowedFd::borrow_raw(2) };
 
+/// Size of the terminals we create if up isn't running in a terminal.
+const WINSIZE: Winsize = Winsize {
+    ws_row: 24,
+    ws_col: 80,
+    ws_xpixel: 0,
+    ws_ypixel: 0,
+};
+
+/// Signals passed on to the command, rather than handled by up.
+const FORWARDED_SIGNALS: [i32; 6] = [SIGHUP, SIGINT, SIGQUIT, SIGTERM, SIGUSR1, SIGUSR2];
+
+/// Run a command in a fake tty, exiting with its exit code (or `128 + signal` if it was killed by
+/// a signal).
 pub(crate) fn run(faketty_options: FakettyOptions) -> Result<()> {
-    let args: Vec<CString> = faketty_options
-        .program
+    let code = proxy(faketty_options)?;
+    process::exit(code);
+}
+
+/// Run the command, returning the exit code up should exit with.
+fn proxy(faketty_options: FakettyOptions) -> Result<i32> {
+    let FakettyOptions {
+        program,
+        merge_output,
+    } = faketty_options;
+    let (program, args) = program
+        .split_first()
+        .ok_or_else(|| eyre!("No program to run was passed."))?;
+
+    let winsize = terminal_size().unwrap_or(WINSIZE);
+    let stdout_pty = pty::openpty(&winsize, None)?;
+    let stderr_pty = if merge_output {
+        None
+    } else {
+        Some(pty::openpty(&winsize, None)?)
+    };
+    for pty in [Some(&stdout_pty), stderr_pty.as_ref()]
         .into_iter()
-        .map(|os_string| CString::new(os_string.as_bytes()))
-        .collect::<Result<_, _>>()?;
-
-    let new_stdin = STDIN.try_clone_to_owned()?;
-    let new_stderr = STDERR.try_clone_to_owned()?;
-    let pty1 = unsafe { forkpty() }?;
-    if let ForkptyResult::Parent { child, master } = pty1 {
-        copyfd(master.as_fd(), STDOUT);
-        copyexit(child);
+        .flatten()
+    {
+        set_cloexec(&pty.master)?;
+        set_cloexec(&pty.slave)?;
     }
-    let new_stdout = STDOUT.try_clone_to_owned()?;
-    let pty2 = unsafe { forkpty() }?;
-    if let ForkptyResult::Parent { child, master } = pty2 {
-        copyfd(master.as_fd(), new_stderr.as_fd());
-        copyexit(child);
+    let stdin_is_tty = unistd::isatty(STDIN).unwrap_or(false);
+
+    // Register before starting the command so no signals are missed.
+    let mut signals = Signals::new(FORWARDED_SIGNALS.iter().chain(&[SIGWINCH]))?;
+
+    let mut command = Command::new(program);
+    command.args(args).stdout(stdout_pty.slave.try_clone()?);
+    match &stderr_pty {
+        Some(stderr_pty) => command.stderr(stderr_pty.slave.try_clone()?),
+        None => command.stderr(stdout_pty.slave.try_clone()?),
+    };
+    if stdin_is_tty {
+        command.stdin(stdout_pty.slave.try_clone()?);
     }
-    unistd::dup2_stdin(new_stdin)?;
-    unistd::dup2_stdout(new_stdout)?;
-    exec(args).map(|_| ())
-}
+    // SAFETY: only calls async-signal-safe functions.
+    unsafe { command.pre_exec(set_controlling_terminal) };
+    let mut child = command.spawn()?;
+    // Close our copies of the terminals, so reading from them stops when the command exits.
+    drop(command);
+    drop(stdout_pty.slave);
+    let stderr_master = stderr_pty.map(|stderr_pty| stderr_pty.master);
 
-unsafe fn forkpty() -> Result<ForkptyResult> {
-    let winsize = Winsize {
-        ws_row: 24,
-        ws_col: 80,
-        ws_xpixel: 0,
-        ws_ypixel: 0,
+    let child_pid = Pid::from_raw(i32::try_from(child.id())?);
+    let mut masters = vec![stdout_pty.master.try_clone()?];
+    if let Some(stderr_master) = &stderr_master {
+        masters.push(stderr_master.try_clone()?);
+    }
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            if signal == SIGWINCH {
+                if let Some(winsize) = terminal_size() {
+                    for master in &masters {
+                        let _ = set_terminal_size(master.as_fd(), &winsize);
+                    }
+                }
+            } else if let Ok(signal) = Signal::try_from(signal) {
+                // The command leads its own session (and process group), so signal anything it
+                // started too.
+                let _ = signal::killpg(child_pid, signal);
+            }
+        }
+    });
+
+    // Put our terminal in raw mode so keypresses (including Ctrl-C) go straight to the command's
+    // terminal, which handles them.
+    let raw_mode = if stdin_is_tty {
+        let raw_mode = RawMode::enable(STDIN)?;
+        let stdin_master = stdout_pty.master.try_clone()?;
+        thread::spawn(move || copyfd(STDIN, stdin_master.as_fd()));
+        Some(raw_mode)
+    } else {
+        None
     };
-    let termios = None;
-    let result = unsafe { pty::forkpty(&winsize, termios) }?;
-    Ok(result)
+
+    let stdout_copier = thread::spawn(move || copyfd(stdout_pty.master.as_fd(), STDOUT));
+    let stderr_copier = stderr_master
+        .map(|stderr_master| thread::spawn(move || copyfd(stderr_master.as_fd(), STDERR)));
+    let status = child.wait()?;
+    let _ = stdout_copier.join();
+    if let Some(stderr_copier) = stderr_copier {
+        let _ = stderr_copier.join();
+    }
+    drop(raw_mode);
+
+    Ok(status
+        .code()
+        .or_else(|| status.signal().map(|signal| 128 + signal))
+        .unwrap_or(1))
+}
+
+/// Size of the terminal up is running in, if any.
+fn terminal_size() -> Option<Winsize> {
+    [STDIN, STDOUT, STDERR].into_iter().find_map(|fd| {
+        let mut winsize = WINSIZE;
+        // SAFETY: `TIOCGWINSZ` writes a `winsize` to the pointer passed.
+        let result = unsafe { libc::ioctl(fd.as_raw_fd(), libc::TIOCGWINSZ, &raw mut winsize) };
+        Errno::result(result).ok().map(|_| winsize)
+    })
+}
+
+/// Resize the terminal `master` is the controlling side of, which signals its processes.
+fn set_terminal_size(master: BorrowedFd, winsize: &Winsize) -> io::Result<()> {
+    // SAFETY: `TIOCSWINSZ` reads a `winsize` from the pointer passed.
+    let result = unsafe { libc::ioctl(master.as_raw_fd(), libc::TIOCSWINSZ, &raw const *winsize) };
+    Errno::result(result)?;
+    Ok(())
+}
+
+/// Puts a terminal in raw mode, restoring its previous settings when dropped.
+struct RawMode {
+    /// The terminal.
+    fd: OwnedFd,
+    /// Settings to restore.
+    original: Termios,
 }
 
-fn exec(args: Vec<CString>) -> Result<Exec> {
-    let args: Vec<_> = args.iter().map(CString::as_c_str).collect();
-    unistd::execvp(args[0], &args)?;
-    unreachable!();
+impl RawMode {
+    /// Put the terminal `fd` in raw mode.
+    fn enable(fd: BorrowedFd) -> io::Result<Self> {
+        let original = termios::tcgetattr(fd)?;
+        let mut raw = original.clone();
+        termios::cfmakeraw(&mut raw);
+        termios::tcsetattr(fd, SetArg::TCSANOW, &raw)?;
+        Ok(Self {
+            fd: fd.try_clone_to_owned()?,
+            original,
+        })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = termios::tcsetattr(&self.fd, SetArg::TCSANOW, &self.original);
+    }
 }
 
+/// Copy from `read` to `write` until `read` is closed (or errors).
 fn copyfd(read: BorrowedFd, write: BorrowedFd) {
-    const BUF: usize = 4096;
-    let mut buf = [0; BUF];
+    let mut buf = [0; 4096];
     loop {
         match unistd::read(read, &mut buf) {
             Ok(0) | Err(_) => return,
@@ -91,6 +242,7 @@ fn copyfd(read: BorrowedFd, write: BorrowedFd) {
     }
 }
 
+/// Write all of `buf` to `fd`.
 fn write_all(fd: BorrowedFd, mut buf: &[u8]) -> Result<()> {
     while !buf.is_empty() {
         let n = unistd::write(fd, buf)?;
@@ -99,10 +251,171 @@ fn write_all(fd: BorrowedFd, mut buf: &[u8]) -> Result<()> {
     Ok(())
 }
 
-fn copyexit(child: Pid) -> ! {
-    let flag = None;
-    process::exit(match wait::waitpid(child, flag) {
-        Ok(WaitStatus::Exited(_pid, code)) => code,
-        _ => 0,
-    });
+/**
+Run `command` with its stdout and stderr attached to a new pseudo-terminal (and stdin from
+`/dev/null`), writing everything it prints to `output_path`.
+
+The terminal doesn't translate `\n` to `\r\n`, so the saved output looks like it would without
+a terminal. If `strip_ansi` is set, ANSI escape sequences (colours, cursor movement, etc.) are
+removed from the saved output.
+
+If `recording` is passed, the unstripped output is also saved with its timing as an asciicast
+file.
+*/
+pub(crate) fn run_in_pty(
+    command: &Expression,
+    output_path: &Utf8Path,
+    strip_ansi: bool,
+    recording: Option<&Recording>,
+) -> io::Result<Output> {
+    let pty = pty::openpty(&WINSIZE, None)?;
+    set_cloexec(&pty.master)?;
+    set_cloexec(&pty.slave)?;
+    let mut attrs = termios::tcgetattr(&pty.slave)?;
+    attrs.output_flags.remove(OutputFlags::ONLCR);
+    termios::tcsetattr(&pty.slave, SetArg::TCSANOW, &attrs)?;
+    let mut recorder = recording
+        .map(|recording| Recorder::create(recording, WINSIZE.ws_col, WINSIZE.ws_row))
+        .transpose()?;
+
+    // The expression holding our copies of the terminal is dropped once the command has started,
+    // so reading from the terminal stops when the command (and anything it started) exits.
+    let handle = command
+        .stdin_null()
+        .stdout_file(pty.slave.try_clone()?)
+        .stderr_file(pty.slave)
+        .before_spawn(|cmd| {
+            // SAFETY: only calls async-signal-safe functions.
+            unsafe { cmd.pre_exec(set_controlling_terminal) };
+            Ok(())
+        })
+        .start()?;
+
+    let mut output_file = File::create(output_path)?;
+    let mut stripper = strip_ansi.then(AnsiStripper::default);
+    let mut master = File::from(pty.master);
+    let mut buf = [0; 4096];
+    let mut text = Vec::new();
+    loop {
+        let n = match master.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            // Linux returns EIO once every copy of the other side of the terminal is closed.
+            Err(e) if e.raw_os_error() == Some(Errno::EIO as i32) => break,
+            Err(e) => return Err(e),
+        };
+        let chunk = &buf[..n];
+        if let Some(recorder) = &mut recorder {
+            recorder.output(chunk)?;
+        }
+        match &mut stripper {
+            Some(stripper) => {
+                text.clear();
+                stripper.strip(chunk, &mut text);
+                output_file.write_all(&text)?;
+            }
+            None => output_file.write_all(chunk)?,
+        }
+    }
+    if let Some(recorder) = recorder {
+        recorder.finish()?;
+    }
+    handle.wait().cloned()
+}
+
+/// Don't let commands we (or other tasks running in parallel) start inherit `fd`, so the other
+/// side of a terminal is only held open by the command it was opened for.
+fn set_cloexec(fd: &impl AsFd) -> io::Result<()> {
+    fcntl::fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
+    Ok(())
+}
+
+/// Start a new session with the terminal on stdout as its controlling terminal. Run in the child
+/// process between `fork` and `exec`, after stdio has been set up.
+fn set_controlling_terminal() -> io::Result<()> {
+    unistd::setsid()?;
+    // SAFETY: `TIOCSCTTY` takes an integer argument, and stdout is a terminal.
+    let result = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCSCTTY, 0) };
+    Errno::result(result)?;
+    Ok(())
+}
+
+/// Where an [`AnsiStripper`] is in an escape sequence.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum AnsiState {
+    /// Not in an escape sequence.
+    #[default]
+    Text,
+    /// After an `ESC`.
+    Escape,
+    /// In a Control Sequence Introducer (`ESC [`) sequence, e.g. a colour.
+    Csi,
+    /// In an Operating System Command (`ESC ]`) sequence, e.g. a window title.
+    Osc,
+    /// After an `ESC` in an Operating System Command, which might be the terminator.
+    OscEscape,
+}
+
+/// Removes ANSI escape sequences from a stream of bytes, which may split sequences across
+/// chunks.
+#[derive(Debug, Default)]
+struct AnsiStripper {
+    /// Current position in any escape sequence.
+    state: AnsiState,
+}
+
+impl AnsiStripper {
+    /// Escape character that starts every sequence.
+    const ESC: u8 = 0x1b;
+    /// Bell character, which can end an Operating System Command.
+    const BEL: u8 = 0x07;
+
+    /// Append the bytes in `input` that aren't part of escape sequences to `output`.
+    fn strip(&mut self, input: &[u8], output: &mut Vec<u8>) {
+        for &byte in input {
+            self.state = match (self.state, byte) {
+                // Intermediate bytes (e.g. the `(` in `ESC ( B`) keep us in the escape.
+                (AnsiState::Text, Self::ESC) | (AnsiState::Escape, 0x20..=0x2f) => {
+                    AnsiState::Escape
+                }
+                (AnsiState::Text, _) => {
+                    output.push(byte);
+                    AnsiState::Text
+                }
+                (AnsiState::Escape, b'[') => AnsiState::Csi,
+                (AnsiState::Escape, b']') => AnsiState::Osc,
+                (AnsiState::Escape | AnsiState::Csi, 0x40..=0x7e) | (AnsiState::Escape, _) => {
+                    AnsiState::Text
+                }
+                (AnsiState::Csi, _) => AnsiState::Csi,
+                (AnsiState::Osc, Self::BEL) | (AnsiState::OscEscape, b'\\') => AnsiState::Text,
+                (AnsiState::Osc | AnsiState::OscEscape, Self::ESC) => AnsiState::OscEscape,
+                (AnsiState::Osc | AnsiState::OscEscape, _) => AnsiState::Osc,
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AnsiStripper;
+    use testutils::ensure_eq;
+
+    #[test]
+    fn test_strip_ansi() -> color_eyre::Result<()> {
+        let input = b"\x1b[1;31mred\x1b[0m \x1b]0;title\x07plain \x1b(Bdone\n";
+        let mut output = Vec::new();
+        AnsiStripper::default().strip(input, &mut output);
+        ensure_eq!("red plain done\n", String::from_utf8(output)?);
+
+        // Sequences split across chunks are still removed.
+        let mut stripper = AnsiStripper::default();
+        let mut output = Vec::new();
+        for chunk in input.chunks(3) {
+            stripper.strip(chunk, &mut output);
+        }
+        ensure_eq!("red plain done\n", String::from_utf8(output)?);
+        Ok(())
+    }
 }
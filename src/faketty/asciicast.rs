@@ -0,0 +1,194 @@
+/*!
+Record and replay terminal sessions as [asciicast v2] files.
+
+Each file is a JSON header line, followed by one `[seconds, "o", "output"]` line for each chunk
+of output the command wrote, with the time since the start of the recording.
+
+[asciicast v2]: https://docs.asciinema.org/manual/asciicast/v2/
+*/
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use color_eyre::eyre::Context;
+use color_eyre::eyre::Result;
+use color_eyre::eyre::bail;
+use color_eyre::eyre::eyre;
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::str;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+/// The asciicast format version we write and can replay.
+const VERSION: u8 = 2;
+
+/// Event type for output written by the command.
+const OUTPUT_EVENT: &str = "o";
+
+/// Where to save a recording, and what to call it.
+#[derive(Debug)]
+pub(crate) struct Recording {
+    /// File to write the recording to.
+    pub(crate) path: Utf8PathBuf,
+    /// Title shown by players.
+    pub(crate) title: String,
+}
+
+/// The first line of an asciicast file.
+#[derive(Debug, Serialize, Deserialize)]
+struct Header {
+    /// Format version, always 2.
+    version: u8,
+    /// Terminal width in columns.
+    width: u16,
+    /// Terminal height in rows.
+    height: u16,
+    /// When the recording started, in seconds since the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timestamp: Option<i64>,
+    /// Title of the recording.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    title: Option<String>,
+}
+
+/// Writes output events to an asciicast file as they happen.
+#[derive(Debug)]
+pub(crate) struct Recorder {
+    /// The recording file.
+    file: File,
+    /// When the recording started, event times are relative to this.
+    start: Instant,
+    /// Bytes from the end of the last chunk that are the start of an incomplete UTF-8 character.
+    partial: Vec<u8>,
+}
+
+impl Recorder {
+    /// Start a recording of a `width` x `height` terminal.
+    pub(crate) fn create(recording: &Recording, width: u16, height: u16) -> io::Result<Self> {
+        let mut file = File::create(&recording.path)?;
+        let header = Header {
+            version: VERSION,
+            width,
+            height,
+            timestamp: Some(chrono::Utc::now().timestamp()),
+            title: Some(recording.title.clone()),
+        };
+        writeln!(file, "{}", serde_json::to_string(&header)?)?;
+        Ok(Self {
+            file,
+            start: Instant::now(),
+            partial: Vec::new(),
+        })
+    }
+
+    /// Record some output from the command.
+    pub(crate) fn output(&mut self, data: &[u8]) -> io::Result<()> {
+        self.partial.extend_from_slice(data);
+        let complete = match str::from_utf8(&self.partial) {
+            // Keep an incomplete character at the end for the next chunk, anything else invalid
+            // is replaced below.
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            _ => self.partial.len(),
+        };
+        let rest = self.partial.split_off(complete);
+        let text = String::from_utf8_lossy(&self.partial).into_owned();
+        self.partial = rest;
+        self.write_event(&text)
+    }
+
+    /// Record any incomplete output left over at the end of the session.
+    pub(crate) fn finish(mut self) -> io::Result<()> {
+        let text = String::from_utf8_lossy(&self.partial).into_owned();
+        self.write_event(&text)
+    }
+
+    /// Write an output event containing `text`.
+    fn write_event(&mut self, text: &str) -> io::Result<()> {
+        if text.is_empty() {
+            return Ok(());
+        }
+        // Our terminals don't add carriage returns to newlines (see `run_in_pty()`), but the
+        // terminals the recording is replayed in need them.
+        let text = text.replace('\n', "\r\n");
+        let event = (self.start.elapsed().as_secs_f64(), OUTPUT_EVENT, text);
+        writeln!(self.file, "{}", serde_json::to_string(&event)?)
+    }
+}
+
+/// Write the output in the recording at `path` to `out`, with the original timing.
+pub(crate) fn replay(path: &Utf8Path, out: &mut impl Write) -> Result<()> {
+    let contents =
+        fs::read_to_string(path).wrap_err_with(|| eyre!("Failed to read recording {path}"))?;
+    let mut lines = contents.lines();
+    let header: Header = serde_json::from_str(lines.next().unwrap_or_default())
+        .wrap_err_with(|| eyre!("Invalid recording header in {path}"))?;
+    if header.version != VERSION {
+        bail!(
+            "Recording {path} is asciicast version {version}, only version {VERSION} is supported.",
+            version = header.version,
+        );
+    }
+
+    let start = Instant::now();
+    for line in lines.filter(|line| !line.trim().is_empty()) {
+        let (time, event_type, data): (f64, String, String) = serde_json::from_str(line)
+            .wrap_err_with(|| eyre!("Invalid recording event in {path}: {line}"))?;
+        if event_type != OUTPUT_EVENT {
+            continue;
+        }
+        let wait = Duration::try_from_secs_f64(time)
+            .unwrap_or_default()
+            .saturating_sub(start.elapsed());
+        if !wait.is_zero() {
+            thread::sleep(wait);
+        }
+        out.write_all(data.as_bytes())?;
+        out.flush()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Recorder;
+    use super::Recording;
+    use color_eyre::Result;
+    use std::fs;
+    use testutils::ensure_eq;
+
+    #[test]
+    fn test_record_and_replay() -> Result<()> {
+        let temp_dir = testutils::temp_dir("up", testutils::function_path!())?;
+        let recording = Recording {
+            path: temp_dir.join("session.cast"),
+            title: "test".to_owned(),
+        };
+
+        let mut recorder = Recorder::create(&recording, 80, 24)?;
+        // A multi-byte character split across chunks.
+        let text = "caf\u{e9}\n".as_bytes();
+        let (start, end) = text.split_at(4);
+        recorder.output(start)?;
+        recorder.output(end)?;
+        recorder.finish()?;
+
+        let contents = fs::read_to_string(&recording.path)?;
+        let lines: Vec<_> = contents.lines().collect();
+        ensure_eq!(3, lines.len());
+        ensure_eq!(
+            true,
+            lines
+                .get(2)
+                .is_some_and(|line| line.ends_with(r#","o","é\r\n"]"#))
+        );
+
+        let mut replayed = Vec::new();
+        super::replay(&recording.path, &mut replayed)?;
+        ensure_eq!("caf\u{e9}\r\n", String::from_utf8(replayed)?);
+        Ok(())
+    }
+}
@@ -4,10 +4,10 @@ use super::GENERATED_PRELUDE_COMMENT;
 use crate::opts::GenerateGitConfig;
 use crate::tasks::ResolveEnv;
 use crate::tasks::TaskError;
+use crate::tasks::changes::Change;
 use crate::tasks::git::GitConfig;
 use crate::tasks::git::GitRemote;
 use crate::tasks::task::Task;
-use crate::tasks::task::TaskStatus;
 use crate::utils::files;
 use camino::Utf8Path;
 use camino::Utf8PathBuf;
@@ -25,23 +25,20 @@ use tracing::info;
 use tracing::trace;
 use walkdir::WalkDir;
 
-/// Run the up git config generation on a set of directories.
-pub fn run(configs: &[GenerateGitConfig]) -> Result<TaskStatus> {
-    let (statuses, errors): (Vec<_>, Vec<_>) =
+/// Run the up git config generation on a set of directories, returning the task files that
+/// changed.
+pub fn run(configs: &[GenerateGitConfig]) -> Result<Vec<Change>> {
+    let (changes, errors): (Vec<_>, Vec<_>) =
         configs
             .par_iter()
             .map(run_single)
             .partition_map(|x| match x {
-                Ok(status) => Either::Left(status),
+                Ok(change) => Either::Left(change),
                 Err(e) => Either::Right(e),
             });
 
     if errors.is_empty() {
-        if statuses.iter().all(|s| matches!(s, TaskStatus::Skipped)) {
-            Ok(TaskStatus::Skipped)
-        } else {
-            Ok(TaskStatus::Passed)
-        }
+        Ok(changes.into_iter().flatten().collect())
     } else {
         for error in &errors {
             error!("{error:?}");
@@ -51,8 +48,8 @@ pub fn run(configs: &[GenerateGitConfig]) -> Result<TaskStatus> {
     }
 }
 
-/// Run a single git config generation.
-pub fn run_single(generate_git_config: &GenerateGitConfig) -> Result<TaskStatus> {
+/// Run a single git config generation, returning the change if the task file was rewritten.
+pub fn run_single(generate_git_config: &GenerateGitConfig) -> Result<Option<Change>> {
     let _span =
         tracing::info_span!("generate_git", repo = &generate_git_config.path.as_str()).entered();
     debug!("Generating git config");
@@ -83,7 +80,7 @@ pub fn run_single(generate_git_config: &GenerateGitConfig) -> Result<TaskStatus>
     trace!("New yaml file: <<<{serialized_task}>>>");
     if serialized_task == fs::read_to_string(&generate_git_config.path)? {
         info!("Skipped task '{name}' as git repo layout unchanged.",);
-        return Ok(TaskStatus::Skipped);
+        return Ok(None);
     }
 
     fs::write(&generate_git_config.path, serialized_task)?;
@@ -91,7 +88,10 @@ pub fn run_single(generate_git_config: &GenerateGitConfig) -> Result<TaskStatus>
         "Git repo layout generated for task '{name}' and written to '{path}'",
         path = generate_git_config.path
     );
-    Ok(TaskStatus::Passed)
+    Ok(Some(
+        Change::new("generated_task", generate_git_config.path.as_str())
+            .after("regenerated from git repos"),
+    ))
 }
 
 impl ResolveEnv for Vec<GenerateGitConfig> {
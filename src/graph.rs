@@ -0,0 +1,563 @@
+/*!
+Print the task dependency graph for `up graph`.
+
+Tasks are linked to the tasks they `require`, and bootstrap tasks are linked in the order they
+run. The graph can be printed as Graphviz DOT or as a Mermaid flowchart.
+
+The highlighted path is the slowest chain of these declared dependencies. It isn't a model of the
+schedule: tasks other than bootstrap tasks run in parallel regardless of `requires`.
+*/
+use crate::config::UpConfig;
+use crate::opts::GraphFormat;
+use crate::opts::GraphOptions;
+use crate::tasks;
+use crate::tasks::TasksDir;
+use crate::tasks::runs;
+use crate::tasks::runs::RunResults;
+use crate::tasks::runs::TaskResultStatus;
+use crate::tasks::task::Task;
+use crate::utils::time::human_readable_duration;
+use color_eyre::eyre::Result;
+use itertools::Itertools;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::time::Duration;
+use tracing::debug;
+use tracing::warn;
+
+/// Colour used to highlight the slowest chain of declared dependencies.
+const CRITICAL_COLOUR: &str = "#d93025";
+
+/// Colour used for tasks that the filters leave out.
+const FILTERED_COLOUR: &str = "#9aa0a6";
+
+/// Mermaid classes used to style tasks, later classes take priority.
+const MERMAID_CLASSES: [&str; 9] = [
+    "pending",
+    "running",
+    "passed",
+    "skipped",
+    "failed",
+    "bootstrap",
+    "manual",
+    "filtered",
+    "critical",
+];
+
+/// A task in the graph.
+#[derive(Debug)]
+struct Node {
+    /// Task name.
+    name: String,
+    /// Position of the task in `bootstrap_tasks` (starting from 1), if it is a bootstrap task.
+    bootstrap: Option<usize>,
+    /// Whether the task runs by default.
+    auto_run: bool,
+    /// Whether the task is left after applying the filters.
+    selected: bool,
+    /// Status of the task in the last run, if it was in it.
+    last_status: Option<TaskResultStatus>,
+    /// How long the task took in the last run, if it finished.
+    last_duration: Option<Duration>,
+    /// Whether the task is on the slowest chain of declared dependencies in the last run.
+    critical: bool,
+}
+
+/// Why one task comes before another.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EdgeKind {
+    /// The later task `requires` the earlier one.
+    Requires,
+    /// Both are bootstrap tasks, and the earlier one runs first.
+    Bootstrap,
+}
+
+/// A link from a task to a task that comes after it.
+#[derive(Debug)]
+struct Edge {
+    /// Index of the earlier task in the nodes.
+    from: usize,
+    /// Index of the later task in the nodes.
+    to: usize,
+    /// Why the earlier task comes first.
+    kind: EdgeKind,
+    /// Whether the edge is on the slowest chain of declared dependencies in the last run.
+    critical: bool,
+}
+
+/// The task dependency graph.
+#[derive(Debug, Default)]
+struct Graph {
+    /// The tasks, sorted by name.
+    nodes: Vec<Node>,
+    /// Links between the tasks.
+    edges: Vec<Edge>,
+}
+
+/// Run the `up graph` command.
+pub(crate) fn run(config: &UpConfig, cmd_opts: &GraphOptions) -> Result<()> {
+    let tasks = tasks::read_tasks(&tasks::tasks_dir(config, TasksDir::Tasks)?)?;
+    let selected = tasks::selected_tasks(config, &tasks)?;
+    let bootstrap_tasks = config
+        .config_yaml
+        .bootstrap_tasks
+        .clone()
+        .unwrap_or_default();
+    let mut graph = Graph::new(&tasks, &selected, &bootstrap_tasks);
+
+    if cmd_opts.last_run {
+        if let Some((run_dir, results)) = runs::latest_run(&config.temp_dir, None)? {
+            debug!("Colouring tasks using the results of run {run_dir}");
+            graph.add_results(&results);
+            graph.mark_critical_path();
+        } else {
+            warn!(
+                "No previous run results found in {temp_dir}, tasks won't be coloured.",
+                temp_dir = config.temp_dir
+            );
+        }
+    }
+
+    let output = match cmd_opts.format {
+        GraphFormat::Dot => graph.to_dot(),
+        GraphFormat::Mermaid => graph.to_mermaid(),
+    };
+    print!("{output}");
+    Ok(())
+}
+
+impl Graph {
+    /// Build the graph of `tasks`, marking the `selected` and bootstrap tasks.
+    fn new(
+        tasks: &HashMap<String, Task>,
+        selected: &HashSet<String>,
+        bootstrap_tasks: &[String],
+    ) -> Self {
+        let nodes = tasks
+            .values()
+            .sorted_by(|a, b| a.name.cmp(&b.name))
+            .map(|task| Node {
+                name: task.name.clone(),
+                bootstrap: bootstrap_tasks
+                    .iter()
+                    .position(|name| *name == task.name)
+                    .map(|i| i + 1),
+                auto_run: task.config.auto_run.unwrap_or(true),
+                selected: selected.contains(&task.name),
+                last_status: None,
+                last_duration: None,
+                critical: false,
+            })
+            .collect_vec();
+        let index: HashMap<&str, usize> = nodes
+            .iter()
+            .enumerate()
+            .map(|(i, node)| (node.name.as_str(), i))
+            .collect();
+
+        let mut edges = Vec::new();
+        for (to, node) in nodes.iter().enumerate() {
+            let requires = tasks
+                .get(&node.name)
+                .and_then(|task| task.config.requires.as_ref());
+            for required in requires.into_iter().flatten() {
+                if let Some(&from) = index.get(required.as_str()) {
+                    edges.push(Edge {
+                        from,
+                        to,
+                        kind: EdgeKind::Requires,
+                        critical: false,
+                    });
+                } else {
+                    warn!(
+                        "Task '{name}' requires task '{required}', which doesn't exist.",
+                        name = node.name
+                    );
+                }
+            }
+        }
+        let bootstrap_indices = bootstrap_tasks
+            .iter()
+            .filter_map(|name| index.get(name.as_str()).copied())
+            .collect_vec();
+        for (from, to) in bootstrap_indices.into_iter().tuple_windows() {
+            edges.push(Edge {
+                from,
+                to,
+                kind: EdgeKind::Bootstrap,
+                critical: false,
+            });
+        }
+
+        Self { nodes, edges }
+    }
+
+    /// Record each task's status and duration from a run.
+    fn add_results(&mut self, results: &RunResults) {
+        for node in &mut self.nodes {
+            if let Some(result) = results.tasks.get(&node.name) {
+                node.last_status = Some(result.status);
+                node.last_duration = result.duration;
+            }
+        }
+    }
+
+    /**
+    Mark the chain of tasks that took longest in total, following the edges (the declared
+    dependencies), as critical.
+
+    Tasks that weren't in the last run count as taking no time. Edges in a cycle of `requires`
+    aren't followed, so the result doesn't depend on where the cycle is entered.
+    */
+    fn mark_critical_path(&mut self) {
+        let cyclic = self.cyclic_edges();
+        let mut chains = HashMap::new();
+        for node in 0..self.nodes.len() {
+            self.longest_chain(node, &cyclic, &mut chains);
+        }
+        let Some((mut node, &(total, _))) = chains
+            .iter()
+            .max_by_key(|(node, (total, _))| (*total, std::cmp::Reverse(**node)))
+            .map(|(node, chain)| (*node, chain))
+        else {
+            return;
+        };
+        if total.is_zero() {
+            return;
+        }
+        loop {
+            if let Some(n) = self.nodes.get_mut(node) {
+                n.critical = true;
+            }
+            let Some(&(_, Some(edge))) = chains.get(&node) else {
+                break;
+            };
+            let Some(edge) = self.edges.get_mut(edge) else {
+                break;
+            };
+            edge.critical = true;
+            node = edge.from;
+        }
+    }
+
+    /// The indices of the edges that are part of a cycle, i.e. whose `from` task can be reached
+    /// from their `to` task.
+    fn cyclic_edges(&self) -> HashSet<usize> {
+        self.edges
+            .iter()
+            .enumerate()
+            .filter(|(_, edge)| {
+                let mut seen = HashSet::new();
+                let mut stack = vec![edge.to];
+                while let Some(node) = stack.pop() {
+                    if node == edge.from {
+                        return true;
+                    }
+                    if seen.insert(node) {
+                        stack.extend(self.edges.iter().filter(|e| e.from == node).map(|e| e.to));
+                    }
+                }
+                false
+            })
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /**
+    The total duration of the longest chain of tasks ending at `node`, caching the total and the
+    edge into `node` that the chain comes from in `chains`.
+
+    The `cyclic` edges aren't followed, so the rest of the graph has no cycles.
+    */
+    fn longest_chain(
+        &self,
+        node: usize,
+        cyclic: &HashSet<usize>,
+        chains: &mut HashMap<usize, (Duration, Option<usize>)>,
+    ) -> Duration {
+        if let Some((total, _)) = chains.get(&node) {
+            return *total;
+        }
+        let mut longest = (Duration::ZERO, None);
+        for (i, edge) in self.edges.iter().enumerate() {
+            if edge.to != node || cyclic.contains(&i) {
+                continue;
+            }
+            let total = self.longest_chain(edge.from, cyclic, chains);
+            if longest.1.is_none() || total > longest.0 {
+                longest = (total, Some(i));
+            }
+        }
+        let own = self
+            .nodes
+            .get(node)
+            .and_then(|n| n.last_duration)
+            .unwrap_or_default();
+        let total = longest.0 + own;
+        chains.insert(node, (total, longest.1));
+        total
+    }
+
+    /// The graph in Graphviz DOT format.
+    fn to_dot(&self) -> String {
+        let mut out = String::from("digraph up {\n  rankdir=LR;\n  node [shape=box];\n");
+        for node in &self.nodes {
+            let label = node.label_lines().iter().map(|l| dot_escape(l)).join("\\n");
+            let mut styles = vec!["rounded"];
+            let mut attrs = vec![format!("label=\"{label}\"")];
+            if !node.auto_run {
+                styles.push("dashed");
+            }
+            if let Some(status) = node.last_status {
+                styles.push("filled");
+                attrs.push(format!("fillcolor=\"{}\"", status_colour(status)));
+            }
+            attrs.push(format!("style=\"{}\"", styles.join(",")));
+            if node.critical {
+                attrs.push(format!("color=\"{CRITICAL_COLOUR}\", penwidth=3"));
+            } else if node.bootstrap.is_some() {
+                attrs.push("penwidth=2".to_owned());
+            }
+            if !node.selected {
+                attrs.push(format!("fontcolor=\"{FILTERED_COLOUR}\""));
+                if !node.critical {
+                    attrs.push(format!("color=\"{FILTERED_COLOUR}\""));
+                }
+            }
+            _ = writeln!(
+                out,
+                "  \"{name}\" [{attrs}];",
+                name = dot_escape(&node.name),
+                attrs = attrs.join(", ")
+            );
+        }
+        for edge in &self.edges {
+            let (Some(from), Some(to)) = (self.nodes.get(edge.from), self.nodes.get(edge.to))
+            else {
+                continue;
+            };
+            let mut attrs = vec![match edge.kind {
+                EdgeKind::Requires => "label=\"requires\"".to_owned(),
+                EdgeKind::Bootstrap => "style=dashed, label=\"then\"".to_owned(),
+            }];
+            if edge.critical {
+                attrs.push(format!("color=\"{CRITICAL_COLOUR}\", penwidth=3"));
+            }
+            _ = writeln!(
+                out,
+                "  \"{from}\" -> \"{to}\" [{attrs}];",
+                attrs = attrs.join(", "),
+                from = dot_escape(&from.name),
+                to = dot_escape(&to.name)
+            );
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    /// The graph as a Mermaid flowchart.
+    fn to_mermaid(&self) -> String {
+        let mut out = String::from("flowchart LR\n");
+        for (i, node) in self.nodes.iter().enumerate() {
+            let label = node
+                .label_lines()
+                .iter()
+                .map(|l| mermaid_escape(l))
+                .join("<br/>");
+            _ = writeln!(out, "  t{i}[\"{label}\"]");
+        }
+        for edge in &self.edges {
+            let arrow = match edge.kind {
+                EdgeKind::Requires => "-->|requires|",
+                EdgeKind::Bootstrap => "-.->|then|",
+            };
+            _ = writeln!(out, "  t{} {arrow} t{}", edge.from, edge.to);
+        }
+
+        for class in MERMAID_CLASSES {
+            let nodes = self
+                .nodes
+                .iter()
+                .enumerate()
+                .filter(|(_, node)| node.mermaid_classes().contains(&class))
+                .map(|(i, _)| format!("t{i}"))
+                .collect_vec();
+            if nodes.is_empty() {
+                continue;
+            }
+            _ = writeln!(out, "  classDef {class} {}", mermaid_class_style(class));
+            _ = writeln!(out, "  class {} {class}", nodes.join(","));
+        }
+        for (i, edge) in self.edges.iter().enumerate() {
+            if edge.critical {
+                _ = writeln!(
+                    out,
+                    "  linkStyle {i} stroke:{CRITICAL_COLOUR},stroke-width:4px"
+                );
+            }
+        }
+        out
+    }
+}
+
+impl Node {
+    /// The Mermaid classes (from [`MERMAID_CLASSES`]) that the task has.
+    fn mermaid_classes(&self) -> Vec<&'static str> {
+        let mut classes = Vec::new();
+        if let Some(status) = self.last_status {
+            classes.push(status.as_str());
+        }
+        if self.bootstrap.is_some() {
+            classes.push("bootstrap");
+        }
+        if !self.auto_run {
+            classes.push("manual");
+        }
+        if !self.selected {
+            classes.push("filtered");
+        }
+        if self.critical {
+            classes.push("critical");
+        }
+        classes
+    }
+
+    /// The lines of text shown for the task.
+    fn label_lines(&self) -> Vec<String> {
+        let mut lines = vec![self.name.clone()];
+        if let Some(position) = self.bootstrap {
+            lines.push(format!("bootstrap {position}"));
+        }
+        if !self.auto_run {
+            lines.push("auto_run: false".to_owned());
+        }
+        if !self.selected {
+            lines.push("filtered out".to_owned());
+        }
+        if let Some(status) = self.last_status {
+            match self
+                .last_duration
+                .and_then(|d| human_readable_duration(d).ok())
+            {
+                Some(duration) => lines.push(format!("{} in {duration}", status.as_str())),
+                None => lines.push(status.as_str().to_owned()),
+            }
+        }
+        lines
+    }
+}
+
+/// The fill colour for a task with `status` in the last run.
+const fn status_colour(status: TaskResultStatus) -> &'static str {
+    match status {
+        TaskResultStatus::Passed => "#ceead6",
+        TaskResultStatus::Failed => "#fad2cf",
+        TaskResultStatus::Skipped => "#d2e3fc",
+        TaskResultStatus::Pending | TaskResultStatus::Running => "#feefc3",
+    }
+}
+
+/// The Mermaid style for a `classDef`.
+fn mermaid_class_style(class: &str) -> String {
+    match class {
+        "bootstrap" => "stroke-width:3px".to_owned(),
+        "manual" => "stroke-dasharray:5 5".to_owned(),
+        "filtered" => format!("color:{FILTERED_COLOUR},stroke:{FILTERED_COLOUR}"),
+        "critical" => format!("stroke:{CRITICAL_COLOUR},stroke-width:4px"),
+        "passed" => format!("fill:{}", status_colour(TaskResultStatus::Passed)),
+        "failed" => format!("fill:{}", status_colour(TaskResultStatus::Failed)),
+        "skipped" => format!("fill:{}", status_colour(TaskResultStatus::Skipped)),
+        _ => format!("fill:{}", status_colour(TaskResultStatus::Pending)),
+    }
+}
+
+/// Escape a string for use inside double quotes in DOT.
+fn dot_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Escape a string for use inside double quotes in a Mermaid label.
+fn mermaid_escape(s: &str) -> String {
+    s.replace('"', "#quot;")
+        .replace('<', "#lt;")
+        .replace('>', "#gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Edge;
+    use super::EdgeKind;
+    use super::Graph;
+    use super::Node;
+    use color_eyre::Result;
+    use std::time::Duration;
+    use testutils::ensure_eq;
+
+    /// A selected task that took `secs` seconds in the last run.
+    fn node(name: &str, secs: u64) -> Node {
+        Node {
+            name: name.to_owned(),
+            bootstrap: None,
+            auto_run: true,
+            selected: true,
+            last_status: None,
+            last_duration: Some(Duration::from_secs(secs)),
+            critical: false,
+        }
+    }
+
+    /// An edge meaning `to` requires `from`.
+    fn requires(from: usize, to: usize) -> Edge {
+        Edge {
+            from,
+            to,
+            kind: EdgeKind::Requires,
+            critical: false,
+        }
+    }
+
+    #[test]
+    fn test_critical_path() -> Result<()> {
+        // a (5s) -> b (1s) -> d (1s), c (4s) -> d, and a cycle between e and f, which g (7s)
+        // requires. The cycle isn't followed (wherever it's entered from), so f -> g is slowest.
+        let mut graph = Graph {
+            nodes: vec![
+                node("a", 5),
+                node("b", 1),
+                node("c", 4),
+                node("d", 1),
+                node("e", 1),
+                node("f", 1),
+                node("g", 7),
+            ],
+            edges: vec![
+                requires(0, 1),
+                requires(1, 3),
+                requires(2, 3),
+                requires(4, 5),
+                requires(5, 4),
+                requires(5, 6),
+            ],
+        };
+        graph.mark_critical_path();
+        ensure_eq!(
+            vec!["f", "g"],
+            graph
+                .nodes
+                .iter()
+                .filter(|node| node.critical)
+                .map(|node| node.name.as_str())
+                .collect::<Vec<_>>()
+        );
+        ensure_eq!(
+            vec![false, false, false, false, false, true],
+            graph
+                .edges
+                .iter()
+                .map(|edge| edge.critical)
+                .collect::<Vec<_>>()
+        );
+        Ok(())
+    }
+}
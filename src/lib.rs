@@ -59,11 +59,13 @@ use crate::opts::SubCommand;
 use color_eyre::eyre::Result;
 use opts::DefaultsSubcommand;
 use opts::GenerateLib;
+use std::time::Duration;
 use tasks::TasksAction;
 use tasks::TasksDir;
 use tasks::defaults;
 use tracing::trace;
 
+mod clean;
 mod config;
 mod docs;
 pub mod env;
@@ -71,9 +73,13 @@ pub mod errors;
 pub mod exec;
 mod faketty;
 mod generate;
+mod graph;
+mod logs;
 pub mod opts;
+pub mod runner;
 pub mod tasks;
 pub mod utils;
+mod watch;
 
 /// The unique identifier of the up application on the system.
 pub const UP_BUNDLE_ID: &str = "co.fahn.up";
@@ -90,9 +96,18 @@ pub const UP_BUNDLE_ID: &str = "co.fahn.up";
 ///
 /// [Opts]: crate::opts::Opts
 pub fn run(opts: Opts) -> Result<()> {
+    opts.temp_dir.migrate_legacy();
     match opts.cmd.clone() {
-        Some(SubCommand::Link(link_options)) => {
-            tasks::link::run(link_options, &opts.temp_dir)?;
+        Some(SubCommand::Link(mut link_options)) => match link_options.cmd.take() {
+            Some(cmd) => {
+                tasks::link::backups::run_subcommand(cmd, &opts.temp_dir, link_options.dry_run)?;
+            }
+            None => {
+                tasks::link::run(link_options, &std::env::vars().collect(), &opts.temp_dir)?;
+            }
+        },
+        Some(SubCommand::Unlink(unlink_options)) => {
+            tasks::link::unlink::run(unlink_options, &opts.temp_dir)?;
         }
         Some(SubCommand::Git(git_options)) => {
             tasks::git::update::update(&git_options.into())?;
@@ -137,6 +152,21 @@ pub fn run(opts: Opts) -> Result<()> {
             let config = UpConfig::from(opts)?;
             tasks::run(&config, TasksDir::Tasks, TasksAction::Run)?;
         }
+        Some(SubCommand::Watch(ref cmd_opts)) => {
+            let debounce = Duration::from_millis(cmd_opts.debounce_ms);
+            watch::run(opts, debounce)?;
+        }
+        Some(SubCommand::Clean(ref cmd_opts)) => {
+            let config = UpConfig::from(opts)?;
+            clean::run(&config, cmd_opts)?;
+        }
+        Some(SubCommand::Logs(ref cmd_opts)) => {
+            logs::run(cmd_opts, &opts.temp_dir)?;
+        }
+        Some(SubCommand::Graph(ref cmd_opts)) => {
+            let config = UpConfig::from(opts)?;
+            graph::run(&config, cmd_opts)?;
+        }
         Some(SubCommand::Faketty(cmd_opts)) => {
             faketty::run(cmd_opts)?;
         }
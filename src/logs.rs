@@ -0,0 +1,229 @@
+//! Show the captured output of tasks from previous runs.
+use crate::faketty::asciicast;
+use crate::opts::LogsOptions;
+use crate::tasks::runs;
+use crate::tasks::runs::RunResults;
+use crate::tasks::runs::TaskResultStatus;
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use color_eyre::eyre::Context;
+use color_eyre::eyre::Result;
+use color_eyre::eyre::bail;
+use color_eyre::eyre::eyre;
+use itertools::Itertools;
+use std::fs::File;
+use std::io;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::thread;
+use std::time::Duration;
+use tracing::debug;
+use tracing::trace;
+
+/// How often to check for new output when following a task.
+const FOLLOW_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Run `up logs`.
+pub(crate) fn run(opts: &LogsOptions, temp_dir: &Utf8Path) -> Result<()> {
+    let (run_dir, results) = find_run(temp_dir, opts.run.as_deref())?;
+    debug!("Showing logs from run {run_dir}");
+
+    if let Some(task_name) = &opts.replay {
+        ensure_task_in_run(&run_dir, &results, task_name)?;
+        return replay(&run_dir, task_name);
+    }
+
+    let task_name = match (&opts.task, opts.follow) {
+        (Some(task_name), _) => task_name.clone(),
+        (None, true) => running_task(&run_dir, &results)?,
+        (None, false) => return list_tasks(&run_dir, &results),
+    };
+    ensure_task_in_run(&run_dir, &results, &task_name)?;
+
+    let output_path = runs::task_output_path(&run_dir, &task_name);
+    if opts.follow {
+        return follow(&run_dir, &task_name, &output_path);
+    }
+    if !output_path.exists() {
+        bail!(
+            "Task '{task_name}' has no captured output in run {run_id}. It may not have started, \
+             or it may have run with --console.",
+            run_id = run_id(&run_dir),
+        );
+    }
+    print_from(&output_path, 0)?;
+    Ok(())
+}
+
+/// Error if `task_name` wasn't part of the run.
+fn ensure_task_in_run(run_dir: &Utf8Path, results: &RunResults, task_name: &str) -> Result<()> {
+    if !results.tasks.contains_key(task_name) {
+        bail!(
+            "Task '{task_name}' wasn't part of run {run_id}, tasks in that run: {tasks}",
+            run_id = run_id(run_dir),
+            tasks = results.tasks.keys().join(", "),
+        );
+    }
+    Ok(())
+}
+
+/// Play back a task's recorded terminal sessions (its `run_if_cmd`'s, then its `run_cmd`'s).
+fn replay(run_dir: &Utf8Path, task_name: &str) -> Result<()> {
+    let recording_paths = runs::task_recording_paths(run_dir, task_name)
+        .into_iter()
+        .filter(|path| path.exists())
+        .collect_vec();
+    if recording_paths.is_empty() {
+        bail!(
+            "Task '{task_name}' has no recording in run {run_id}. Record tasks with \
+             `up run --record`, or by setting `record: true` in the task config.",
+            run_id = run_id(run_dir),
+        );
+    }
+    let mut stdout = io::stdout().lock();
+    for recording_path in recording_paths {
+        asciicast::replay(&recording_path, &mut stdout)?;
+    }
+    Ok(())
+}
+
+/// Find the run matching `run` (a run ID or a unique prefix of one), or the latest run.
+fn find_run(temp_dir: &Utf8Path, run: Option<&str>) -> Result<(Utf8PathBuf, RunResults)> {
+    let Some(run) = run else {
+        return runs::latest_run(temp_dir, None)?.ok_or_else(|| {
+            eyre!(
+                "No runs found in {runs_dir}.",
+                runs_dir = runs::runs_dir(temp_dir)
+            )
+        });
+    };
+
+    let run_dirs = runs::run_dirs(temp_dir)?;
+    let matching = match run_dirs.iter().find(|run_dir| run_id(run_dir) == run) {
+        Some(run_dir) => vec![run_dir],
+        None => run_dirs
+            .iter()
+            .filter(|run_dir| run_id(run_dir).starts_with(run))
+            .collect(),
+    };
+    let run_dir = match matching.as_slice() {
+        [run_dir] => *run_dir,
+        [] => bail!(
+            "No run matching '{run}', available runs:\n  {runs}",
+            runs = run_dirs.iter().map(|run_dir| run_id(run_dir)).join("\n  "),
+        ),
+        _ => bail!(
+            "Run ID '{run}' matches multiple runs:\n  {runs}",
+            runs = matching.iter().map(|run_dir| run_id(run_dir)).join("\n  "),
+        ),
+    };
+    let results = runs::read_results(&run_dir.join(runs::RUN_RESULTS_FILE_NAME))?;
+    Ok((run_dir.clone(), results))
+}
+
+/// The ID of a run, which is the name of its directory.
+fn run_id(run_dir: &Utf8Path) -> &str {
+    run_dir.file_name().unwrap_or(run_dir.as_str())
+}
+
+/// Print the tasks in a run along with their statuses and output sizes.
+fn list_tasks(run_dir: &Utf8Path, results: &RunResults) -> Result<()> {
+    let mut stdout = io::stdout().lock();
+    writeln!(
+        stdout,
+        "Run {run_id} (started {start_time}):",
+        run_id = run_id(run_dir),
+        start_time = results.start_time.format("%Y-%m-%d %H:%M:%S UTC"),
+    )?;
+    let name_width = results.tasks.keys().map(String::len).max().unwrap_or(0);
+    for (name, result) in &results.tasks {
+        let output_path = runs::task_output_path(run_dir, name);
+        let size = match output_path.metadata() {
+            Ok(metadata) => human_readable_size(metadata.len()),
+            Err(e) => {
+                trace!("No output file at {output_path}: {e}");
+                "-".to_owned()
+            }
+        };
+        writeln!(
+            stdout,
+            "  {name:name_width$}  {status:7}  {size}",
+            status = result.status.as_str(),
+        )?;
+    }
+    Ok(())
+}
+
+/// The name of the single task that is currently running in a run.
+fn running_task(run_dir: &Utf8Path, results: &RunResults) -> Result<String> {
+    let running = results
+        .tasks
+        .iter()
+        .filter(|(_, result)| result.status == TaskResultStatus::Running)
+        .map(|(name, _)| name)
+        .collect_vec();
+    match running.as_slice() {
+        [task_name] => Ok((*task_name).clone()),
+        [] => bail!(
+            "No tasks are running in run {run_id}, pass a task name to show its output.",
+            run_id = run_id(run_dir),
+        ),
+        _ => bail!(
+            "Multiple tasks are running ({tasks}), pass the one to follow.",
+            tasks = running.iter().join(", "),
+        ),
+    }
+}
+
+/// Print a task's output as it is written, until the task is no longer running (or the run has
+/// ended without recording that, e.g. because it crashed).
+fn follow(run_dir: &Utf8Path, task_name: &str, output_path: &Utf8Path) -> Result<()> {
+    let results_path = run_dir.join(runs::RUN_RESULTS_FILE_NAME);
+    let mut offset = 0;
+    loop {
+        // Check the status before printing, so we print everything written before it finished.
+        let running = runs::is_in_progress(run_dir)
+            && runs::read_results(&results_path)?
+                .tasks
+                .get(task_name)
+                .is_some_and(|result| result.status == TaskResultStatus::Running);
+        offset = print_from(output_path, offset)?;
+        if !running {
+            return Ok(());
+        }
+        thread::sleep(FOLLOW_POLL_INTERVAL);
+    }
+}
+
+/// Print the contents of a file from `offset` bytes onwards, returning the new end offset.
+fn print_from(path: &Utf8Path, offset: u64) -> Result<u64> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(offset),
+        Err(e) => return Err(e).wrap_err_with(|| eyre!("Failed to open task output {path}")),
+    };
+    file.seek(SeekFrom::Start(offset))?;
+    let mut stdout = io::stdout().lock();
+    let copied = io::copy(&mut file, &mut stdout)?;
+    stdout.flush()?;
+    Ok(offset + copied)
+}
+
+/// Format a number of bytes, e.g. `512 B` or `1.5 KiB`.
+fn human_readable_size(bytes: u64) -> String {
+    let mut tenths = bytes.saturating_mul(10);
+    let mut unit = "B";
+    for next_unit in ["KiB", "MiB", "GiB"] {
+        if tenths < 1024 * 10 {
+            break;
+        }
+        tenths /= 1024;
+        unit = next_unit;
+    }
+    if unit == "B" {
+        format!("{bytes} B")
+    } else {
+        format!("{}.{} {unit}", tenths / 10, tenths % 10)
+    }
+}
@@ -16,7 +16,6 @@ to use to manage the machines they regularly use.
 #![allow(clippy::implicit_return, clippy::missing_docs_in_private_items)]
 
 use camino::Utf8PathBuf;
-use chrono::SecondsFormat;
 use color_eyre::Section;
 use color_eyre::SectionExt;
 use color_eyre::eyre::Context;
@@ -130,7 +129,7 @@ fn set_up_logging(opts: &Opts) -> Result<(Utf8PathBuf, LevelFilter)> {
                 |state: &ProgressState, writer: &mut dyn std::fmt::Write| {
                     let elapsed = state.elapsed();
 
-                    if elapsed > Duration::from_secs(60) {
+                    if elapsed > Duration::from_mins(1) {
                         // Red
                         let _ = write!(writer, "\x1b[{}m", 1 + 30);
                     } else if elapsed > Duration::from_secs(10) {
@@ -166,13 +165,7 @@ fn set_up_logging(opts: &Opts) -> Result<(Utf8PathBuf, LevelFilter)> {
         .with_writer(indicatif_layer.get_stderr_writer());
 
     // Logs go to e.g. ~/Library/Logs/co.fahn.up/up_2024-04-26T11_22_24.834348Z.log
-    let log_path = files::log_dir()?.join(format!(
-        "up_{}.log",
-        opts.start_time
-            .to_rfc3339_opts(SecondsFormat::AutoSi, true)
-            // : is not an allowed filename character in Finder.
-            .replace(':', "_")
-    ));
+    let log_path = files::log_path(&opts.start_time)?;
 
     let log_file = files::create(&log_path, None).wrap_err("Failed to create log file.")?;
 
@@ -1,19 +1,27 @@
 //! Options passed to `up` commands.
-mod paths;
+pub(crate) mod paths;
 pub(crate) mod start_time;
 
+use crate::docs::completions;
 use crate::opts::paths::TempDir;
 use crate::opts::start_time::StartTime;
 use camino::Utf8PathBuf;
+use clap::CommandFactory;
 use clap::Parser;
 use clap::ValueEnum;
 use clap::ValueHint;
 use clap::builder::styling::AnsiColor;
 use clap::builder::styling::Styles;
+use clap_complete::ArgValueCandidates;
+use clap_complete::ArgValueCompleter;
+use clap_complete::CompleteEnv;
+use clap_complete::PathCompleter;
 use clap_complete::Shell;
 use serde_derive::Deserialize;
 use serde_derive::Serialize;
 use std::ffi::OsString;
+use std::fmt;
+use std::str::FromStr;
 
 /// The default fallback path inside a fallback repo to look for the up.yaml file in.
 pub(crate) const FALLBACK_CONFIG_PATH: &str = "dotfiles/.config/up/up.yaml";
@@ -36,9 +44,17 @@ const STYLES: Styles = Styles::styled()
     .literal(AnsiColor::Blue.on_default().bold())
     .placeholder(AnsiColor::Cyan.on_default());
 
-/// Builds the Args struct from CLI input and from environment variable input.
+/**
+Builds the Args struct from CLI input and from environment variable input.
+
+If up was called by a shell to complete a command line (see `up doc completions --dynamic`),
+prints the completions and exits instead.
+*/
 #[must_use]
 pub fn parse() -> Opts {
+    CompleteEnv::with_factory(Opts::command)
+        .var(completions::COMPLETE_ENV_NAME)
+        .complete();
     Opts::parse()
 }
 
@@ -63,7 +79,7 @@ There are also a number of libraries built into up, that can be accessed directl
 up task configs, e.g. `up link` to link dotfiles.
 
 For debugging, run with `RUST_LIB_BACKTRACE=1` to show error/panic traces.
-Logs from the latest run are available at `$TMPDIR/up/logs/up_<timestamp>.log` by default.
+Logs from each run are written to `up_<timestamp>.log` in `~/Library/Logs/co.fahn.up/` on macOS, or `$XDG_STATE_HOME/up/logs/` (by default `~/.local/state/up/logs/`) on Linux.
 Parallel tasks are run with rayon, so you can control the number of threads used via `RAYON_NUM_THREADS`, e.g. `RAYON_NUM_THREADS=1 up` to run everything sequentially.
 */
 #[derive(Debug, Clone, Parser)]
@@ -81,7 +97,11 @@ pub struct Opts {
     pub log: String,
 
     /**
-    Temporary directory to use for logs, fifos, and other intermediate artifacts.
+    Directory to use for run results, backups, fifos, and other intermediate artifacts.
+
+    Defaults to `$XDG_STATE_HOME/up` (by default `~/.local/state/up`) on Linux, and `$TMPDIR/up`
+    elsewhere. Old runs are removed automatically, see `up clean`. On Linux, anything earlier
+    versions of up left in `$TMPDIR/up` is moved to the new default.
     */
     #[clap(long, env = "UP_TEMP_DIR", default_value_t, value_hint = ValueHint::DirPath, alias = "up-dir")]
     pub temp_dir: TempDir,
@@ -96,7 +116,15 @@ pub struct Opts {
     pub color: Color,
 
     /// Path to the up.yaml file for up.
-    #[clap(long, short = 'c', default_value = "$XDG_CONFIG_HOME/up/up.yaml", value_hint = ValueHint::FilePath)]
+    #[clap(
+        long,
+        short = 'c',
+        default_value = "$XDG_CONFIG_HOME/up/up.yaml",
+        value_hint = ValueHint::FilePath,
+        add = ArgValueCompleter::new(PathCompleter::any().filter(|path| {
+            path.is_file() && completions::is_config_file(path)
+        })),
+    )]
     pub(crate) config: String,
 
     /**
@@ -133,8 +161,36 @@ pub(crate) enum SubCommand {
     If you want to pass Run args you will need to specify the subcommand.
     */
     Run(RunOptions),
-    /// Symlink your dotfiles from a git repo to your home directory.
+    /**
+    Symlink your dotfiles from a git repo to your home directory.
+
+    Files that would be overwritten are moved into a timestamped directory for that run inside
+    the backup directory. Use `up link backups` to list them, and `up link restore` to put them
+    back.
+
+    EXAMPLES:
+
+    ❯ up link --dry-run
+
+    ❯ up link backups
+
+    ❯ up link restore ~/.bashrc
+    */
     Link(LinkOptions),
+    /**
+    Undo `up link`: remove links in the home directory that point into your dotfiles, and put
+    back the files they replaced.
+
+    Files that `up link` moved into the backup directory are restored to where they were.
+    Copies and rendered templates are left in place.
+
+    EXAMPLES:
+
+    ❯ up unlink --dry-run
+
+    ❯ up unlink ~/.config/nvim ~/.bashrc
+    */
+    Unlink(UnlinkOptions),
     /// Clone or update a repo at a path.
     Git(GitOptions),
     /// Set macOS defaults in plist files.
@@ -149,12 +205,89 @@ pub(crate) enum SubCommand {
     /// List available tasks.
     List(RunOptions),
     /**
+    Watch the up config and task inputs, and re-run tasks when they change.
+
+    Watches the directory containing the up config (including the task configs), and any paths
+    listed in a task's `watch` field. When something changes, the affected tasks are re-run.
+    Changes to the up config itself re-run all tasks.
+
+    EXAMPLES:
+
+    ❯ up watch --tasks link
+    */
+    Watch(WatchOptions),
+    /**
+    Remove the results and logs of old runs, and empty backup directories.
+
+    Lists everything that will be removed, and asks before removing it (unless `--yes` is
+    passed). Runs are kept according to the `retention` section of the up config, which is also
+    applied automatically after every `up run`. Runs still in progress in another `up` process are
+    never removed.
+
+    EXAMPLES:
+
+    ❯ up clean --dry-run
+
+    ❯ up clean --yes
+
+    ❯ up clean --keep-runs 5 --keep-for 7d
+    */
+    Clean(CleanOptions),
+    /**
+    Show the captured output of a task from the latest run, or from a chosen run.
+
+    Without a task name, lists the tasks in the run with their statuses and output sizes.
+
+    EXAMPLES:
+
+    ❯ up logs
+
+    ❯ up logs brew
+
+    ❯ up logs --run 2024-04-26T11_22 brew
+
+    ❯ up logs --follow
+
+    ❯ up logs --replay brew
+    */
+    Logs(LogsOptions),
+    /**
     Runs a command in a fake tty.
+
+    The command's stdout and stderr are terminals (the same size as up's terminal, if any), so it
+    prints as it would if run interactively, even if up's output is piped or redirected. If up's
+    stdin is a terminal, input is passed through to the command. Signals sent to up are passed on
+    to the command, and up exits with the command's exit code (or `128 + signal` if the command
+    was killed by a signal).
+
+    EXAMPLES:
+
+    ❯ up faketty ls --color=auto | less -R
+
+    ❯ up faketty --merge-output cargo build > build.log
     */
     Faketty(FakettyOptions),
+    /**
+    Print the task dependency graph, in Graphviz DOT or Mermaid format.
+
+    Tasks are linked to the tasks they `require`, and bootstrap tasks are linked in the order
+    they run. Bootstrap tasks, tasks that don't `auto_run`, and tasks left out by the filters
+    (e.g. `--tasks`, `--exclude-tasks`) are marked. With `--last-run`, tasks are coloured by
+    their status in the last run and labelled with how long they took, and the slowest chain of
+    declared dependencies is highlighted. Tasks other than bootstrap tasks run in parallel
+    regardless of `requires`, so this isn't necessarily what made the run take as long as it did.
+
+    EXAMPLES:
+
+    ❯ up graph | dot -Tsvg > tasks.svg
+
+    ❯ up graph --format mermaid --last-run
+    */
+    Graph(GraphOptions),
 }
 
 /// Options passed to `up run`.
+#[allow(clippy::struct_excessive_bools)] // Each is a separate `up run` flag.
 #[derive(Debug, Clone, Parser, Default)]
 pub(crate) struct RunOptions {
     /// Run the bootstrap list of tasks in series first, then run the rest in
@@ -185,7 +318,12 @@ pub(crate) struct RunOptions {
 
     ❯ up run --tasks=rust,apt --tasks=otherslowtask
     */
-    #[clap(short = 't', long, value_delimiter = ',')]
+    #[clap(
+        short = 't',
+        long,
+        value_delimiter = ',',
+        add = ArgValueCandidates::new(completions::task_candidates),
+    )]
     pub(crate) tasks: Option<Vec<String>>,
 
     /**
@@ -206,19 +344,505 @@ pub(crate) struct RunOptions {
 
     ❯ up run --exclude-tasks=brew,slowtask --exclude-tasks=otherslowtask
     */
-    #[clap(long, value_delimiter = ',')]
+    #[clap(
+        long,
+        value_delimiter = ',',
+        add = ArgValueCandidates::new(completions::task_candidates),
+    )]
     pub(crate) exclude_tasks: Option<Vec<String>>,
+
+    /**
+    Only run tasks that use one of these run libraries (the `run_lib` in their task config).
+    This option can be provided multiple times, or use a comma-separated list of values.
+
+    EXAMPLES:
+
+    ❯ up run --run-libs=link,git
+    */
+    #[clap(
+        long,
+        value_delimiter = ',',
+        add = ArgValueCandidates::new(completions::run_lib_candidates),
+    )]
+    pub(crate) run_libs: Option<Vec<String>>,
+
+    /**
+    Only run the tasks that failed or didn't finish in the previous run.
+
+    The previous run's results are read from the `runs` directory inside `--temp-dir`.
+
+    EXAMPLES:
+
+    ❯ up run --failed
+    */
+    #[clap(long, conflicts_with = "tasks")]
+    pub(crate) failed: bool,
+
+    /**
+    Like `--failed`, but also run any tasks that require (directly or indirectly) one of the
+    tasks that failed or didn't finish in the previous run.
+    */
+    #[clap(long, conflicts_with_all = ["tasks", "failed"])]
+    pub(crate) last_failed_and_dependents: bool,
+
+    /**
+    Report what tasks would change, without changing anything.
+
+    Built-in and external run libraries that support it are asked to do a dry run, other tasks
+    are skipped. No commands are run (not even `run_if_cmd`s), and dry runs aren't recorded, so
+    they don't affect `--failed` or the progress estimates of later runs.
+    */
+    #[clap(long)]
+    pub(crate) dry_run: bool,
+
+    /**
+    Record each task's terminal session, with timing, so it can be replayed with
+    `up logs --replay <task>`.
+
+    Commands are run in a pseudo-terminal (as with `tty: true` in the task config), and each
+    command's session is saved as an asciicast file next to the task's output file. Has no effect with
+    `--console`.
+    */
+    #[clap(long)]
+    pub(crate) record: bool,
+
+    /**
+    Don't prompt for sudo, and skip tasks that need privileges (tasks that set `needs_sudo`, or
+    `run_as` another user).
+
+    The skipped tasks are listed at the end of the run.
+    */
+    #[clap(long)]
+    pub(crate) no_sudo: bool,
+
+    /**
+    Pick which tasks to run in a terminal UI.
+
+    Lists the tasks left after applying the other filters (e.g. `--tasks`, `--exclude-tasks`,
+    `--failed`), with their descriptions, tags, and status in the last run. Type to fuzzy-filter
+    the list, `Tab` to pick tasks, and `Enter` to run the picked tasks (or the highlighted task if
+    none are picked). The preview shows the task's config file and its output from the last run.
+
+    Only available if up was built with the `interactive` cargo feature.
+
+    EXAMPLES:
+
+    ❯ up run --interactive
+
+    ❯ up run -i --exclude-tasks=brew
+    */
+    #[clap(short, long)]
+    pub(crate) interactive: bool,
+}
+
+/// Options passed to `up clean`.
+#[derive(Debug, Clone, Parser)]
+pub(crate) struct CleanOptions {
+    /// Show what would be removed without removing anything.
+    #[clap(long)]
+    pub(crate) dry_run: bool,
+
+    /// Remove without asking for confirmation.
+    #[clap(short, long)]
+    pub(crate) yes: bool,
+
+    /// Number of most recent runs to keep, overrides `retention.keep_runs` in the up config.
+    #[clap(long)]
+    pub(crate) keep_runs: Option<usize>,
+
+    /// Also keep runs newer than this (e.g. `30d` or `1w 2d`), overrides `retention.keep_for`
+    /// in the up config.
+    #[clap(long)]
+    pub(crate) keep_for: Option<String>,
+}
+
+/// Options passed to `up logs`.
+#[derive(Debug, Clone, Parser)]
+pub(crate) struct LogsOptions {
+    /// Task to show the output of.
+    #[clap(add = ArgValueCandidates::new(completions::task_candidates))]
+    pub(crate) task: Option<String>,
+
+    /// Play back the recorded terminal session of this task (see `up run --record`), with its
+    /// original timing.
+    #[clap(
+        long,
+        value_name = "TASK",
+        conflicts_with_all = ["task", "follow"],
+        add = ArgValueCandidates::new(completions::task_candidates),
+    )]
+    pub(crate) replay: Option<String>,
+
+    /// Keep printing output as the task writes it, until the task (or its run) finishes. Without a
+    /// task name, follows the task that is currently running.
+    #[clap(long, short = 'f')]
+    pub(crate) follow: bool,
+
+    /// ID of the run to show (the name of its directory in `runs`, or a unique prefix of it),
+    /// defaults to the latest run.
+    #[clap(long)]
+    pub(crate) run: Option<String>,
+}
+
+/// Options passed to `up watch`.
+#[derive(Debug, Clone, Parser)]
+pub(crate) struct WatchOptions {
+    /// Options used to select the tasks to run.
+    #[clap(flatten)]
+    pub(crate) run_options: RunOptions,
+    /// How long to wait (in milliseconds) for changes to settle before re-running tasks.
+    #[clap(long, default_value_t = 500)]
+    pub(crate) debounce_ms: u64,
+}
+
+/// Options passed to `up graph`.
+#[derive(Debug, Clone, Parser)]
+pub(crate) struct GraphOptions {
+    /// Options used to select the tasks to run, tasks that aren't selected are marked.
+    #[clap(flatten)]
+    pub(crate) run_options: RunOptions,
+    /// Format to print the graph in.
+    #[clap(long, value_enum, default_value_t)]
+    pub(crate) format: GraphFormat,
+    /// Colour tasks by their status in the last run, label them with how long they took, and
+    /// highlight the slowest chain of declared dependencies.
+    #[clap(long)]
+    pub(crate) last_run: bool,
+}
+
+/// Formats `up graph` can print.
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+pub(crate) enum GraphFormat {
+    /// Graphviz DOT, render with e.g. `dot -Tsvg`.
+    #[default]
+    Dot,
+    /// Mermaid flowchart, rendered by e.g. GitHub markdown.
+    Mermaid,
 }
 
 /// Options passed to `up link`.
+#[allow(clippy::struct_excessive_bools)] // Each is a separate `up link` flag (or task key).
 #[derive(Debug, Clone, Parser, Default, Serialize, Deserialize)]
 pub(crate) struct LinkOptions {
-    /// Path where your dotfiles are kept (hopefully in source control).
+    /**
+    Path where your dotfiles are kept (hopefully in source control).
+
+    Pass more than once to link from several directories (layers), e.g. a shared team repo and
+    a personal one. If more than one layer has a file, `--conflicts` decides which is linked.
+    In task configs `from_dir` can be a single path or a list.
+
+    EXAMPLES:
+
+    ❯ up link --from ~/code/team-dotfiles --from ~/code/dotfiles
+    */
     #[clap(short = 'f', long = "from", default_value = "~/code/dotfiles", value_hint = ValueHint::DirPath)]
-    pub(crate) from_dir: String,
+    #[serde(rename = "from_dir", deserialize_with = "one_or_more")]
+    pub(crate) from_dirs: Vec<String>,
     /// Path to link them to.
     #[clap(short = 't', long = "to", default_value = "~", value_hint = ValueHint::DirPath)]
     pub(crate) to_dir: String,
+    /// Which layer's file to link when more than one `--from` directory has it.
+    #[clap(long, value_enum, default_value_t)]
+    #[serde(default)]
+    pub(crate) conflicts: LinkConflicts,
+    /**
+    Say which `--from` directory (layer) a target is linked from, and why other layers that have
+    it aren't used, without linking anything.
+
+    The target can be absolute, or relative to the `--to` directory.
+
+    EXAMPLES:
+
+    ❯ up link --from ~/code/team-dotfiles --from ~/code/dotfiles --explain ~/.gitconfig
+    */
+    #[clap(long, value_name = "TARGET", value_hint = ValueHint::AnyPath)]
+    #[serde(skip)]
+    pub(crate) explain: Option<Utf8PathBuf>,
+    /**
+    Gitignore-style patterns (relative to `from_dir`) for files not to link.
+
+    These are added to the patterns in the `.upignore` file in `from_dir`, if there is one.
+
+    EXAMPLES:
+
+    ❯ up link --ignore='README.md,.git/,*.swp'
+    */
+    #[clap(long, value_delimiter = ',')]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) ignore: Vec<String>,
+    /**
+    Only link files that are tracked by git (in the index of the repository containing
+    `from_dir`).
+
+    Untracked and git-ignored files are skipped, and tracked files with uncommitted changes are
+    linked with a warning. Everything in a submodule (apart from its `.git`) counts as tracked.
+    */
+    #[clap(long)]
+    #[serde(default)]
+    pub(crate) tracked_only: bool,
+    /**
+    How to put files from `from_dir` into `to_dir`.
+
+    Copies are kept in sync with their source by content hash. If a copy has been edited since
+    it was made, up warns and backs it up before replacing it.
+    */
+    #[clap(long, value_enum, default_value_t)]
+    #[serde(default)]
+    pub(crate) strategy: LinkStrategy,
+    /**
+    Use a different strategy for files matching a gitignore-style pattern (relative to
+    `from_dir`), as `<glob>=<strategy>`. If more than one pattern matches, the last one wins.
+
+    EXAMPLES:
+
+    ❯ up link --strategy-override='*.service=copy' --strategy-override='.ssh/config=hardlink'
+    */
+    #[clap(long = "strategy-override", value_name = "GLOB=STRATEGY")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) strategy_overrides: Vec<StrategyOverride>,
+    /**
+    Set the mode of files matching a gitignore-style pattern (relative to `from_dir`, without a
+    template's `.tmpl` suffix), as `<glob>=<octal mode>`. If more than one pattern matches, the
+    last one wins.
+
+    Modes are set on the files in `from_dir` (and so on the links to them), on copies and
+    rendered templates, and on matching directories that have to be created in `to_dir`. Sources
+    more permissive than their rule are reported, as are sensitive-looking files (e.g. SSH
+    private keys) that other users can read but that don't have a rule.
+
+    Only modes are managed, not ownership: files are left owned by whoever created them (normally
+    the user running `up link`), as changing that needs root, and sources in `from_dir` should
+    stay owned by its user.
+
+    EXAMPLES:
+
+    ❯ up link --permission='.ssh=700' --permission='.ssh/config=600' --permission='.netrc=600'
+    */
+    #[clap(long = "permission", value_name = "GLOB=MODE")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) permissions: Vec<PermissionRule>,
+    /**
+    Link whole directories, rather than each file in them, when the directory doesn't exist in
+    `to_dir` yet (like GNU Stow's folding).
+
+    New files created inside a linked directory then end up in `from_dir`. A directory is only
+    linked as a whole if everything in it would be symlinked anyway, and it's unfolded into links
+    to each file if something else needs to link into it later. Directories containing a
+    `.upfold` marker file are always linked as a whole, even without this option.
+    */
+    #[clap(long)]
+    #[serde(default)]
+    pub(crate) fold: bool,
+    /**
+    Don't remove symlinks made by earlier runs whose source has since been removed from
+    `from_dir`.
+
+    Each run records the symlinks it makes, and removes recorded links that are now broken
+    because their source was deleted or renamed. Other broken links are left alone.
+    */
+    #[clap(long)]
+    #[serde(default)]
+    pub(crate) no_prune: bool,
+    /// Show what would be linked (and which files are ignored), without changing anything.
+    #[clap(long, global = true)]
+    #[serde(skip)]
+    pub(crate) dry_run: bool,
+    /// Undo the link instead, as `up unlink` does (for use in task configs).
+    #[clap(skip)]
+    #[serde(default)]
+    pub(crate) unlink: bool,
+    /// Manage the backups `up link` made, rather than linking.
+    #[clap(subcommand)]
+    #[serde(skip)]
+    pub(crate) cmd: Option<LinkSubcommand>,
+}
+
+/// Deserialize either a single string or a list of strings.
+fn one_or_more<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    /// A single string or a list of strings.
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMore {
+        /// A single string.
+        One(String),
+        /// A list of strings.
+        More(Vec<String>),
+    }
+    Ok(
+        match <OneOrMore as serde::Deserialize>::deserialize(deserializer)? {
+            OneOrMore::One(one) => vec![one],
+            OneOrMore::More(more) => more,
+        },
+    )
+}
+
+/// What `up link` does when more than one `from_dir` has the same file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum LinkConflicts {
+    /// Link the file from the first `from_dir` that has it.
+    FirstWins,
+    /// Link the file from the last `from_dir` that has it (so later directories override earlier
+    /// ones).
+    #[default]
+    LastWins,
+    /// Fail without linking anything.
+    Error,
+}
+
+/// Subcommands supported by `up link`.
+#[derive(Debug, Clone, Parser)]
+pub(crate) enum LinkSubcommand {
+    /**
+    List the files `up link` has backed up, grouped by the run that backed them up (newest
+    first).
+
+    EXAMPLES:
+
+    ❯ up link backups
+    */
+    Backups,
+    /**
+    Put back files `up link` backed up, replacing the links to them.
+
+    EXAMPLES:
+
+    ❯ up link restore ~/.config/nvim
+
+    ❯ up link restore 2024-04-26T11_22_33
+    */
+    Restore(LinkRestoreOptions),
+}
+
+/// Options passed to `up link restore`.
+#[derive(Debug, Clone, Parser)]
+pub(crate) struct LinkRestoreOptions {
+    /**
+    A path that was backed up (or a directory containing backed-up paths), whose latest backup
+    should be restored. Or the ID of a backup run (as shown by `up link backups`, or a unique
+    prefix of it), to restore everything backed up in that run.
+    */
+    pub(crate) target: String,
+}
+
+/// Options passed to `up unlink`.
+#[derive(Debug, Clone, Parser, Default)]
+pub(crate) struct UnlinkOptions {
+    /// Path where your dotfiles are kept.
+    #[clap(short = 'f', long = "from", default_value = "~/code/dotfiles", value_hint = ValueHint::DirPath)]
+    pub(crate) from_dir: String,
+    /// Path they were linked to.
+    #[clap(short = 't', long = "to", default_value = "~", value_hint = ValueHint::DirPath)]
+    pub(crate) to_dir: String,
+    /// Only unlink these paths (in, or relative to, the `--to` directory), and links inside them.
+    #[clap(value_hint = ValueHint::AnyPath)]
+    pub(crate) paths: Vec<Utf8PathBuf>,
+    /// Show what would be unlinked and restored, without changing anything.
+    #[clap(long)]
+    pub(crate) dry_run: bool,
+}
+
+/// How `up link` puts a file from `from_dir` into `to_dir`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum LinkStrategy {
+    /// Symlink to the absolute path of the file.
+    #[default]
+    Symlink,
+    /// Symlink to the path of the file relative to the link, so both directories can be moved
+    /// together.
+    #[value(alias = "relative_symlink")]
+    RelativeSymlink,
+    /// Hard link to the file (both directories must be on the same filesystem).
+    Hardlink,
+    /// Copy of the file, kept in sync with it.
+    Copy,
+}
+
+/// A link strategy to use for files matching a glob, instead of the default strategy.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct StrategyOverride {
+    /// Gitignore-style pattern, relative to `from_dir`.
+    pub(crate) glob: String,
+    /// Strategy for matching files.
+    pub(crate) strategy: LinkStrategy,
+}
+
+impl FromStr for StrategyOverride {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (glob, strategy) = s
+            .rsplit_once('=')
+            .ok_or_else(|| format!("expected <glob>=<strategy>, got '{s}'"))?;
+        Ok(Self {
+            glob: glob.to_owned(),
+            strategy: LinkStrategy::from_str(strategy, true)?,
+        })
+    }
+}
+
+/// A file mode to set on files matching a glob.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct PermissionRule {
+    /// Gitignore-style pattern, relative to `from_dir`.
+    pub(crate) glob: String,
+    /// Mode for matching files.
+    pub(crate) mode: FileMode,
+}
+
+impl FromStr for PermissionRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (glob, mode) = s
+            .rsplit_once('=')
+            .ok_or_else(|| format!("expected <glob>=<mode>, got '{s}'"))?;
+        Ok(Self {
+            glob: glob.to_owned(),
+            mode: mode.parse()?,
+        })
+    }
+}
+
+/// Unix file permission bits, written in octal (e.g. `600`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub(crate) struct FileMode(pub(crate) u32);
+
+impl FromStr for FileMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        u32::from_str_radix(s.trim_start_matches("0o"), 8)
+            .ok()
+            .filter(|mode| *mode <= 0o7777)
+            .map(Self)
+            .ok_or_else(|| format!("expected an octal file mode like 600, got '{s}'"))
+    }
+}
+
+impl TryFrom<String> for FileMode {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<FileMode> for String {
+    fn from(mode: FileMode) -> Self {
+        mode.to_string()
+    }
+}
+
+impl fmt::Display for FileMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:03o}", self.0)
+    }
 }
 
 /// Options passed to `up git`.
@@ -294,11 +918,19 @@ pub(crate) enum DocSubcommand {
     /**
     Generate shell completions to stdout.
 
-    Completions are printed to stdout. They are designed to be written to a file.
+    Completions are printed to stdout. By default they are static, and designed to be written to
+    a file. With `--dynamic`, the shell calls back into up while completing, so it can also
+    complete task names (for `--tasks`, `--exclude-tasks`, and `up logs`) from the up config
+    being used. Dynamic completions should be sourced on shell startup, so they always match the
+    installed version of up.
 
     EXAMPLES:
 
     ❯ up doc completions zsh | sudo tee >/dev/null /usr/local/share/zsh/site-functions/_up
+
+    ❯ echo 'source <(up doc completions --dynamic zsh)' >> ~/.zshrc
+
+    ❯ echo 'source (up doc completions --dynamic fish | psub)' >> ~/.config/fish/config.fish
     */
     Completions(CompletionsOptions),
     /**
@@ -334,6 +966,11 @@ pub(crate) struct CompletionsOptions {
     /// Shell for which to generate completions.
     #[clap(value_enum)]
     pub(crate) shell: Shell,
+
+    /// Generate completions that call back into up to complete task names, rather than static
+    /// completions.
+    #[clap(long)]
+    pub(crate) dynamic: bool,
 }
 
 impl Default for UpdateSelfOptions {
@@ -447,6 +1084,10 @@ pub struct DefaultsWriteOptions {
 /// Options supported by the `up faketty` subcommand.
 #[derive(Debug, Parser, Default, Clone)]
 pub struct FakettyOptions {
+    /// Send stderr to the same terminal as stdout, so both are written to stdout, in the order
+    /// they were printed.
+    #[clap(long)]
+    pub(crate) merge_output: bool,
     /// The program to run.
     #[clap(
         num_args(1..),
@@ -5,8 +5,12 @@ use camino::Utf8Path;
 use camino::Utf8PathBuf;
 use color_eyre::eyre::Result;
 use std::fmt::Display;
+use std::fs;
 use std::ops::Deref;
 use std::str::FromStr;
+use tracing::debug;
+use tracing::info;
+use tracing::warn;
 
 /// The path to a temporary directory for up to use for temporary file output.
 #[derive(Debug, Clone)]
@@ -31,14 +35,67 @@ impl TempDir {
         files::create_dir_all(&self.0)?;
         Ok(self.as_ref())
     }
+
+    /**
+    Move state that earlier versions of up left in the old default temp dir (`$TMPDIR/up`) into
+    this one, if this is the default temp dir and it has moved (to the XDG state dir on Linux).
+
+    Run results, backups, and other state that already exist here are left alone, as is anything
+    that can't be moved (e.g. because `$TMPDIR` is on another filesystem), with a warning saying
+    how to move it.
+    */
+    pub fn migrate_legacy(&self) {
+        if self.0 != Self::default().0 {
+            return;
+        }
+        let Ok(legacy_dir) = Utf8PathBuf::try_from(std::env::temp_dir()).map(|dir| dir.join("up"))
+        else {
+            return;
+        };
+        if legacy_dir == self.0 || !legacy_dir.is_dir() {
+            return;
+        }
+        let Ok(entries) = legacy_dir.read_dir_utf8() else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let new_path = self.0.join(entry.file_name());
+            if new_path.symlink_metadata().is_ok() {
+                debug!(
+                    "Not moving {} from the old temp dir, {new_path} exists.",
+                    entry.path()
+                );
+                continue;
+            }
+            let result = files::create_dir_all(&self.0)
+                .and_then(|()| Ok(fs::rename(entry.path(), &new_path)?));
+            match result {
+                Ok(()) => info!(
+                    "Moved {} to {new_path}, as the default up temp dir has moved.",
+                    entry.path()
+                ),
+                Err(e) => warn!(
+                    "The default up temp dir has moved to {dir}, but {path} couldn't be moved \
+                     there ({e}). To keep it, run: mv {path} {new_path}",
+                    dir = self.0,
+                    path = entry.path(),
+                ),
+            }
+        }
+        // Only removed if everything was moved.
+        _ = fs::remove_dir(&legacy_dir);
+    }
 }
 
 impl Default for TempDir {
     fn default() -> Self {
-        let mut temp_dir = Utf8PathBuf::try_from(std::env::temp_dir())
-            .expect("Expected default temporary directory for system to be valid UTF-8");
-        temp_dir.push("up");
-        Self(temp_dir)
+        // Fall back to the system temp dir if we can't find the user's state directory.
+        Self(files::state_dir().unwrap_or_else(|_| {
+            let mut temp_dir = Utf8PathBuf::try_from(std::env::temp_dir())
+                .expect("Expected default temporary directory for system to be valid UTF-8");
+            temp_dir.push("up");
+            temp_dir
+        }))
     }
 }
 
@@ -0,0 +1,225 @@
+/*!
+Run up tasks from other Rust programs.
+
+```no_run
+# fn main() -> color_eyre::eyre::Result<()> {
+use up::runner::Runner;
+
+let results = Runner::new("/path/to/up.yaml")
+    .tasks(["brew", "link"])
+    .keep_going(true)
+    .run()?;
+for (name, result) in &results.tasks {
+    println!("{name}: {status}", status = result.status.as_str());
+}
+# Ok(())
+# }
+```
+*/
+use crate::config::ConfigYaml;
+use crate::config::UpConfig;
+use crate::opts::paths::TempDir;
+use crate::opts::start_time::StartTime;
+use crate::tasks;
+use crate::tasks::RunHooks;
+use crate::tasks::run_lib::RunLib;
+use crate::tasks::run_lib::RunLibs;
+use crate::tasks::runs::RunResults;
+use camino::Utf8PathBuf;
+use color_eyre::eyre::Context;
+use color_eyre::eyre::Result;
+use color_eyre::eyre::eyre;
+use std::fs;
+
+/**
+Builder for a run of the tasks in an up config.
+
+Unlike `up run`, failing tasks don't cause [`Runner::run`] to return an error, instead the
+status and error of each task are returned in the [`RunResults`]. The exception is a failing
+bootstrap task (with [`Runner::bootstrap`]), which stops the run with an error unless
+[`Runner::keep_going`] is set.
+
+Also unlike `up run`, a `Runner` doesn't prompt for sudo or remove old runs from the temp dir
+unless asked to with [`Runner::sudo`] and [`Runner::apply_retention`].
+*/
+#[allow(clippy::struct_excessive_bools)] // Each has its own builder method.
+#[derive(Debug)]
+pub struct Runner {
+    /// Path to the up config file.
+    config_path: Utf8PathBuf,
+    /// Only run these tasks.
+    tasks: Option<Vec<String>>,
+    /// Don't run these tasks.
+    exclude_tasks: Option<Vec<String>>,
+    /// Temporary directory for run results and task output.
+    temp_dir: Option<Utf8PathBuf>,
+    /// Run the config's bootstrap tasks first, in order.
+    bootstrap: bool,
+    /// Keep going if a bootstrap task fails.
+    keep_going: bool,
+    /// Whether task stdout/stderr should inherit from this process's stdout/stderr.
+    console: Option<bool>,
+    /// Only report what tasks would change.
+    dry_run: bool,
+    /// Record every task's terminal session.
+    record: bool,
+    /// Skip tasks that need privileges.
+    no_sudo: bool,
+    /// Get sudo before running if any task needs privileges.
+    sudo: bool,
+    /// Remove old runs from the temp dir afterwards.
+    apply_retention: bool,
+    /// Run libraries available to tasks.
+    libs: RunLibs,
+}
+
+impl Runner {
+    /// Run the tasks in the up config at `config_path`, with the built-in run libraries.
+    pub fn new(config_path: impl Into<Utf8PathBuf>) -> Self {
+        Self {
+            config_path: config_path.into(),
+            tasks: None,
+            exclude_tasks: None,
+            temp_dir: None,
+            bootstrap: false,
+            keep_going: false,
+            console: None,
+            dry_run: false,
+            record: false,
+            no_sudo: false,
+            sudo: false,
+            apply_retention: false,
+            libs: RunLibs::builtin(),
+        }
+    }
+
+    /// Only run these tasks (like `up run --tasks`).
+    #[must_use]
+    pub fn tasks(mut self, tasks: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.tasks = Some(tasks.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Don't run these tasks, even if passed to [`Runner::tasks`] (like
+    /// `up run --exclude-tasks`).
+    #[must_use]
+    pub fn exclude_tasks(mut self, tasks: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.exclude_tasks = Some(tasks.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Directory for run results and task output (like `up --temp-dir`).
+    #[must_use]
+    pub fn temp_dir(mut self, temp_dir: impl Into<Utf8PathBuf>) -> Self {
+        self.temp_dir = Some(temp_dir.into());
+        self
+    }
+
+    /// Run the config's `bootstrap_tasks` first, in order (like `up run --bootstrap`).
+    #[must_use]
+    pub const fn bootstrap(mut self, bootstrap: bool) -> Self {
+        self.bootstrap = bootstrap;
+        self
+    }
+
+    /// Keep going if a bootstrap task fails (like `up run --keep-going`).
+    #[must_use]
+    pub const fn keep_going(mut self, keep_going: bool) -> Self {
+        self.keep_going = keep_going;
+        self
+    }
+
+    /// Whether task stdout/stderr should inherit from this process's stdout/stderr (like
+    /// `up run --console`).
+    #[must_use]
+    pub const fn console(mut self, console: bool) -> Self {
+        self.console = Some(console);
+        self
+    }
+
+    /// Only report what tasks would change (like `up run --dry-run`).
+    #[must_use]
+    pub const fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Record every task's terminal session (like `up run --record`).
+    #[must_use]
+    pub const fn record(mut self, record: bool) -> Self {
+        self.record = record;
+        self
+    }
+
+    /// Don't prompt for sudo, and skip tasks that need privileges (like `up run --no-sudo`).
+    #[must_use]
+    pub const fn no_sudo(mut self, no_sudo: bool) -> Self {
+        self.no_sudo = no_sudo;
+        self
+    }
+
+    /**
+    Get sudo before running (and keep it alive while tasks run) if any task needs privileges, as
+    `up run` does. This may prompt for a password.
+
+    Off by default, in which case tasks that need sudo ask for it themselves when they run.
+    */
+    #[must_use]
+    pub const fn sudo(mut self, sudo: bool) -> Self {
+        self.sudo = sudo;
+        self
+    }
+
+    /**
+    Remove old runs and logs according to the up config's `retention` policy after running, as
+    `up run` does.
+
+    Off by default, so the temp dir is only cleaned if you ask for it.
+    */
+    #[must_use]
+    pub const fn apply_retention(mut self, apply_retention: bool) -> Self {
+        self.apply_retention = apply_retention;
+        self
+    }
+
+    /// Make a run library available to tasks, replacing any library with the same name.
+    #[must_use]
+    pub fn register_lib(mut self, lib: impl RunLib + 'static) -> Self {
+        self.libs.register(lib);
+        self
+    }
+
+    /**
+    Load the up config and run the selected tasks.
+
+    Returns an error if the config or tasks couldn't be loaded, or if a bootstrap task fails
+    without [`Runner::keep_going`], but not if other tasks fail.
+    */
+    pub fn run(self) -> Result<RunResults> {
+        let config_path = self.config_path;
+        let config_str = fs::read_to_string(&config_path)
+            .wrap_err_with(|| eyre!("Failed to read up config {config_path}"))?;
+        let config = UpConfig {
+            config_yaml: ConfigYaml::parse(&config_str)?,
+            up_yaml_path: Some(config_path),
+            bootstrap: self.bootstrap,
+            keep_going: self.keep_going,
+            tasks: self.tasks,
+            exclude_tasks: self.exclude_tasks,
+            run_libs: None,
+            rerun: None,
+            console: self.console,
+            dry_run: self.dry_run,
+            record: self.record,
+            no_sudo: self.no_sudo,
+            interactive: false,
+            temp_dir: self.temp_dir.unwrap_or_else(|| TempDir::default().0),
+            start_time: StartTime::default(),
+        };
+        let hooks = RunHooks {
+            sudo: self.sudo,
+            retention: self.apply_retention,
+        };
+        tasks::run_with_libs(&config, self.libs, hooks)
+    }
+}
@@ -2,16 +2,24 @@
 //! Logic for dealing with tasks executed by up.
 use self::TaskError as E;
 use self::task::CommandType;
+use self::task::RunSettings;
 use self::task::Task;
+use crate::clean;
 use crate::config;
 use crate::env::get_env;
+use crate::tasks::durations::TaskDurations;
+use crate::tasks::run_lib::RunLibs;
+use crate::tasks::runs::RunRecorder;
+use crate::tasks::runs::RunResults;
 use crate::tasks::task::TaskStatus;
+use crate::utils::errors::log_error;
 use crate::utils::files;
+use crate::utils::time::human_readable_duration;
 use crate::utils::user::current_user_is_root;
 use crate::utils::user::get_and_keep_sudo;
+use crate::utils::user::get_sudo;
 use camino::Utf8Path;
 use camino::Utf8PathBuf;
-use chrono::SecondsFormat;
 use color_eyre::eyre::Result;
 use color_eyre::eyre::bail;
 use color_eyre::eyre::eyre;
@@ -23,6 +31,9 @@ use rayon::prelude::*;
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::io;
+use std::sync::mpsc;
+use std::sync::mpsc::RecvTimeoutError;
+use std::thread;
 use std::time::Duration;
 use std::time::Instant;
 use thiserror::Error;
@@ -33,9 +44,36 @@ use tracing::trace;
 use tracing::warn;
 use tracing_indicatif::span_ext::IndicatifSpanExt;
 
+pub mod changes;
 pub mod defaults;
+mod durations;
+pub(crate) mod external;
 pub mod git;
 pub mod link;
+#[cfg(feature = "interactive")]
+mod picker;
+/// Stand-in for the task picker when up is built without the `interactive` feature.
+#[cfg(not(feature = "interactive"))]
+mod picker {
+    use crate::tasks::task::Task;
+    use camino::Utf8Path;
+    use color_eyre::eyre::Result;
+    use color_eyre::eyre::bail;
+    use std::collections::HashMap;
+
+    /// Fail, as there's no picker to show.
+    pub(crate) fn pick(
+        _temp_dir: &Utf8Path,
+        _tasks: HashMap<String, Task>,
+    ) -> Result<Option<HashMap<String, Task>>> {
+        bail!(
+            "`up run --interactive` needs up to be built with the `interactive` feature, e.g. \
+             `cargo install up --features interactive`."
+        );
+    }
+}
+pub mod run_lib;
+pub mod runs;
 pub mod task;
 pub mod update_self;
 
@@ -83,6 +121,27 @@ impl TasksDir {
     }
 }
 
+/// What happens around a run of tasks, besides running them.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RunHooks {
+    /// Get sudo before running (and keep it alive) if any task needs privileges.
+    pub(crate) sudo: bool,
+    /// Remove old runs afterwards, according to the retention policy.
+    pub(crate) retention: bool,
+}
+
+/// Tasks loaded from the tasks dir, along with what they need to run.
+struct LoadedTasks {
+    /// Tasks to run in order before the rest, in bootstrap mode.
+    bootstrap_tasks: Vec<String>,
+    /// All the tasks that matched the filters, keyed by name.
+    tasks: HashMap<String, Task>,
+    /// Env resolved from the up config.
+    env: HashMap<String, String>,
+    /// Whether task stdout/stderr should inherit from up's stdout/stderr.
+    console: bool,
+}
+
 /// Run a set of tasks specified in a subdir of the directory containing the up
 /// config.
 pub fn run(
@@ -90,14 +149,47 @@ pub fn run(
     tasks_dirname: TasksDir,
     tasks_action: TasksAction,
 ) -> Result<()> {
-    // TODO(gib): Handle missing dir & move into config.
-    let mut tasks_dir = config
-        .up_yaml_path
-        .as_ref()
-        .ok_or(E::UnexpectedNone)?
-        .clone();
-    tasks_dir.pop();
-    tasks_dir.push(tasks_dirname.to_dir_name());
+    let hooks = RunHooks {
+        sudo: true,
+        retention: true,
+    };
+    let Some(loaded) = load(config, tasks_dirname, tasks_action, hooks)? else {
+        return Ok(());
+    };
+    match tasks_action {
+        TasksAction::List => println!("{}", loaded.tasks.keys().join("\n")),
+        TasksAction::Run => {
+            let (completed_tasks, _) = run_and_clean(config, loaded, RunLibs::builtin(), hooks)?;
+            report(completed_tasks, config.dry_run, config.no_sudo)?;
+        }
+    }
+    Ok(())
+}
+
+/**
+Run the tasks in the tasks dir with the run libraries in `libs`, returning the result of each
+task rather than logging a summary or failing if a task fails.
+*/
+pub(crate) fn run_with_libs(
+    config: &config::UpConfig,
+    libs: RunLibs,
+    hooks: RunHooks,
+) -> Result<RunResults> {
+    let loaded = load(config, TasksDir::Tasks, TasksAction::Run, hooks)?
+        .ok_or_else(|| eyre!("Task picker was cancelled."))?;
+    let (_, results) = run_and_clean(config, loaded, libs, hooks)?;
+    Ok(results)
+}
+
+/// Load the tasks to run and the env to run them with, and get sudo if any task needs it (and
+/// the `hooks` say to). Returns `None` if the user cancelled `up run --interactive`.
+fn load(
+    config: &config::UpConfig,
+    tasks_dirname: TasksDir,
+    tasks_action: TasksAction,
+    hooks: RunHooks,
+) -> Result<Option<LoadedTasks>> {
+    let tasks_dir = tasks_dir(config, tasks_dirname)?;
 
     let env = get_env(
         config.config_yaml.inherit_env.as_ref(),
@@ -113,7 +205,7 @@ pub fn run(
 
     // TODO(gib): Handle and filter by constraints.
 
-    let bootstrap_tasks = match (config.bootstrap, &config.config_yaml.bootstrap_tasks) {
+    let mut bootstrap_tasks = match (config.bootstrap, &config.config_yaml.bootstrap_tasks) {
         (false, _) => Ok(Vec::new()),
         (true, None) => Err(eyre!(
             "Bootstrap flag set but no bootstrap_tasks specified in config."
@@ -121,19 +213,94 @@ pub fn run(
         (true, Some(b_tasks)) => Ok(b_tasks.clone()),
     }?;
 
-    let filter_tasks_set: Option<HashSet<String>> =
-        config.tasks.clone().map(|v| v.into_iter().collect());
-    debug!("Filter tasks set: {filter_tasks_set:?}");
+    let mut tasks = load_tasks(config, &tasks_dir)?;
+
+    // Only run the tasks the user picks, after the filters in the config have been applied.
+    if config.interactive && matches!(tasks_action, TasksAction::Run) {
+        let Some(picked) = picker::pick(&config.temp_dir, tasks)? else {
+            info!("Task picker cancelled, not running anything.");
+            return Ok(None);
+        };
+        tasks = picked;
+        if tasks.is_empty() {
+            info!("No tasks picked, nothing to run.");
+        }
+        bootstrap_tasks.retain(|name| tasks.contains_key(name));
+    }
 
-    let excluded_tasks: HashSet<String> = config
-        .exclude_tasks
-        .clone()
-        .map_or_else(HashSet::new, |v| v.into_iter().collect());
-    debug!("Excluded tasks set: {excluded_tasks:?}");
+    if matches!(tasks_action, TasksAction::Run)
+        && hooks.sudo
+        && !config.no_sudo
+        && !current_user_is_root()
+    {
+        if tasks.values().any(|t| t.config.needs_sudo) {
+            get_and_keep_sudo(false)?;
+        } else if tasks.values().any(Task::needs_privileges) {
+            // Tasks with `run_as` use sudo for their own commands, so just make sure we have
+            // credentials now, rather than keeping them alive for everything up runs.
+            get_sudo(false)?;
+        }
+    }
+
+    debug!("Task count: {:?}", tasks.len());
+    trace!("Task list: {tasks:#?}");
+
+    let console = config
+        .console
+        .unwrap_or_else(|| bootstrap_tasks.len() + tasks.len() == 1);
+    trace!("Setting console option to: {console}");
+
+    Ok(Some(LoadedTasks {
+        bootstrap_tasks,
+        tasks,
+        env,
+        console,
+    }))
+}
+
+/// Run the loaded tasks, then remove old runs according to the retention policy (if the `hooks`
+/// say to).
+fn run_and_clean(
+    config: &config::UpConfig,
+    loaded: LoadedTasks,
+    libs: RunLibs,
+    hooks: RunHooks,
+) -> Result<(Vec<Task>, RunResults)> {
+    let result = run_tasks(config, loaded, libs);
+    if hooks.retention
+        && let Err(e) = clean::apply_retention(config)
+    {
+        warn!("Failed to remove old runs.{}", log_error(&e));
+    }
+    result
+}
+
+/// The directory containing the task config files, found next to the up config file.
+pub(crate) fn tasks_dir(config: &config::UpConfig, tasks_dirname: TasksDir) -> Result<Utf8PathBuf> {
+    // TODO(gib): Handle missing dir & move into config.
+    let mut tasks_dir = config
+        .up_yaml_path
+        .as_ref()
+        .ok_or(E::UnexpectedNone)?
+        .clone();
+    tasks_dir.pop();
+    tasks_dir.push(tasks_dirname.to_dir_name());
+    Ok(tasks_dir)
+}
+
+/// Load the tasks in `tasks_dir`, leaving out any tasks the config excludes or filters out.
+pub(crate) fn load_tasks(
+    config: &config::UpConfig,
+    tasks_dir: &Utf8Path,
+) -> Result<HashMap<String, Task>> {
+    filter_tasks(config, read_tasks(tasks_dir)?)
+}
 
+/// Load all the tasks in `tasks_dir`, removing any broken symlinks to task configs.
+pub(crate) fn read_tasks(tasks_dir: &Utf8Path) -> Result<HashMap<String, Task>> {
     let mut tasks: HashMap<String, task::Task> = HashMap::new();
     for entry in tasks_dir.read_dir().map_err(|e| E::ReadDir {
-        path: tasks_dir.clone(),
+        path: tasks_dir.to_owned(),
         source: e,
     })? {
         let entry = entry?;
@@ -147,80 +314,151 @@ pub fn run(
             continue;
         }
         let task = task::Task::from(&path)?;
-        let name = &task.name;
+        tasks.insert(task.name.clone(), task);
+    }
+    Ok(tasks)
+}
 
-        if excluded_tasks.contains(name) {
-            debug!(
-                "Not running task '{name}' as it is in the excluded tasks set {excluded_tasks:?}"
-            );
-            continue;
-        }
+/// Leave out any of `tasks` that the config excludes or filters out.
+pub(crate) fn filter_tasks(
+    config: &config::UpConfig,
+    mut tasks: HashMap<String, Task>,
+) -> Result<HashMap<String, Task>> {
+    let selected = selected_tasks(config, &tasks)?;
+    tasks.retain(|name, _| selected.contains(name));
+    Ok(tasks)
+}
 
-        if let Some(filter) = filter_tasks_set.as_ref()
-            && !filter.contains(name)
-        {
-            debug!("Not running task '{name}' as not in tasks filter {filter:?}",);
-            continue;
-        }
-        tasks.insert(name.clone(), task);
-    }
+/**
+The names of the `tasks` that the config doesn't exclude or filter out.
 
-    if matches!(tasks_action, TasksAction::Run)
-        && tasks.values().any(|t| t.config.needs_sudo)
-        && !current_user_is_root()
-    {
-        get_and_keep_sudo(false)?;
-    }
+Excluding takes priority over the `--tasks`, `--run-libs`, and rerun filters.
+*/
+pub(crate) fn selected_tasks(
+    config: &config::UpConfig,
+    tasks: &HashMap<String, Task>,
+) -> Result<HashSet<String>> {
+    let excluded_tasks: HashSet<String> = config
+        .exclude_tasks
+        .clone()
+        .map_or_else(HashSet::new, |v| v.into_iter().collect());
+    debug!("Excluded tasks set: {excluded_tasks:?}");
 
-    debug!("Task count: {:?}", tasks.len());
-    trace!("Task list: {tasks:#?}");
+    let filter_tasks_set: Option<HashSet<String>> = match config.rerun {
+        Some(rerun) => Some(runs::rerun_tasks(&config.temp_dir, rerun, tasks)?),
+        None => config.tasks.clone().map(|v| v.into_iter().collect()),
+    };
+    debug!("Filter tasks set: {filter_tasks_set:?}");
 
-    let console = config
-        .console
-        .unwrap_or_else(|| bootstrap_tasks.len() + tasks.len() == 1);
-    trace!("Setting console option to: {console}");
+    Ok(tasks
+        .keys()
+        .filter(|name| {
+            if excluded_tasks.contains(*name) {
+                debug!(
+                    "Not running task '{name}' as it is in the excluded tasks set \
+                     {excluded_tasks:?}"
+                );
+                return false;
+            }
+            if let Some(filter) = &filter_tasks_set
+                && !filter.contains(*name)
+            {
+                debug!("Not running task '{name}' as not in tasks filter {filter:?}");
+                return false;
+            }
+            if let Some(run_libs) = &config.run_libs
+                && !tasks
+                    .get(*name)
+                    .and_then(|task| task.config.run_lib.as_ref())
+                    .is_some_and(|run_lib| run_libs.contains(run_lib))
+            {
+                debug!("Not running task '{name}' as its run_lib isn't in {run_libs:?}");
+                return false;
+            }
+            true
+        })
+        .cloned()
+        .collect())
+}
 
-    match tasks_action {
-        TasksAction::List => println!("{}", tasks.keys().join("\n")),
-        TasksAction::Run => {
-            let run_tempdir = config.temp_dir.join(format!(
-                "runs/{start_time}",
-                start_time = config
-                    .start_time
-                    .to_rfc3339_opts(SecondsFormat::AutoSi, true)
-                    // : is not an allowed filename character in Finder.
-                    .replace(':', "_")
-            ));
-
-            run_tasks(
-                bootstrap_tasks,
-                tasks,
-                &env,
-                &run_tempdir,
-                config.keep_going,
-                console,
-            )?;
-        }
-    }
-    Ok(())
+/// Expand `~` and env vars in a task config string, using the env resolved from the up config.
+pub(crate) fn resolve_env_string(s: &str, env: &HashMap<String, String>) -> Result<String, E> {
+    let home_dir = files::home_dir().map_err(|e| E::EyreError { source: e })?;
+    let out = shellexpand::full_with_context(
+        s,
+        || Some(home_dir),
+        |k| env.get(k).ok_or_else(|| eyre!("Value not found")).map(Some),
+    )
+    .map(std::borrow::Cow::into_owned)
+    .map_err(|e| E::ResolveEnv {
+        var: e.var_name,
+        source: e.cause,
+    })?;
+
+    Ok(out)
 }
 
-/// Runs a set of tasks.
+/// Runs a set of tasks, returning the finished tasks and their recorded results.
 fn run_tasks(
-    bootstrap_tasks: Vec<String>,
-    mut tasks: HashMap<String, task::Task>,
-    env: &HashMap<String, String>,
-    temp_dir: &Utf8Path,
-    keep_going: bool,
-    console: bool,
-) -> Result<()> {
+    config: &config::UpConfig,
+    loaded: LoadedTasks,
+    libs: RunLibs,
+) -> Result<(Vec<Task>, RunResults)> {
+    let LoadedTasks {
+        bootstrap_tasks,
+        mut tasks,
+        env,
+        console,
+    } = loaded;
+    let env = &env;
     let mut completed_tasks = Vec::new();
+    let temp_dir = &runs::run_dir(&config.temp_dir, &config.start_time);
+    let settings = RunSettings {
+        console,
+        dry_run: config.dry_run,
+        record: config.record,
+        no_sudo: config.no_sudo,
+        lib_dirs: config
+            .up_yaml_path
+            .as_deref()
+            .and_then(Utf8Path::parent)
+            .map(Utf8Path::to_owned)
+            .into_iter()
+            .collect(),
+        libs,
+        up_dir: config.temp_dir.clone(),
+    };
+
+    // Tasks that aren't auto-run are never started, so don't record them as pending.
+    tasks.retain(|name, task| {
+        task.config.auto_run.unwrap_or(true) || bootstrap_tasks.contains(name)
+    });
+    let recorder = RunRecorder::new(
+        temp_dir,
+        *config.start_time,
+        bootstrap_tasks
+            .iter()
+            .map(String::as_str)
+            .chain(tasks.keys().map(String::as_str)),
+        !config.dry_run,
+    )?;
+
+    let mut durations = TaskDurations::load(&config.temp_dir);
 
     // Has to be top-level so span continues for whole run.
-    let _header_span;
-    if !console {
-        _header_span = set_up_header(tasks.len() + bootstrap_tasks.len())?;
-    }
+    let header_span = if console {
+        None
+    } else {
+        let total_weight = bootstrap_tasks
+            .iter()
+            .chain(tasks.keys())
+            .map(|name| durations.weight(name))
+            .sum();
+        Some(set_up_header(
+            tasks.len() + bootstrap_tasks.len(),
+            total_weight,
+        )?)
+    };
 
     if !bootstrap_tasks.is_empty() {
         for task_name in bootstrap_tasks {
@@ -232,9 +470,14 @@ fn run_tasks(
                     .ok_or_else(|| eyre!("Task '{task_name}' was missing."))?,
                 env,
                 &task_tempdir,
-                console,
+                &settings,
+                &recorder,
+                &durations,
+                header_span.as_ref(),
             );
-            if !keep_going && let TaskStatus::Failed(e) = task.status {
+            if !config.keep_going
+                && let TaskStatus::Failed(e) = task.status
+            {
                 bail!(e);
             }
             completed_tasks.push(task);
@@ -244,7 +487,6 @@ fn run_tasks(
     completed_tasks.extend(
         tasks
             .into_par_iter()
-            .filter(|(_, task)| task.config.auto_run.unwrap_or(true))
             .map(|(_, task)| {
                 let task_name = task.name.as_str();
                 let _span = if console {
@@ -254,12 +496,60 @@ fn run_tasks(
                     tracing::info_span!("task", task = task_name).entered()
                 };
                 let task_tempdir = create_task_tempdir(temp_dir, task_name)?;
-                Ok(run_task(task, env, &task_tempdir, console))
+                Ok(run_task(
+                    task,
+                    env,
+                    &task_tempdir,
+                    &settings,
+                    &recorder,
+                    &durations,
+                    header_span.as_ref(),
+                ))
             })
             .collect::<Result<Vec<Task>>>()?,
     );
+    let results = recorder.results()?;
+    // Dry runs don't run most commands, so their durations would skew the estimates.
+    if !config.dry_run
+        && let Err(e) = durations.update(&results)
+    {
+        warn!("Failed to save task durations: {e:?}");
+    }
+    Ok((completed_tasks, results))
+}
+
+/**
+Log a summary of the finished tasks, what they changed, and which needed privileges, returning an
+error if any of them failed.
+*/
+fn report(mut completed_tasks: Vec<Task>, dry_run: bool, no_sudo: bool) -> Result<()> {
     let completed_tasks_len = completed_tasks.len();
 
+    completed_tasks.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+    let privileged_tasks = completed_tasks
+        .iter()
+        .filter(|task| task.needs_privileges())
+        .map(|task| task.name.clone())
+        .collect_vec();
+    let changes = completed_tasks
+        .iter()
+        .flat_map(|task| task.changes.iter().map(|change| (&task.name, change)))
+        .collect_vec();
+    if !changes.is_empty() {
+        let heading = if dry_run {
+            "Changes that would be made"
+        } else {
+            "Changes made"
+        };
+        info!(
+            "{heading}:\n{}",
+            changes
+                .iter()
+                .map(|(task_name, change)| format!("  {task_name}: {change}"))
+                .join("\n")
+        );
+    }
+
     let mut tasks_passed = Vec::new();
     let mut tasks_skipped = Vec::new();
     let mut tasks_failed = Vec::new();
@@ -294,6 +584,13 @@ fn run_tasks(
             tasks_skipped.iter().map(|t| &t.name).collect::<Vec<_>>()
         );
     }
+    if !privileged_tasks.is_empty() {
+        if no_sudo {
+            info!("Tasks skipped as they need privileges (--no-sudo): {privileged_tasks:?}");
+        } else {
+            info!("Tasks that needed privileges: {privileged_tasks:?}");
+        }
+    }
 
     if !tasks_failed.is_empty() {
         error!("One or more tasks failed, exiting.");
@@ -315,38 +612,76 @@ fn run_tasks(
     Ok(())
 }
 
-/// Runs a specific task.
+/**
+Runs a specific task.
+
+Shows how long the task usually takes in its progress bar, and warns if it is taking much
+longer than usual.
+*/
 fn run_task(
     mut task: Task,
     env: &HashMap<String, String>,
     task_tempdir: &Utf8Path,
-    console: bool,
+    settings: &RunSettings,
+    recorder: &RunRecorder,
+    durations: &TaskDurations,
+    header_span: Option<&tracing::Span>,
 ) -> Task {
-    let env_fn = &|s: &str| {
-        let home_dir = files::home_dir().map_err(|e| E::EyreError { source: e })?;
-        let out = shellexpand::full_with_context(
-            s,
-            || Some(home_dir),
-            |k| env.get(k).ok_or_else(|| eyre!("Value not found")).map(Some),
-        )
-        .map(std::borrow::Cow::into_owned)
-        .map_err(|e| E::ResolveEnv {
-            var: e.var_name,
-            source: e.cause,
-        })?;
+    let mut env = env.clone();
+    env.insert(
+        changes::UP_CHANGES_ENV_NAME.to_owned(),
+        changes::changes_file_path(task_tempdir).into_string(),
+    );
+    let env = &env;
+    let env_fn = &|s: &str| resolve_env_string(s, env);
 
-        Ok(out)
-    };
+    let expected = durations.expected(&task.name);
+    if let Some(expected) = expected
+        && let Ok(expected) = human_readable_duration(expected)
+    {
+        tracing::Span::current().pb_set_message(&format!("usually {expected}"));
+    }
 
+    recorder.started(&task.name);
     let now = Instant::now();
-    task.run(env_fn, env, task_tempdir, console);
+    let (done_tx, done_rx) = mpsc::channel::<()>();
+    thread::scope(|scope| {
+        let task_name = task.name.clone();
+        scope.spawn(move || warn_if_slow(&task_name, expected, &done_rx));
+        task.run(env_fn, env, task_tempdir, settings);
+        drop(done_tx);
+    });
     let elapsed_time = now.elapsed();
-    if elapsed_time > Duration::from_secs(60) {
-        warn!("Task took {elapsed_time:?}");
+    recorder.finished(&task, elapsed_time);
+    if let Some(header_span) = header_span {
+        header_span.pb_inc(duration_millis(durations.weight(&task.name)));
     }
     task
 }
 
+/// Warn if a task is still running (`done_rx` hasn't disconnected) once it has taken much
+/// longer than it usually does.
+fn warn_if_slow(task_name: &str, expected: Option<Duration>, done_rx: &mpsc::Receiver<()>) {
+    let threshold = durations::slow_threshold(expected);
+    if let Err(RecvTimeoutError::Timeout) = done_rx.recv_timeout(threshold) {
+        let threshold = human_readable_duration(threshold).unwrap_or_default();
+        if let Some(expected) = expected.and_then(|expected| human_readable_duration(expected).ok())
+        {
+            warn!(
+                "Task '{task_name}' has been running for over {threshold}, it usually takes \
+                 {expected}."
+            );
+        } else {
+            warn!("Task '{task_name}' has been running for over {threshold}.");
+        }
+    }
+}
+
+/// Number of milliseconds in a duration, used as progress bar units.
+fn duration_millis(duration: Duration) -> u64 {
+    u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+}
+
 /// Create a subdir of the current temporary directory for the task.
 fn create_task_tempdir(temp_dir: &Utf8Path, task_name: &str) -> Result<Utf8PathBuf> {
     let task_tempdir = temp_dir.join(task_name);
@@ -357,16 +692,20 @@ fn create_task_tempdir(temp_dir: &Utf8Path, task_name: &str) -> Result<Utf8PathB
 /**
 Set up a header span to show progress.
 
+Progress is measured in milliseconds of the tasks' usual durations (`total_weight` is the sum of
+them), so the percentage and ETA account for some tasks taking much longer than others.
+
 If you don't want this to show, filter out Indicatif progress bars by default with
 [`tracing_indicatif::filter::IndicatifFilter::new`] as `IndicatifFilter::new(false)`.
 */
-fn set_up_header(tasks_count: usize) -> Result<tracing::Span> {
+fn set_up_header(tasks_count: usize, total_weight: Duration) -> Result<tracing::Span> {
     let header_span = tracing::info_span!("header");
     let command = std::env::args().join(" ");
+    header_span.pb_set_length(duration_millis(total_weight));
     header_span.pb_set_style(
         &ProgressStyle::with_template(&format!(
             "Running {tasks_count} tasks for command: `{command}`. {{wide_msg}} \
-             {{elapsed_sec}}\n{{wide_bar}}"
+             {{percent}}% (ETA {{eta}}) {{elapsed_sec}}\n{{wide_bar}}"
         ))?
         .with_key(
             "elapsed_sec",
@@ -375,7 +714,7 @@ fn set_up_header(tasks_count: usize) -> Result<tracing::Span> {
                 let _ = writer.write_str(&format!("{seconds}s"));
             },
         )
-        .progress_chars("---"),
+        .progress_chars("=>-"),
     );
     header_span.pb_start();
     Ok(header_span)
@@ -422,6 +761,11 @@ pub enum TaskError {
         /// The task name.
         name: String,
     },
+    /// Task `{name}` sets both `run_as` and `run_lib`, but `run_as` only applies to commands.
+    RunAsWithRunLib {
+        /// The task name.
+        name: String,
+    },
     /**
     Task `{name}` {command_type} failed.Command: {cmd:?}.{suggestion}
     */
@@ -439,7 +783,7 @@ pub enum TaskError {
     },
     /**
     Task `{name}` {command_type} failed with exit code {code}. Command: {cmd:?}.
-      Output: {output_file}
+      Output: {output_file} (view with `up logs {name}`)
     */
     CmdNonZero {
         /// The type of command that failed (check or run).
@@ -455,7 +799,7 @@ pub enum TaskError {
     },
     /**
     Task `{name}` {command_type} was terminated. Command: {cmd:?}, output: {output_file}.
-      Output: {output_file}
+      Output: {output_file} (view with `up logs {name}`)
     */
     CmdTerminated {
         /// The type of command that failed (check or run).
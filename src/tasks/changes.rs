@@ -0,0 +1,200 @@
+/*!
+Records of what tasks changed on the machine.
+
+Run libraries return a [`Change`] for each thing they change (a symlink, a defaults value, a git
+checkout, etc.), and shell tasks can add their own by writing lines to the file named in
+`$UP_CHANGES`. Each line has tab-separated fields `kind`, `target`, and optionally `before`
+and `after`, e.g.:
+
+```sh
+printf 'package\t%s\t%s\t%s\n' jq 1.6 1.7 >> "$UP_CHANGES"
+```
+
+Changes are saved in the run results, and listed in the summary at the end of the run.
+*/
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use color_eyre::eyre::Context;
+use color_eyre::eyre::Result;
+use color_eyre::eyre::bail;
+use color_eyre::eyre::eyre;
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::sync::Mutex;
+
+/// Environment variable containing the path shell tasks should write change records to.
+pub const UP_CHANGES_ENV_NAME: &str = "UP_CHANGES";
+
+/// Name of the file in each task's directory that shell tasks write change records to.
+const CHANGES_FILE_NAME: &str = "changes.tsv";
+
+/// Something a task changed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Change {
+    /// What sort of thing changed, e.g. `symlink` or `defaults`.
+    pub kind: String,
+    /// What changed, e.g. a path or a defaults domain and key.
+    pub target: String,
+    /// The previous state, if there was one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub before: Option<String>,
+    /// The new state, if there is one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub after: Option<String>,
+}
+
+impl Change {
+    /// A change of `kind` to `target`, with no before or after state.
+    pub fn new(kind: impl Into<String>, target: impl Into<String>) -> Self {
+        Self {
+            kind: kind.into(),
+            target: target.into(),
+            before: None,
+            after: None,
+        }
+    }
+
+    /// Set the previous state.
+    #[must_use]
+    pub fn before(mut self, before: impl Into<String>) -> Self {
+        self.before = Some(before.into());
+        self
+    }
+
+    /// Set the new state.
+    #[must_use]
+    pub fn after(mut self, after: impl Into<String>) -> Self {
+        self.after = Some(after.into());
+        self
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.kind, self.target)?;
+        match (&self.before, &self.after) {
+            (Some(before), Some(after)) => write!(f, ": {before} -> {after}"),
+            (None, Some(after)) => write!(f, ": {after}"),
+            (Some(before), None) => write!(f, ": {before} -> (removed)"),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+/// Collects the changes a task makes while it runs, possibly from multiple threads.
+#[derive(Debug, Default)]
+pub struct ChangeRecorder {
+    /// Changes recorded so far.
+    changes: Mutex<Vec<Change>>,
+}
+
+impl ChangeRecorder {
+    /**
+    Record some changes.
+
+    # Panics
+
+    If another thread panicked while recording changes.
+    */
+    pub fn record(&self, changes: impl IntoIterator<Item = Change>) {
+        self.changes
+            .lock()
+            .expect("Changes lock should never be poisoned.")
+            .extend(changes);
+    }
+
+    /**
+    The recorded changes.
+
+    # Panics
+
+    If another thread panicked while recording changes.
+    */
+    #[must_use]
+    pub fn into_changes(self) -> Vec<Change> {
+        self.changes
+            .into_inner()
+            .expect("Changes lock should never be poisoned.")
+    }
+}
+
+/// The file a shell task should write change records to.
+#[must_use]
+pub fn changes_file_path(task_tempdir: &Utf8Path) -> Utf8PathBuf {
+    task_tempdir.join(CHANGES_FILE_NAME)
+}
+
+/// Read the change records a shell task wrote, if it wrote any.
+pub(crate) fn read_changes_file(path: &Utf8Path) -> Result<Vec<Change>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).wrap_err_with(|| eyre!("Failed to read changes file {path}")),
+    };
+    let mut changes = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut fields = line
+            .split('\t')
+            .map(|field| Some(field).filter(|field| !field.is_empty()));
+        let (Some(Some(kind)), Some(Some(target))) = (fields.next(), fields.next()) else {
+            bail!(
+                "Invalid change record on line {line_number} of {path}, expected tab-separated \
+                 kind, target, before, and after, got: {line:?}",
+                line_number = index + 1,
+            );
+        };
+        changes.push(Change {
+            kind: kind.to_owned(),
+            target: target.to_owned(),
+            before: fields.next().flatten().map(ToOwned::to_owned),
+            after: fields.next().flatten().map(ToOwned::to_owned),
+        });
+    }
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Change;
+    use super::read_changes_file;
+    use color_eyre::Result;
+    use std::fs;
+    use testutils::ensure_eq;
+
+    #[test]
+    fn test_read_changes_file() -> Result<()> {
+        let temp_dir = testutils::temp_dir("up", testutils::function_path!())?;
+        let path = temp_dir.join("changes.tsv");
+        ensure_eq!(Vec::<Change>::new(), read_changes_file(&path)?);
+
+        fs::write(
+            &path,
+            "package\tjq\t1.6\t1.7\n\nfile\t/etc/hosts\nservice\tnginx\t\trunning\n",
+        )?;
+        ensure_eq!(
+            vec![
+                Change::new("package", "jq").before("1.6").after("1.7"),
+                Change::new("file", "/etc/hosts"),
+                Change::new("service", "nginx").after("running"),
+            ],
+            read_changes_file(&path)?
+        );
+        ensure_eq!(
+            "package jq: 1.6 -> 1.7",
+            Change::new("package", "jq")
+                .before("1.6")
+                .after("1.7")
+                .to_string()
+        );
+
+        fs::write(&path, "just-a-kind\n")?;
+        ensure_eq!(true, read_changes_file(&path).is_err());
+        Ok(())
+    }
+}
@@ -64,12 +64,12 @@ use crate::opts::DefaultsReadOptions;
 use crate::opts::DefaultsWriteOptions;
 use crate::tasks::ResolveEnv;
 use crate::tasks::TaskError;
+use crate::tasks::changes::Change;
 use crate::tasks::defaults::DefaultsError as E;
 use crate::tasks::defaults::plist_utils::get_plist_value_type;
 use crate::tasks::defaults::plist_utils::plist_path;
 use crate::tasks::defaults::plist_utils::write_defaults_values;
 use crate::tasks::defaults::ser::replace_data_in_plist;
-use crate::tasks::task::TaskStatus;
 use camino::Utf8Path;
 use camino::Utf8PathBuf;
 use color_eyre::eyre::Context;
@@ -117,11 +117,11 @@ impl ResolveEnv for DefaultsConfig {
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct DefaultsConfig(HashMap<String, HashMap<String, plist::Value>>);
 
-/// Run a defaults run library command.
-pub(crate) fn run(config: DefaultsConfig, up_dir: &Utf8Path) -> Result<TaskStatus> {
+/// Run a defaults run library command, returning the values that changed.
+pub(crate) fn run(config: DefaultsConfig, up_dir: &Utf8Path) -> Result<Vec<Change>> {
     if !(cfg!(target_os = "macos") || cfg!(target_os = "ios")) {
         debug!("Defaults: skipping setting defaults as not on a Darwin platform.");
-        return Ok(TaskStatus::Skipped);
+        return Ok(Vec::new());
     }
 
     debug!("Setting defaults");
@@ -131,13 +131,9 @@ pub(crate) fn run(config: DefaultsConfig, up_dir: &Utf8Path) -> Result<TaskStatu
         .map(|(domain, prefs)| write_defaults_values(&domain, prefs, false, up_dir))
         .partition(Result::is_ok);
     let errors: Vec<_> = errors.into_iter().map(Result::unwrap_err).collect();
-    let passed: Vec<_> = passed.into_iter().map(Result::unwrap).collect();
+    let changes: Vec<_> = passed.into_iter().flat_map(Result::unwrap).collect();
 
-    if passed.iter().all(|r| !r) && errors.is_empty() {
-        return Ok(TaskStatus::Skipped);
-    }
-
-    if passed.into_iter().any(|r| r) {
+    if !changes.is_empty() {
         warn!(
             "Defaults values have been changed, these may not take effect until you restart the \
              system or run `sudo killall cfprefsd`"
@@ -145,7 +141,7 @@ pub(crate) fn run(config: DefaultsConfig, up_dir: &Utf8Path) -> Result<TaskStatu
     }
 
     if errors.is_empty() {
-        Ok(TaskStatus::Passed)
+        Ok(changes)
     } else {
         for error in &errors {
             error!("{error:?}");
@@ -1,6 +1,7 @@
 //! Utility functions for updating plist files.
 use crate::cmd;
 use crate::exec::UpDuct;
+use crate::tasks::changes::Change;
 use crate::tasks::defaults::DefaultsError as E;
 use crate::utils::files;
 use crate::utils::mac;
@@ -153,13 +154,13 @@ pub(super) fn is_binary(file: &Utf8Path) -> Result<bool, E> {
     Ok(&magic == b"bplist00")
 }
 
-/// Write a `HashMap` of key-value pairs to a plist file.
+/// Write a `HashMap` of key-value pairs to a plist file, returning the values that changed.
 pub(super) fn write_defaults_values(
     domain: &str,
     prefs: HashMap<String, plist::Value>,
     current_host: bool,
     up_dir: &Utf8Path,
-) -> Result<bool, E> {
+) -> Result<Vec<Change>, E> {
     let backup_dir = up_dir.join("backup/defaults");
 
     let plist_path = plist_path(domain, current_host)?;
@@ -178,8 +179,8 @@ pub(super) fn write_defaults_values(
 
     trace!("Plist: {plist_value:?}");
 
-    // Whether we changed anything.
-    let mut values_changed = false;
+    // What we changed.
+    let mut changes = Vec::new();
     for (key, mut new_value) in prefs {
         let old_value = plist_value
             .as_dictionary()
@@ -205,9 +206,12 @@ pub(super) fn write_defaults_values(
             continue;
         }
 
-        values_changed = true;
-
         info!("Changing default {domain} {key}: {old_value:?} -> {new_value:?}",);
+        let mut change = Change::new("defaults", format!("{domain} {key}"));
+        if let Some(old_value) = old_value {
+            change = change.before(format!("{old_value:?}"));
+        }
+        changes.push(change.after(format!("{new_value:?}")));
 
         let plist_type = get_plist_value_type(&plist_value);
         trace!("Plist type: {plist_type:?}");
@@ -222,8 +226,8 @@ pub(super) fn write_defaults_values(
             .insert(key, new_value);
     }
 
-    if !values_changed {
-        return Ok(values_changed);
+    if changes.is_empty() {
+        return Ok(changes);
     }
 
     if plist_path_exists {
@@ -258,7 +262,7 @@ pub(super) fn write_defaults_values(
     write_plist(plist_path_exists, &plist_path, plist_value)?;
     trace!("Plist updated at {plist_path}");
 
-    Ok(values_changed)
+    Ok(changes)
 }
 
 /// Write a plist file to a path. Will fall back to trying to use sudo if a normal write fails.
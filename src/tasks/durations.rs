@@ -0,0 +1,162 @@
+//! Remembers how long each task usually takes, so we can estimate how long a run has left and
+//! spot tasks that are taking much longer than normal.
+use crate::tasks::runs::RunResults;
+use crate::tasks::runs::TaskResultStatus;
+use crate::utils::state::StateMap;
+use camino::Utf8Path;
+use color_eyre::eyre::Result;
+use std::time::Duration;
+
+/// Name of the file inside the up temp dir that records previous task durations.
+const DURATIONS_FILE_NAME: &str = "task_durations.json";
+
+/// Number of previous durations to remember for each task.
+const HISTORY_LEN: usize = 5;
+
+/// How long we assume a task will take if it has never passed before.
+const DEFAULT_EXPECTED: Duration = Duration::from_secs(1);
+
+/// How long a task with no history can run before we warn that it's slow.
+const DEFAULT_SLOW_THRESHOLD: Duration = Duration::from_mins(1);
+
+/// The durations of the last few passing runs of each task.
+#[derive(Debug)]
+pub(crate) struct TaskDurations {
+    /// Previous durations, oldest first, keyed by task name.
+    history: StateMap<String, Vec<Duration>>,
+}
+
+impl TaskDurations {
+    /// Load the task durations from the up temp dir, starting afresh if there aren't any.
+    pub(crate) fn load(temp_dir: &Utf8Path) -> Self {
+        Self {
+            history: StateMap::load(temp_dir.join(DURATIONS_FILE_NAME), "task durations"),
+        }
+    }
+
+    /// The usual duration of a task (the median of its recent passing runs), if it has any.
+    pub(crate) fn expected(&self, task_name: &str) -> Option<Duration> {
+        let mut durations = self.history.get(task_name)?.clone();
+        durations.sort_unstable();
+        durations.get(durations.len() / 2).copied()
+    }
+
+    /// How much a task counts towards the overall progress of a run.
+    pub(crate) fn weight(&self, task_name: &str) -> Duration {
+        self.expected(task_name).unwrap_or(DEFAULT_EXPECTED)
+    }
+
+    /// Add the durations of the tasks that passed in `results`, and save them to disk.
+    pub(crate) fn update(&mut self, results: &RunResults) -> Result<()> {
+        for (name, result) in &results.tasks {
+            let (TaskResultStatus::Passed, Some(duration)) = (result.status, result.duration)
+            else {
+                continue;
+            };
+            let mut durations = self.history.get(name).cloned().unwrap_or_default();
+            durations.push(duration);
+            if durations.len() > HISTORY_LEN {
+                durations.drain(..durations.len() - HISTORY_LEN);
+            }
+            self.history.insert(name.clone(), durations);
+        }
+        self.history.save()
+    }
+}
+
+/**
+How long a task can run before we warn that it's taking much longer than usual.
+
+That's twice its usual duration, but at least ten seconds more, so that quick tasks don't warn
+every time they're a bit slow.
+*/
+pub(crate) fn slow_threshold(expected: Option<Duration>) -> Duration {
+    expected.map_or(DEFAULT_SLOW_THRESHOLD, |expected| {
+        (expected * 2).max(expected + Duration::from_secs(10))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TaskDurations;
+    use super::slow_threshold;
+    use crate::tasks::runs::RunResults;
+    use crate::tasks::runs::TaskResult;
+    use crate::tasks::runs::TaskResultStatus;
+    use chrono::Utc;
+    use color_eyre::Result;
+    use std::time::Duration;
+    use testutils::ensure_eq;
+
+    #[test]
+    fn test_expected_duration() -> Result<()> {
+        let temp_dir = testutils::temp_dir("up", testutils::function_path!())?;
+        let mut durations = TaskDurations::load(&temp_dir);
+        ensure_eq!(None, durations.expected("task"));
+        ensure_eq!(Duration::from_secs(1), durations.weight("task"));
+
+        for (secs, status) in [
+            (3, TaskResultStatus::Passed),
+            (100, TaskResultStatus::Passed),
+            (1, TaskResultStatus::Passed),
+            // Failed runs don't count towards the usual duration.
+            (500, TaskResultStatus::Failed),
+        ] {
+            let results = RunResults {
+                start_time: Utc::now(),
+                tasks: [(
+                    "task".to_owned(),
+                    TaskResult {
+                        status,
+                        duration: Some(Duration::from_secs(secs)),
+                        error: None,
+                        changes: Vec::new(),
+                    },
+                )]
+                .into(),
+            };
+            durations.update(&results)?;
+        }
+        // The median ignores the outlier.
+        ensure_eq!(Some(Duration::from_secs(3)), durations.expected("task"));
+        // Durations are saved to disk.
+        ensure_eq!(
+            Some(Duration::from_secs(3)),
+            TaskDurations::load(&temp_dir).expected("task")
+        );
+
+        // Only the last few runs are remembered.
+        for _ in 0..super::HISTORY_LEN {
+            let results = RunResults {
+                start_time: Utc::now(),
+                tasks: [(
+                    "task".to_owned(),
+                    TaskResult {
+                        status: TaskResultStatus::Passed,
+                        duration: Some(Duration::from_secs(7)),
+                        error: None,
+                        changes: Vec::new(),
+                    },
+                )]
+                .into(),
+            };
+            durations.update(&results)?;
+        }
+        ensure_eq!(Some(Duration::from_secs(7)), durations.expected("task"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_slow_threshold() -> Result<()> {
+        ensure_eq!(Duration::from_mins(1), slow_threshold(None));
+        ensure_eq!(
+            Duration::from_secs(11),
+            slow_threshold(Some(Duration::from_secs(1)))
+        );
+        ensure_eq!(
+            Duration::from_mins(10),
+            slow_threshold(Some(Duration::from_mins(5)))
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,272 @@
+/*!
+Run libraries provided by external executables.
+
+If a task's `run_lib` isn't one of the libraries built into up, up looks for an executable called
+`up-lib-<run_lib>`, first in the directory containing the up config, and then in `$PATH`.
+
+# Protocol
+
+The executable is run in the task's temporary directory, with the task's env as its
+environment. up writes a JSON request to its stdin:
+
+```json
+{
+  "protocol_version": 1,
+  "task": "<task name>",
+  "data": <the task's data, with env vars expanded, or null>,
+  "env": {"<name>": "<value>"},
+  "temp_dir": "<the task's temporary directory>",
+  "dry_run": false
+}
+```
+
+If `dry_run` is `true` the library should report what it would change, but not change anything.
+
+The executable should write a JSON response to its stdout:
+
+```json
+{
+  "status": "passed" | "skipped" | "failed",
+  "message": "<optional, explains why the task skipped or failed>",
+  "changes": [
+    {"kind": "<e.g. file>", "target": "<e.g. a path>", "before": "<optional>", "after": "<optional>"}
+  ]
+}
+```
+
+`changes` is optional, and lists each change made (or that would be made in a dry run). Fields up
+doesn't know about are ignored, so libraries can add fields that only newer versions of up use.
+
+Anything written to stderr is saved in the task's output file. A non-zero exit code is treated as
+a failure, even if a response was written.
+*/
+use crate::exec::UpDuct;
+use crate::exec::cmd_log;
+use crate::tasks::ResolveEnv;
+use crate::tasks::TaskError as E;
+use crate::tasks::changes::Change;
+use crate::tasks::run_lib::RunContext;
+use crate::tasks::runs;
+use crate::tasks::task::TaskStatus;
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use color_eyre::eyre::Context;
+use color_eyre::eyre::Result;
+use color_eyre::eyre::bail;
+use color_eyre::eyre::eyre;
+use duct::Expression;
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::env;
+use std::os::unix::fs::PermissionsExt;
+use tracing::Level;
+use tracing::debug;
+use tracing::info;
+
+/// Version of the protocol described in the module docs, sent with every request.
+const PROTOCOL_VERSION: u32 = 1;
+
+/// Prefix of the executable names of external run libraries.
+const LIB_EXECUTABLE_PREFIX: &str = "up-lib-";
+
+/// The request sent to an external library on stdin.
+#[derive(Debug, Serialize)]
+struct Request<'a> {
+    /// Version of the protocol.
+    protocol_version: u32,
+    /// Name of the task being run.
+    task: &'a str,
+    /// The task's `data`, with env vars expanded.
+    data: &'a serde_yaml::Value,
+    /// The task's environment.
+    env: &'a HashMap<String, String>,
+    /// The task's temporary directory.
+    temp_dir: &'a Utf8Path,
+    /// Whether to only report what would change.
+    dry_run: bool,
+}
+
+/// The response read from an external library's stdout.
+#[derive(Debug, Deserialize)]
+struct Response {
+    /// How the task went.
+    status: ResponseStatus,
+    /// Why the task skipped or failed.
+    #[serde(default)]
+    message: Option<String>,
+    /// The changes that were made (or would be made in a dry run).
+    #[serde(default)]
+    changes: Vec<Change>,
+}
+
+/// Status of an external library run.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ResponseStatus {
+    /// Completed successfully.
+    Passed,
+    /// Nothing needed doing.
+    Skipped,
+    /// Completed unsuccessfully.
+    Failed,
+}
+
+impl ResolveEnv for serde_yaml::Value {
+    fn resolve_env<F>(&mut self, env_fn: F) -> Result<(), E>
+    where
+        F: Fn(&str) -> Result<String, E>,
+    {
+        resolve_value(self, &env_fn)
+    }
+}
+
+/// Expand env vars in all the strings in a yaml value (but not in mapping keys).
+fn resolve_value(
+    value: &mut serde_yaml::Value,
+    env_fn: &dyn Fn(&str) -> Result<String, E>,
+) -> Result<(), E> {
+    match value {
+        serde_yaml::Value::String(s) => *s = env_fn(s)?,
+        serde_yaml::Value::Sequence(values) => {
+            for value in values {
+                resolve_value(value, env_fn)?;
+            }
+        }
+        serde_yaml::Value::Mapping(mapping) => {
+            for (_, value) in mapping.iter_mut() {
+                resolve_value(value, env_fn)?;
+            }
+        }
+        serde_yaml::Value::Tagged(tagged) => resolve_value(&mut tagged.value, env_fn)?,
+        serde_yaml::Value::Null | serde_yaml::Value::Bool(_) | serde_yaml::Value::Number(_) => {}
+    }
+    Ok(())
+}
+
+/**
+Find the executable for an external run library, looking in `lib_dirs` and then in `$PATH`.
+
+Returns `None` if no executable was found.
+*/
+pub(crate) fn find_lib(lib: &str, lib_dirs: &[Utf8PathBuf]) -> Option<Utf8PathBuf> {
+    if lib.contains('/') {
+        return None;
+    }
+    let executable_name = format!("{LIB_EXECUTABLE_PREFIX}{lib}");
+    search_dirs(lib_dirs)
+        .map(|dir| dir.join(&executable_name))
+        .find(|path| is_executable(path))
+}
+
+/// The names of the external run libraries in `lib_dirs` and `$PATH`.
+pub(crate) fn lib_names(lib_dirs: &[Utf8PathBuf]) -> BTreeSet<String> {
+    search_dirs(lib_dirs)
+        .filter_map(|dir| dir.read_dir_utf8().ok())
+        .flatten()
+        .filter_map(Result::ok)
+        .filter(|entry| is_executable(entry.path()))
+        .filter_map(|entry| {
+            entry
+                .file_name()
+                .strip_prefix(LIB_EXECUTABLE_PREFIX)
+                .filter(|name| !name.is_empty())
+                .map(ToOwned::to_owned)
+        })
+        .collect()
+}
+
+/// The directories to look for external run libraries in: `lib_dirs`, and then `$PATH`.
+fn search_dirs(lib_dirs: &[Utf8PathBuf]) -> impl Iterator<Item = Utf8PathBuf> {
+    let path_dirs = env::var_os("PATH")
+        .map(|path| env::split_paths(&path).collect::<Vec<_>>())
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|dir| Utf8PathBuf::try_from(dir).ok());
+    lib_dirs.iter().cloned().chain(path_dirs)
+}
+
+/// Whether `path` is an executable file.
+fn is_executable(path: &Utf8Path) -> bool {
+    path.metadata()
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+/// Run an external library executable with the task's (env-resolved) data.
+pub(crate) fn run(
+    executable: &Utf8Path,
+    data: &serde_yaml::Value,
+    context: &RunContext,
+) -> Result<TaskStatus> {
+    let RunContext {
+        task_name,
+        env,
+        task_tempdir,
+        dry_run,
+        ..
+    } = *context;
+    let request = serde_json::to_vec(&Request {
+        protocol_version: PROTOCOL_VERSION,
+        task: task_name,
+        data,
+        env,
+        temp_dir: task_tempdir,
+        dry_run,
+    })?;
+    let output_file = task_tempdir.join(runs::TASK_OUTPUT_FILE_NAME);
+
+    let command = cmd_log(Level::DEBUG, executable.as_std_path(), Vec::<String>::new())
+        .dir(task_tempdir)
+        .full_env(env)
+        .stdin_bytes(request)
+        .unchecked();
+    let command = if context.console {
+        command
+    } else {
+        command.stderr_path(&output_file)
+    };
+    let output = command
+        .run_with(Expression::stdout_capture)
+        .wrap_err_with(|| eyre!("Failed to run external run library {executable}"))?;
+
+    if !output.status.success() {
+        bail!(
+            "External run library {executable} failed with {status}.\n  Output: {output_file}",
+            status = output.status,
+        );
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    debug!("External run library response: {stdout}");
+    let response: Response = serde_json::from_str(&stdout).wrap_err_with(|| {
+        eyre!(
+            "External run library {executable} wrote an invalid response to stdout:\n<<<\n\
+             {stdout}>>>"
+        )
+    })?;
+
+    for change in &response.changes {
+        if dry_run {
+            info!("Would change: {change}");
+        } else {
+            info!("Changed: {change}");
+        }
+    }
+    context.record_changes(response.changes);
+    match response.status {
+        ResponseStatus::Passed => Ok(TaskStatus::Passed),
+        ResponseStatus::Skipped => {
+            if let Some(message) = &response.message {
+                debug!("Skipped: {message}");
+            }
+            Ok(TaskStatus::Skipped)
+        }
+        ResponseStatus::Failed => Err(eyre!(
+            "{message}\n  Output: {output_file}",
+            message = response
+                .message
+                .as_deref()
+                .unwrap_or("External run library reported a failure."),
+        )),
+    }
+}
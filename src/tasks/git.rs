@@ -3,7 +3,7 @@ use self::GitTaskError as E;
 use crate::opts::GitOptions;
 use crate::tasks::ResolveEnv;
 use crate::tasks::TaskError;
-use crate::tasks::task::TaskStatus;
+use crate::tasks::changes::Change;
 use camino::Utf8PathBuf;
 use clap::Parser;
 use color_eyre::eyre::Result;
@@ -52,22 +52,19 @@ const fn prune_default() -> bool {
     false
 }
 
-/// Run the `up git` task.
-pub(crate) fn run(configs: &[GitConfig]) -> Result<TaskStatus> {
-    let (statuses, errors): (Vec<_>, Vec<_>) = configs
-        .par_iter()
-        .map(update::update)
-        .partition_map(|x| match x {
-            Ok(status) => Either::Left(status),
-            Err(e) => Either::Right(e),
-        });
+/// Run the `up git` task, returning what changed.
+pub(crate) fn run(configs: &[GitConfig]) -> Result<Vec<Change>> {
+    let (changes, errors): (Vec<_>, Vec<_>) =
+        configs
+            .par_iter()
+            .map(update::update)
+            .partition_map(|x| match x {
+                Ok(changes) => Either::Left(changes),
+                Err(e) => Either::Right(e),
+            });
 
     if errors.is_empty() {
-        if statuses.iter().all(|s| matches!(s, TaskStatus::Skipped)) {
-            Ok(TaskStatus::Skipped)
-        } else {
-            Ok(TaskStatus::Passed)
-        }
+        Ok(changes.into_iter().flatten().collect())
     } else {
         for error in &errors {
             error!("{error:?}");
@@ -80,7 +80,7 @@ pub(super) fn set_remote_head(
     let remote_name = remote.name().ok_or(E::RemoteNameMissing)?;
     let remote_ref = format!("refs/remotes/{remote_name}/HEAD");
     let short_branch = shorten_branch_ref(default_branch);
-    let remote_head = format!("refs/remotes/{remote_name}/{short_branch}",);
+    let remote_head = format!("refs/remotes/{remote_name}/{short_branch}");
     debug!("Setting remote head for remote {remote_name}: {remote_ref} => {remote_head}",);
     match repo.find_reference(&remote_ref) {
         Ok(reference) => {
@@ -1,6 +1,7 @@
 //! Update a git repo.
 // TODO(gib): Use https://lib.rs/crates/indicatif for progress bars and remove this.
 #![allow(clippy::print_stdout, clippy::unwrap_used)]
+use crate::tasks::changes::Change;
 use crate::tasks::git::GitConfig;
 use crate::tasks::git::GitRemote;
 use crate::tasks::git::branch::calculate_head;
@@ -15,7 +16,6 @@ use crate::tasks::git::fetch::set_remote_head;
 use crate::tasks::git::merge::do_ff_merge;
 use crate::tasks::git::prune::prune_merged_branches;
 use crate::tasks::git::status::warn_for_unpushed_changes;
-use crate::tasks::task::TaskStatus;
 use color_eyre::eyre::Context;
 use color_eyre::eyre::Result;
 use color_eyre::eyre::bail;
@@ -35,37 +35,29 @@ use tracing::trace;
 use tracing::warn;
 use url::Url;
 
-/// Update a git repo.
-pub(crate) fn update(git_config: &GitConfig) -> Result<TaskStatus> {
+/// Update a git repo, returning what changed.
+pub(crate) fn update(git_config: &GitConfig) -> Result<Vec<Change>> {
     let now = Instant::now();
     let _span = tracing::info_span!("git", repo = &git_config.path.as_str()).entered();
-    let result = real_update(git_config)
-        .map(|did_work| {
-            if did_work {
-                TaskStatus::Passed
-            } else {
-                TaskStatus::Skipped
-            }
-        })
-        .wrap_err_with(|| E::GitUpdate {
-            path: git_config.path.clone(),
-        });
+    let result = real_update(git_config).wrap_err_with(|| E::GitUpdate {
+        path: git_config.path.clone(),
+    });
     let elapsed_time = now.elapsed();
     // TODO(gib): configurable logging for long actions.
-    if elapsed_time > Duration::from_secs(60) {
+    if elapsed_time > Duration::from_mins(1) {
         warn!("Git update took {elapsed_time:?}",);
     }
     result
 }
 
-/// Update a git repo, returns `true` if we did any work (or `false` if we skipped).
+/// Update a git repo, returns what changed (nothing if we skipped).
 // TODO(gib): remove more stuff from this function.
 // TODO(gib): Handle the case where a repo update has changed the default
 // branch, e.g. master -> main, and now there's a branch with an upstream
 // pointing to nothing.
 #[allow(clippy::too_many_lines)]
-pub(crate) fn real_update(git_config: &GitConfig) -> Result<bool> {
-    let mut did_work = false;
+pub(crate) fn real_update(git_config: &GitConfig) -> Result<Vec<Change>> {
+    let mut changes = Vec::new();
 
     // Create dir if it doesn't exist.
     let git_path = git_config.path.clone();
@@ -79,7 +71,6 @@ pub(crate) fn real_update(git_config: &GitConfig) -> Result<bool> {
             path: git_path.clone(),
             source: e,
         })?;
-        did_work = true;
     }
 
     // Initialize repo if it doesn't exist.
@@ -88,7 +79,6 @@ pub(crate) fn real_update(git_config: &GitConfig) -> Result<bool> {
         Err(e) => {
             if e.code() == ErrorCode::NotFound {
                 newly_created_repo = true;
-                did_work = true;
                 Repository::init(&git_path)?
             } else {
                 debug!(
@@ -143,7 +133,7 @@ pub(crate) fn real_update(git_config: &GitConfig) -> Result<bool> {
         && git_config.prune
         && prune_merged_branches(&repo, &default_remote_name)?
     {
-        did_work = true;
+        changes.push(Change::new("git_prune", git_path.as_str()).after("pruned merged branches"));
     }
 
     let branch_name: String = if let Some(branch_name) = &git_config.branch {
@@ -157,6 +147,10 @@ pub(crate) fn real_update(git_config: &GitConfig) -> Result<bool> {
 
     if newly_created_repo || needs_checkout(&repo, &branch_name) {
         debug!("Checking out branch: {short_branch}");
+        let previous_branch = repo
+            .head()
+            .ok()
+            .and_then(|head| head.shorthand().map(ToOwned::to_owned));
         checkout_branch(
             &repo,
             &branch_name,
@@ -164,9 +158,20 @@ pub(crate) fn real_update(git_config: &GitConfig) -> Result<bool> {
             &default_remote_name,
             newly_created_repo,
         )?;
-        did_work = true;
+        let change = if newly_created_repo {
+            Change::new("git_clone", git_path.as_str())
+        } else {
+            Change::new("git_checkout", git_path.as_str())
+        };
+        changes.push(Change {
+            before: previous_branch.filter(|_| !newly_created_repo),
+            ..change.after(short_branch)
+        });
     }
 
+    let commit_before_merge = short_commit(&repo, &branch_name);
+    let mut merged = false;
+
     // TODO(gib): use `repo.revparse_ext(&push_revision)?.1` when available.
     // Refs: https://github.com/libgit2/libgit2/issues/5689
     if let Some(push_branch) = get_push_branch(&repo, short_branch, &user_git_config)? {
@@ -179,7 +184,7 @@ pub(crate) fn real_update(git_config: &GitConfig) -> Result<bool> {
             merge_rev: push_revision,
             merge_ref: push_branch_name,
         })? {
-            did_work = true;
+            merged = true;
         }
     } else {
         debug!("Branch doesn't have an @{{push}} branch, checking @{{upstream}} instead.");
@@ -198,7 +203,7 @@ pub(crate) fn real_update(git_config: &GitConfig) -> Result<bool> {
                         merge_ref: upstream_branch_name,
                     }
                 })? {
-                    did_work = true;
+                    merged = true;
                 }
             }
             Err(e) if e.code() == ErrorCode::NotFound => {
@@ -209,11 +214,29 @@ pub(crate) fn real_update(git_config: &GitConfig) -> Result<bool> {
             }
         }
     }
+    if merged {
+        changes.push(Change {
+            before: commit_before_merge,
+            after: short_commit(&repo, &format!("refs/heads/{short_branch}")),
+            ..Change::new("git_merge", git_path.as_str())
+        });
+    }
     drop(default_remote); // Can't mutably use repo while this value is around.
     if !newly_created_repo {
         warn_for_unpushed_changes(&mut repo, &user_git_config)?;
     }
-    Ok(did_work)
+    Ok(changes)
+}
+
+/// The abbreviated ID of the commit `revision` points to, if it exists.
+fn short_commit(repo: &Repository, revision: &str) -> Option<String> {
+    let commit = repo.revparse_single(revision).ok()?.peel_to_commit().ok()?;
+    commit
+        .as_object()
+        .short_id()
+        .ok()?
+        .as_str()
+        .map(ToOwned::to_owned)
 }
 
 /// Set up the specified remote in a git repo.
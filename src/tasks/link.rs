@@ -1,9 +1,18 @@
 #![allow(unused_assignments)] // Rust nightly bug: https://github.com/rust-lang/rust/issues/147648
 //! The link library task.
 use crate::opts::LinkOptions;
+use crate::opts::LinkStrategy;
 use crate::tasks::ResolveEnv;
 use crate::tasks::TaskError;
-use crate::tasks::task::TaskStatus;
+use crate::tasks::changes::Change;
+use crate::tasks::link::backups::BackupFileType;
+use crate::tasks::link::backups::RunBackups;
+use crate::tasks::link::layers::Layer;
+use crate::tasks::link::layers::Owners;
+use crate::tasks::link::manifest::LinkManifest;
+use crate::tasks::link::permissions::Permissions;
+use crate::tasks::link::strategy::CopyHashes;
+use crate::tasks::link::template::Renderer;
 use crate::utils::files;
 use camino::Utf8Path;
 use camino::Utf8PathBuf;
@@ -15,10 +24,20 @@ use color_eyre::eyre::bail;
 use color_eyre::eyre::ensure;
 use color_eyre::eyre::eyre;
 use displaydoc::Display;
+use git2::Repository;
+use git2::Status;
+use git2::StatusOptions;
+use ignore::Match;
+use ignore::gitignore::Gitignore;
+use ignore::gitignore::GitignoreBuilder;
+use std::collections::HashMap;
+use std::collections::HashSet;
 use std::fs;
 use std::io;
 use std::io::ErrorKind;
 use std::os::unix;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
 use thiserror::Error;
 use tracing::debug;
 use tracing::info;
@@ -27,48 +46,111 @@ use tracing::warn;
 use walkdir::DirEntry;
 use walkdir::WalkDir;
 
+pub(crate) mod backups;
+mod fold;
+mod layers;
+mod manifest;
+mod permissions;
+mod strategy;
+mod template;
+pub(crate) mod unlink;
+
+/// File in `from_dir` listing gitignore-style patterns for files not to link.
+pub(crate) const UPIGNORE_FILE_NAME: &str = ".upignore";
+
 impl ResolveEnv for LinkOptions {
     fn resolve_env<F>(&mut self, env_fn: F) -> Result<(), TaskError>
     where
         F: Fn(&str) -> Result<String, TaskError>,
     {
-        self.from_dir = env_fn(&self.from_dir)?;
+        for from_dir in &mut self.from_dirs {
+            *from_dir = env_fn(from_dir)?;
+        }
         self.to_dir = env_fn(&self.to_dir)?;
         Ok(())
     }
 }
 
 /// Symlink everything from `to_dir` (default: ~/code/dotfiles/) into `from_dir`
-/// (default: ~). Anything that would be overwritten is copied into `backup_dir`
-/// (default: `up_dir/backup/link/`).
+/// (default: ~). Anything that would be overwritten is moved into a directory for this run
+/// inside `backup_dir` (default: `up_dir/backup/link/`), see [`backups`].
 ///
 /// Basically you put your dotfiles in ~/code/dotfiles/, in the same structure
 /// they were in relative to ~. Then if you want to edit your .bashrc (for
 /// example) you just edit ~/.bashrc, and as it's a symlink it'll actually edit
 /// ~/code/dotfiles/.bashrc. Then you can add and commit that change in ~/code/
 /// dotfiles.
-pub(crate) fn run(config: LinkOptions, up_dir: &Utf8Path) -> Result<TaskStatus> {
+///
+/// With more than one `from_dir` (layers), files are linked from all of them, and `conflicts`
+/// decides which layer a file more than one of them has is linked from, see [`layers`]. With
+/// `explain`, nothing is linked, we just say which layer a target would be linked from.
+///
+/// Files matching the `ignore` patterns, or the patterns in the `.upignore` file in
+/// `from_dir`, aren't linked. With `tracked_only`, only files tracked by git are linked.
+///
+/// Files are symlinked by default, but can be put in place with another `strategy` (e.g.
+/// copied), for all files or just those matching `strategy_overrides`.
+///
+/// With `fold`, directories whose target doesn't exist yet are linked as a whole rather than file
+/// by file (as are directories containing a `.upfold` marker file), see [`fold`].
+///
+/// Files ending in `.tmpl` are rendered with `env` (and facts about the machine) to the path
+/// without the suffix, see [`template`].
+///
+/// Symlinks made by earlier runs to files that have since been removed from `from_dir` are
+/// pruned, unless `no_prune` is set.
+///
+/// In a dry run nothing is changed, but the changes that would be made (and the files that
+/// were ignored) are still reported.
+#[allow(clippy::too_many_lines)]
+pub(crate) fn run(
+    config: LinkOptions,
+    env: &HashMap<String, String>,
+    up_dir: &Utf8Path,
+) -> Result<Vec<Change>> {
     let now: DateTime<Utc> = Utc::now();
     debug!("UTC time is: {now}");
 
-    let from_dir = Utf8PathBuf::from(config.from_dir);
-    let to_dir = Utf8PathBuf::from(config.to_dir);
-    let backup_dir = up_dir.join("backup/link");
+    let dry_run = config.dry_run;
+    let to_dir_arg = Utf8PathBuf::from(&config.to_dir);
+    let backup_dir = backup_dir(up_dir);
 
-    let from_dir = resolve_directory(from_dir, "From")?;
-    let to_dir = resolve_directory(to_dir, "To")?;
+    ensure!(
+        !config.from_dirs.is_empty(),
+        "No directory to link from was given."
+    );
+    let layers = config
+        .from_dirs
+        .iter()
+        .map(|from_dir| Layer::new(from_dir, &config))
+        .collect::<Result<Vec<_>>>()?;
+    let to_dir = resolve_directory(to_dir_arg.clone(), "To")?;
+    let owners = Owners::new(&layers, config.conflicts)?;
+    if let Some(target) = &config.explain {
+        owners.explain(&layers, &to_dir_arg, &to_dir, target)?;
+        return Ok(Vec::new());
+    }
+    owners.ensure_no_conflicts()?;
+    let mut copies = CopyHashes::load(up_dir);
+    let mut manifest = LinkManifest::load(up_dir);
+    let renderer = Renderer::new(env);
 
     // Create the backup dir if it doesn't exist.
-    if !backup_dir.exists() {
+    if !backup_dir.exists() && !dry_run {
         debug!("Backup dir '{backup_dir}' doesn't exist, creating it.",);
         fs::create_dir_all(&backup_dir).map_err(|e| LinkError::CreateDirError {
             path: backup_dir.clone(),
             source: e,
         })?;
     }
-    let backup_dir = resolve_directory(backup_dir, "Backup")?;
+    let backup_dir = if dry_run && !backup_dir.exists() {
+        backup_dir
+    } else {
+        resolve_directory(backup_dir, "Backup")?
+    };
+
+    let mut backups = RunBackups::new(&backup_dir, now);
 
-    debug!("Linking from {from_dir} to {to_dir} (backup dir {backup_dir}).",);
     debug!(
         "to_dir contents: {:?}",
         fs::read_dir(&to_dir)?
@@ -78,31 +160,180 @@ pub(crate) fn run(config: LinkOptions, up_dir: &Utf8Path) -> Result<TaskStatus>
             .collect::<Result<Vec<_>>>()
     );
 
-    let mut work_done = false;
-    // For each non-directory file in from_dir.
-    for from_path in WalkDir::new(&from_dir)
-        .min_depth(1)
-        .into_iter()
-        .filter_map(Result::ok)
-        .filter(|f| !f.file_type().is_dir())
-    {
-        let rel_path = Utf8Path::from_path(from_path.path())
-            .ok_or_else(|| eyre!("Invalid path {from_path:?}"))?
-            .strip_prefix(&from_dir)?;
-        create_parent_dir(&to_dir, rel_path, &backup_dir)?;
-        if link_path(&from_path, &to_dir, rel_path, &backup_dir)? {
-            work_done = true;
+    let mut changes = Vec::new();
+    let mut ignored = Vec::new();
+    // Folded directories a dry run would unfold. They're left in place, so what's inside them is
+    // treated as not being there yet.
+    let mut dry_run_unfolded: Vec<Utf8PathBuf> = Vec::new();
+    for (index, layer) in layers.iter().enumerate() {
+        let from_dir = &layer.from_dir;
+        debug!("Linking from {from_dir} to {to_dir} (backup dir {backup_dir}).",);
+        let skip_reason = |path: &Path, is_dir: bool| layer.skip_reason(path, is_dir);
+        // For each file in from_dir (skipping ignored files and directories).
+        let mut walker = WalkDir::new(from_dir)
+            .min_depth(1)
+            .into_iter()
+            .filter_entry(|entry| {
+                let is_dir = entry.file_type().is_dir();
+                let Some(reason) = skip_reason(entry.path(), is_dir) else {
+                    return true;
+                };
+                let rel_path = entry
+                    .path()
+                    .strip_prefix(from_dir)
+                    .unwrap_or_else(|_| entry.path());
+                let slash = if is_dir { "/" } else { "" };
+                ignored.push(format!(
+                    "{path}{slash} ({reason})",
+                    path = rel_path.display()
+                ));
+                false
+            });
+        while let Some(entry) = walker.next() {
+            let Ok(from_path) = entry else {
+                continue;
+            };
+            let rel_path = Utf8Path::from_path(from_path.path())
+                .ok_or_else(|| eyre!("Invalid path {from_path:?}"))?
+                .strip_prefix(from_dir)?;
+            let source_path = from_dir.join(rel_path);
+            if from_path.file_type().is_dir() {
+                let to_path = to_dir.join(rel_path);
+                let in_unfolded = dry_run_unfolded.iter().any(|dir| to_path.starts_with(dir));
+                // Link the whole directory if we can (and were asked to), and no other layer links
+                // into it, otherwise link its contents.
+                if (config.fold || fold::is_marked(&source_path))
+                    && owners.owns_dir(rel_path, index)
+                    && let Some(strategy) =
+                        fold::fold_strategy(&source_path, &layer.strategies, &skip_reason)
+                    && (in_unfolded || fold::can_fold(&source_path, &to_path, strategy))
+                {
+                    walker.skip_current_dir();
+                    if !dry_run {
+                        changes.extend(create_parent_dir(
+                            &to_dir,
+                            rel_path,
+                            &mut backups,
+                            &layer.permissions,
+                        )?);
+                    }
+                    let kind = LinkKind::Strategy(strategy);
+                    if in_unfolded {
+                        changes.push(new_link_change(&source_path, &to_path, &kind)?);
+                    } else {
+                        changes.extend(link_path(
+                            &from_path,
+                            &to_dir,
+                            rel_path,
+                            &mut backups,
+                            &kind,
+                            &mut copies,
+                            dry_run,
+                        )?);
+                    }
+                    manifest.insert(&to_path, &source_path);
+                } else if let Some(change) =
+                    fold::unfold(&to_path, rel_path, &layers, &config, &mut manifest, dry_run)?
+                {
+                    if dry_run {
+                        dry_run_unfolded.push(to_path);
+                    }
+                    changes.push(change);
+                }
+                continue;
+            }
+            let rendered_path = template::rendered_path(rel_path);
+            let is_template = rendered_path.is_some();
+            let rel_path = rendered_path.unwrap_or_else(|| rel_path.to_owned());
+            if !owners.is_owner(&rel_path, index) {
+                debug!("Not linking {source_path}, {rel_path} is linked from another layer.");
+                continue;
+            }
+            let kind = if is_template {
+                LinkKind::Template(renderer.render(&source_path)?)
+            } else {
+                LinkKind::Strategy(layer.strategies.strategy(&source_path))
+            };
+            layer.permissions.check_source(&source_path, &rel_path);
+            changes.extend(
+                layer
+                    .permissions
+                    .enforce(&source_path, &rel_path, false, dry_run)?,
+            );
+            if !dry_run {
+                changes.extend(create_parent_dir(
+                    &to_dir,
+                    &rel_path,
+                    &mut backups,
+                    &layer.permissions,
+                )?);
+            }
+            let to_path = to_dir.join(&rel_path);
+            if dry_run_unfolded.iter().any(|dir| to_path.starts_with(dir)) {
+                changes.push(new_link_change(&source_path, &to_path, &kind)?);
+            } else {
+                changes.extend(link_path(
+                    &from_path,
+                    &to_dir,
+                    &rel_path,
+                    &mut backups,
+                    &kind,
+                    &mut copies,
+                    dry_run,
+                )?);
+            }
+            match kind {
+                LinkKind::Strategy(LinkStrategy::Symlink | LinkStrategy::RelativeSymlink) => {
+                    manifest.insert(&to_path, &source_path);
+                }
+                // Copies have their own mode (hard links share the source's).
+                LinkKind::Strategy(LinkStrategy::Copy) | LinkKind::Template(_) => {
+                    changes.extend(
+                        layer
+                            .permissions
+                            .enforce(&to_path, &rel_path, false, dry_run)?,
+                    );
+                }
+                LinkKind::Strategy(LinkStrategy::Hardlink) => {}
+            }
+        }
+        if !config.no_prune {
+            changes.extend(manifest.prune(from_dir, &to_dir, dry_run)?);
+        }
+    }
+
+    for path in ignored {
+        if dry_run {
+            info!("Ignoring {path}");
+        } else {
+            debug!("Ignoring {path}");
         }
     }
 
-    // Remove backup dir if not empty.
-    match fs::remove_dir(&backup_dir) {
-        Err(e) if e.kind() == ErrorKind::NotFound => {
-            trace!("Looks like another link process already cleaned the backup directory.");
+    if dry_run {
+        for change in &changes {
+            info!("Would change {change}");
         }
+        return Ok(changes);
+    }
+    copies.save()?;
+    manifest.save()?;
 
-        Err(e) => warn!("Backup dir {backup_dir} non-empty, check contents: {e:?}"),
-        Ok(()) => (),
+    if backups.is_empty() {
+        // Remove backup dir if empty.
+        match fs::remove_dir(&backup_dir) {
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                trace!("Looks like another link process already cleaned the backup directory.");
+            }
+            Err(e) => trace!("Backup dir {backup_dir} has backups from earlier runs: {e:?}"),
+            Ok(()) => (),
+        }
+    } else {
+        warn!(
+            "Backed up {count} files to {run_dir}, see `up link backups`.",
+            count = backups.len(),
+            run_dir = backups.dir()
+        );
     }
 
     debug!(
@@ -121,11 +352,155 @@ pub(crate) fn run(config: LinkOptions, up_dir: &Utf8Path) -> Result<TaskStatus>
         );
     }
 
-    if work_done {
-        Ok(TaskStatus::Passed)
-    } else {
-        Ok(TaskStatus::Skipped)
+    Ok(changes)
+}
+
+/// Why the file at `path` (in `from_dir`) shouldn't be linked, or `None` if it should be.
+fn ignore_reason(
+    ignore: &Gitignore,
+    tracked: Option<&TrackedFiles>,
+    from_dir: &Utf8Path,
+    path: &Path,
+    is_dir: bool,
+) -> Option<String> {
+    match ignore.matched(path, is_dir) {
+        Match::Ignore(glob) => Some(format!(
+            "matched `{pattern}` in {source}",
+            pattern = glob.original(),
+            source = glob
+                .from()
+                .map_or_else(|| "ignore option".to_owned(), |p| p.display().to_string()),
+        )),
+        Match::None | Match::Whitelist(_) => tracked
+            .filter(|tracked| {
+                !tracked.contains(path.strip_prefix(from_dir).unwrap_or(path), is_dir)
+            })
+            .map(|_| "not tracked by git".to_owned()),
+    }
+}
+
+/// Build the matcher for files in `from_dir` that shouldn't be linked, from the `.upignore` file
+/// (if any) and the `ignore` patterns. The `.upignore` file itself, and `.upfold` markers, are
+/// always ignored.
+fn ignore_matcher(from_dir: &Utf8Path, patterns: &[String]) -> Result<Gitignore> {
+    let mut builder = GitignoreBuilder::new(from_dir);
+    builder.add_line(None, &format!("/{UPIGNORE_FILE_NAME}"))?;
+    builder.add_line(None, fold::FOLD_MARKER_FILE_NAME)?;
+    let upignore_path = from_dir.join(UPIGNORE_FILE_NAME);
+    if upignore_path.exists()
+        && let Some(e) = builder.add(&upignore_path)
+    {
+        return Err(e).wrap_err_with(|| format!("Failed to read ignore file {upignore_path}"));
+    }
+    for pattern in patterns {
+        builder
+            .add_line(None, pattern)
+            .wrap_err_with(|| format!("Invalid link ignore pattern `{pattern}`"))?;
+    }
+    builder
+        .build()
+        .wrap_err_with(|| format!("Failed to build link ignore rules for {from_dir}"))
+}
+
+/// Index entry mode of a gitlink, i.e. a submodule.
+const GITLINK_MODE: u32 = 0o160_000;
+
+/// Files in `from_dir` that are tracked by git, and the directories containing them, relative to
+/// `from_dir`.
+#[derive(Debug, Default)]
+struct TrackedFiles {
+    /// Tracked files (including symlinks).
+    files: HashSet<Utf8PathBuf>,
+    /// Directories containing tracked files.
+    dirs: HashSet<Utf8PathBuf>,
+    /// Submodules, everything in which (apart from their `.git`) counts as tracked, as we don't
+    /// read their indexes.
+    submodules: HashSet<Utf8PathBuf>,
+}
+
+impl TrackedFiles {
+    /// Whether `rel_path` is a tracked file, or a directory containing tracked files.
+    fn contains(&self, rel_path: &Path, is_dir: bool) -> bool {
+        let Some(rel_path) = Utf8Path::from_path(rel_path) else {
+            return false;
+        };
+        if rel_path
+            .ancestors()
+            .any(|path| self.submodules.contains(path))
+        {
+            return rel_path.file_name() != Some(".git");
+        }
+        if is_dir {
+            self.dirs.contains(rel_path)
+        } else {
+            self.files.contains(rel_path)
+        }
+    }
+}
+
+/// Read the files in `from_dir` that are in the index of the git repository containing it.
+/// Warns about tracked files that have uncommitted changes, as they'll be linked as they are.
+/// Submodules are treated as wholly tracked.
+fn tracked_files(from_dir: &Utf8Path) -> Result<TrackedFiles> {
+    let repo = Repository::discover(from_dir).wrap_err_with(|| {
+        format!("Link option tracked_only is set, but {from_dir} isn't in a git repository.")
+    })?;
+    let workdir = repo
+        .workdir()
+        .and_then(Utf8Path::from_path)
+        .ok_or_else(|| eyre!("Git repository for {from_dir} has no valid working directory."))?
+        .canonicalize_utf8()?;
+    let prefix = from_dir.strip_prefix(&workdir)?;
+
+    let mut tracked = TrackedFiles::default();
+    for entry in repo.index()?.iter() {
+        let path = Utf8PathBuf::from(String::from_utf8(entry.path)?);
+        let Ok(rel_path) = path.strip_prefix(prefix) else {
+            continue;
+        };
+        tracked.dirs.extend(
+            rel_path
+                .ancestors()
+                .skip(1)
+                .filter(|dir| !dir.as_str().is_empty())
+                .map(Utf8Path::to_path_buf),
+        );
+        if entry.mode == GITLINK_MODE {
+            tracked.submodules.insert(rel_path.to_path_buf());
+        } else {
+            tracked.files.insert(rel_path.to_path_buf());
+        }
+    }
+    debug!(
+        "Found {files} tracked files and {submodules} submodules in {from_dir} (repo {workdir}).",
+        files = tracked.files.len(),
+        submodules = tracked.submodules.len(),
+    );
+
+    let mut status_options = StatusOptions::new();
+    status_options
+        .include_untracked(false)
+        .include_ignored(false);
+    if !prefix.as_str().is_empty() {
+        status_options.pathspec(prefix.as_str());
     }
+    for entry in repo.statuses(Some(&mut status_options))?.iter() {
+        if entry.status().intersects(
+            Status::INDEX_MODIFIED
+                | Status::INDEX_TYPECHANGE
+                | Status::WT_MODIFIED
+                | Status::WT_TYPECHANGE,
+        ) && let Some(path) = entry.path()
+        {
+            warn!("Tracked file has uncommitted changes, linking it anyway: {workdir}/{path}");
+        }
+    }
+    Ok(tracked)
+}
+
+/// The directory in the up temp dir that files replaced by links are moved to.
+fn backup_dir(up_dir: &Utf8Path) -> Utf8PathBuf {
+    up_dir.join("backup/link")
 }
 
 /// Ensure dir exists, and resolve symlinks to find it's canonical path.
@@ -147,10 +522,21 @@ fn resolve_directory(dir_path: Utf8PathBuf, name: &str) -> Result<Utf8PathBuf> {
     })
 }
 
-/// Create the parent directory to create the symlink in.
-fn create_parent_dir(to_dir: &Utf8Path, rel_path: &Utf8Path, backup_dir: &Utf8Path) -> Result<()> {
+/// Create the parent directory to create the symlink in, setting the mode of any directories
+/// created that have a permission rule. Returns the mode changes made.
+fn create_parent_dir(
+    to_dir: &Utf8Path,
+    rel_path: &Utf8Path,
+    backups: &mut RunBackups,
+    permissions: &Permissions,
+) -> Result<Vec<Change>> {
     let to_path = to_dir.join(rel_path);
     let to_path_parent = get_parent_path(&to_path)?;
+    let missing_dirs: Vec<&Utf8Path> = rel_path
+        .ancestors()
+        .skip(1)
+        .filter(|path| !path.as_str().is_empty() && !to_dir.join(path).is_dir())
+        .collect();
     fs::create_dir_all(to_path_parent).or_else(|_err| {
         info!(
             "Failed to create parent dir, walking up the tree to see if there's a file that needs \
@@ -177,17 +563,7 @@ fn create_parent_dir(to_dir: &Utf8Path, rel_path: &Utf8Path, backup_dir: &Utf8Pa
                      Link: {to_path}",
                 );
                 if abs_path.is_file() {
-                    if let Some(parent_path) = &path.parent() {
-                        info!("Path: {path}, parent: {parent_path}");
-                        if parent_path != &Utf8Path::new("") {
-                            let path = backup_dir.join(parent_path);
-                            fs::create_dir_all(&path)
-                                .map_err(|e| LinkError::CreateDirError { path, source: e })?;
-                        }
-                        let backup_path = backup_dir.join(path);
-                        info!("Moving file to backup: {abs_path} -> {backup_path}",);
-                        fs::rename(&abs_path, backup_path)?;
-                    }
+                    backups.backup(&abs_path, path, BackupFileType::File)?;
                 } else {
                     info!("Removing symlink: {abs_path}");
                     fs::remove_file(abs_path)?;
@@ -198,7 +574,13 @@ fn create_parent_dir(to_dir: &Utf8Path, rel_path: &Utf8Path, backup_dir: &Utf8Pa
         let to_parent_path = get_parent_path(&to_path)?;
         fs::create_dir_all(to_parent_path)
             .wrap_err_with(|| format!("Failed to create parent dir {:?}.", to_path.parent()))
-    })
+    })?;
+    // Shallowest directories first.
+    let mut changes = Vec::new();
+    for path in missing_dirs.into_iter().rev() {
+        changes.extend(permissions.enforce(&to_dir.join(path), path, true, false)?);
+    }
+    Ok(changes)
 }
 
 /// Get the parent directory of a path.
@@ -208,30 +590,60 @@ fn get_parent_path(path: &Utf8Path) -> Result<&Utf8Path> {
     })?)
 }
 
-/// Create a symlink from `from_path` -> `to_path`.
-/// `rel_path` is the relative path within `from_dir`.
-/// Moves any existing files that would be overwritten into `backup_dir`.
-/// Returns a boolean indicating whether any symlinks were created.
+/// Link `from_path` -> `to_path` as `kind` (using a strategy, or rendering a template).
+/// `rel_path` is the relative path within `to_dir`.
+/// Moves any existing files that would be overwritten into this run's `backups`.
+/// Returns the change made, or `None` if the link (or an up-to-date copy) already existed.
+/// In a dry run, returns the change that would be made without changing anything.
 #[allow(clippy::filetype_is_file)]
 fn link_path(
     from_path_direntry: &DirEntry,
     to_dir: &Utf8Path,
     rel_path: &Utf8Path,
-    backup_dir: &Utf8Path,
-) -> Result<bool> {
+    backups: &mut RunBackups,
+    kind: &LinkKind,
+    copies: &mut CopyHashes,
+    dry_run: bool,
+) -> Result<Option<Change>> {
     let to_path = to_dir.join(rel_path);
     let from_path = Utf8Path::from_path(from_path_direntry.path())
         .ok_or_else(|| eyre!("Invalid UTF-8 in path {from_path_direntry:?}"))?;
+    // Rendered templates are managed like copies.
+    let (strategy, rendered) = match kind {
+        LinkKind::Strategy(strategy) => (*strategy, None),
+        LinkKind::Template(rendered) => (LinkStrategy::Copy, Some(rendered.as_str())),
+    };
+    let link_target = link_target(from_path, &to_path, strategy)?;
+    let copy_hash = match (strategy, rendered) {
+        (_, Some(rendered)) => Some(strategy::bytes_hash(rendered.as_bytes())),
+        (LinkStrategy::Copy, None) => Some(strategy::content_hash(from_path)?),
+        (LinkStrategy::Symlink | LinkStrategy::RelativeSymlink | LinkStrategy::Hardlink, None) => {
+            None
+        }
+    };
+    let mut change = new_link_change(from_path, &to_path, kind)?;
     if to_path.exists() {
         let to_path_file_type = to_path.symlink_metadata()?.file_type();
         if to_path_file_type.is_symlink() {
             match to_path.read_link_utf8() {
                 Ok(existing_link) => {
-                    if existing_link == from_path {
+                    let is_symlink_strategy = matches!(
+                        strategy,
+                        LinkStrategy::Symlink | LinkStrategy::RelativeSymlink
+                    );
+                    if is_symlink_strategy && existing_link == link_target {
                         debug!("Link at {to_path} already points to {existing_link}, skipping.",);
-                        return Ok(false);
+                        return Ok(None);
+                    }
+                    if dry_run {
+                        return Ok(Some(change.before(existing_link.as_str())));
                     }
-                    warn!("Link at {to_path} points to {existing_link}, changing to {from_path}.");
+                    warn!(
+                        "Link at {to_path} points to {existing_link}, changing to {kind} of \
+                         {from_path}.",
+                        kind = kind.change_kind()
+                    );
+                    change = change.before(existing_link.as_str());
                     fs::remove_file(&to_path).map_err(|e| LinkError::DeleteError {
                         path: to_path.clone(),
                         source: e,
@@ -242,50 +654,196 @@ fn link_path(
                 }
             }
         } else if to_path_file_type.is_dir() {
-            warn!("Expected file or link at {to_path}, found directory, moving to {backup_dir}",);
-            let backup_path = backup_dir.join(rel_path);
-            fs::create_dir_all(&backup_path).map_err(|e| LinkError::CreateDirError {
-                path: backup_path.clone(),
-                source: e,
-            })?;
-            fs::rename(&to_path, &backup_path).map_err(|e| LinkError::RenameError {
-                from_path: to_path.clone(),
-                to_path: backup_path,
-                source: e,
-            })?;
+            let backup_path = backups.path(rel_path);
+            change = change.before(format!("directory (backed up to {backup_path})"));
+            if dry_run {
+                return Ok(Some(change));
+            }
+            warn!("Expected file or link at {to_path}, found directory, moving to {backup_path}",);
+            backups.backup(&to_path, rel_path, BackupFileType::Directory)?;
         } else if to_path_file_type.is_file() {
-            warn!("Existing file at {to_path}, moving to {backup_dir}");
-            let backup_path = backup_dir.join(rel_path);
-            let backup_parent_path = get_parent_path(&backup_path)?;
-            fs::create_dir_all(backup_parent_path).map_err(|e| LinkError::CreateDirError {
-                path: backup_parent_path.to_path_buf(),
-                source: e,
-            })?;
-            fs::rename(&to_path, &backup_path).map_err(|e| LinkError::RenameError {
-                from_path: to_path.clone(),
-                to_path: backup_path,
-                source: e,
-            })?;
+            match existing_file(strategy, from_path, &to_path, copy_hash.as_deref(), copies)? {
+                ExistingFile::UpToDate => {
+                    debug!(
+                        "{to_path} is already an up-to-date {kind} of {from_path}.",
+                        kind = kind.change_kind()
+                    );
+                    return Ok(None);
+                }
+                ExistingFile::OutdatedCopy => {
+                    change = change.before("outdated copy");
+                    if dry_run {
+                        return Ok(Some(change));
+                    }
+                    fs::remove_file(&to_path).map_err(|e| LinkError::DeleteError {
+                        path: to_path.clone(),
+                        source: e,
+                    })?;
+                }
+                ExistingFile::Other => {
+                    let backup_path = backups.path(rel_path);
+                    change = change.before(format!("file (backed up to {backup_path})"));
+                    if dry_run {
+                        return Ok(Some(change));
+                    }
+                    warn!("Existing file at {to_path}, moving to {backup_path}");
+                    backups.backup(&to_path, rel_path, BackupFileType::File)?;
+                }
+            }
         } else {
             bail!("This should be unreachable.")
         }
     } else if to_path.symlink_metadata().is_ok() {
+        if let Ok(existing_link) = to_path.read_link_utf8() {
+            change = change.before(format!("{existing_link} (broken)"));
+        }
+        if dry_run {
+            return Ok(Some(change));
+        }
         files::remove_broken_symlink(&to_path)?;
     } else {
         trace!("File '{to_path}' doesn't exist.");
+        if dry_run {
+            return Ok(Some(change));
+        }
+    }
+    info!(
+        "Linking ({kind}):\n  From: {from_path}\n  To: {to_path}",
+        kind = kind.change_kind()
+    );
+    let result = match (strategy, rendered) {
+        // Rendered files get the template's mode, as copies do.
+        (_, Some(rendered)) => fs::write(&to_path, rendered)
+            .and_then(|()| fs::set_permissions(&to_path, from_path.metadata()?.permissions())),
+        (LinkStrategy::Symlink | LinkStrategy::RelativeSymlink, None) => {
+            unix::fs::symlink(&link_target, &to_path)
+        }
+        (LinkStrategy::Hardlink, None) => fs::hard_link(from_path, &to_path),
+        (LinkStrategy::Copy, None) => fs::copy(from_path, &to_path).map(|_| ()),
+    };
+    result.map_err(|source| {
+        let (from_path, to_path) = (from_path.to_owned(), to_path.clone());
+        match strategy {
+            LinkStrategy::Symlink | LinkStrategy::RelativeSymlink => LinkError::SymlinkError {
+                from_path,
+                to_path,
+                source,
+            },
+            LinkStrategy::Hardlink => LinkError::HardlinkError {
+                from_path,
+                to_path,
+                source,
+            },
+            LinkStrategy::Copy => LinkError::CopyError {
+                from_path,
+                to_path,
+                source,
+            },
+        }
+    })?;
+    if let Some(hash) = copy_hash {
+        copies.insert(&to_path, hash);
+    }
+    Ok(Some(change))
+}
+
+/// The change putting `from_path` in place at `to_path` makes, if nothing is there yet.
+fn new_link_change(from_path: &Utf8Path, to_path: &Utf8Path, kind: &LinkKind) -> Result<Change> {
+    let link_target = link_target(from_path, to_path, kind.strategy())?;
+    Ok(Change::new(kind.change_kind(), to_path.as_str()).after(link_target.as_str()))
+}
+
+/// What the link (or copy) at `to_path` should point to, to put `from_path` in place with
+/// `strategy`.
+fn link_target(
+    from_path: &Utf8Path,
+    to_path: &Utf8Path,
+    strategy: LinkStrategy,
+) -> Result<Utf8PathBuf> {
+    Ok(match strategy {
+        LinkStrategy::RelativeSymlink => {
+            strategy::relative_path(from_path, get_parent_path(to_path)?)
+        }
+        LinkStrategy::Symlink | LinkStrategy::Hardlink | LinkStrategy::Copy => from_path.to_owned(),
+    })
+}
+
+/// What `link_path` puts at a path in `to_dir`.
+#[derive(Debug)]
+enum LinkKind {
+    /// The file from `from_dir`, put in place with a link strategy.
+    Strategy(LinkStrategy),
+    /// The rendered output of a template in `from_dir`.
+    Template(String),
+}
+
+impl LinkKind {
+    /// The kind of [`Change`] made.
+    const fn change_kind(&self) -> &'static str {
+        match self {
+            Self::Strategy(strategy) => strategy.change_kind(),
+            Self::Template(_) => "template",
+        }
+    }
+
+    /// The strategy used to put the file in place (rendered templates are managed like copies).
+    const fn strategy(&self) -> LinkStrategy {
+        match self {
+            Self::Strategy(strategy) => *strategy,
+            Self::Template(_) => LinkStrategy::Copy,
+        }
     }
-    info!("Linking:\n  From: {from_path}\n  To: {to_path}");
-    unix::fs::symlink(from_path, &to_path)
-        // If we got here, we did work, so return true.
-        .map(|()| true)
-        .map_err(|e| {
-            LinkError::SymlinkError {
-                from_path: from_path.to_owned(),
-                to_path: to_path.clone(),
-                source: e,
+}
+
+/// What the regular file already at a link's path is.
+#[derive(Debug)]
+enum ExistingFile {
+    /// Already what the strategy would create (a hard link to, or in-sync copy of, the source).
+    UpToDate,
+    /// A copy we made that hasn't been edited since, but whose source has changed.
+    OutdatedCopy,
+    /// Something else, which should be backed up before it's replaced.
+    Other,
+}
+
+/// Work out what the regular file at `to_path` is, given the `strategy` for `from_path` (and
+/// its `copy_hash` if it's being copied).
+fn existing_file(
+    strategy: LinkStrategy,
+    from_path: &Utf8Path,
+    to_path: &Utf8Path,
+    copy_hash: Option<&str>,
+    copies: &mut CopyHashes,
+) -> Result<ExistingFile> {
+    match (strategy, copy_hash) {
+        (LinkStrategy::Hardlink, _) => {
+            let from_metadata = from_path.symlink_metadata()?;
+            let to_metadata = to_path.symlink_metadata()?;
+            if from_metadata.dev() == to_metadata.dev() && from_metadata.ino() == to_metadata.ino()
+            {
+                return Ok(ExistingFile::UpToDate);
             }
-            .into()
-        })
+        }
+        (LinkStrategy::Copy, Some(copy_hash)) => {
+            let to_hash = strategy::content_hash(to_path)?;
+            if to_hash == copy_hash {
+                copies.insert(to_path, to_hash);
+                return Ok(ExistingFile::UpToDate);
+            }
+            match copies.get(to_path) {
+                Some(recorded_hash) if recorded_hash == to_hash => {
+                    return Ok(ExistingFile::OutdatedCopy);
+                }
+                Some(_) => warn!(
+                    "Copy at {to_path} has been edited since it was copied from {from_path}, \
+                     backing up the edited copy before replacing it."
+                ),
+                None => (),
+            }
+        }
+        _ => (),
+    }
+    Ok(ExistingFile::Other)
 }
 
 #[derive(Error, Debug, Display)]
@@ -335,7 +893,7 @@ pub enum LinkError {
         /// Source error.
         source: io::Error,
     },
-    /// Failed to symlink from `{from_path}` to `{to_path}`.
+    /// Failed to link from `{from_path}` to `{to_path}`.
     SymlinkError {
         /// Real file we were trying to symlink from.
         from_path: Utf8PathBuf,
@@ -344,6 +902,24 @@ pub enum LinkError {
         /// Source error.
         source: io::Error,
     },
+    /// Failed to hard link from `{from_path}` to `{to_path}`.
+    HardlinkError {
+        /// File we were trying to hard link to.
+        from_path: Utf8PathBuf,
+        /// Hard link we were trying to create.
+        to_path: Utf8PathBuf,
+        /// Source error.
+        source: io::Error,
+    },
+    /// Failed to copy (or render) `{from_path}` to `{to_path}`.
+    CopyError {
+        /// File (or template) we were trying to copy.
+        from_path: Utf8PathBuf,
+        /// Copy we were trying to create.
+        to_path: Utf8PathBuf,
+        /// Source error.
+        source: io::Error,
+    },
     /// Path `{path}` should have a parent directory.
     MissingParentDir {
         /// Path that doesn't have a parent dir.
@@ -0,0 +1,443 @@
+/*!
+Backups of the files `up link` replaces.
+
+Each run of `up link` that replaces something moves it into its own timestamped directory inside
+the link backup directory, along with a manifest recording where each file came from. Backups can
+be listed with `up link backups`, and put back with `up link restore`.
+*/
+use crate::opts::LinkSubcommand;
+use crate::tasks::changes::Change;
+use crate::tasks::link::LinkError;
+use crate::tasks::link::backup_dir;
+use crate::utils::files;
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use chrono::DateTime;
+use chrono::SecondsFormat;
+use chrono::Utc;
+use color_eyre::eyre::Context;
+use color_eyre::eyre::Result;
+use color_eyre::eyre::bail;
+use itertools::Itertools;
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+use std::fs;
+use std::io::ErrorKind;
+use tracing::debug;
+use tracing::info;
+use tracing::warn;
+use walkdir::WalkDir;
+
+/// Name of the manifest file in each run's backup directory.
+const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+/// What sort of thing was backed up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum BackupFileType {
+    /// A regular file.
+    File,
+    /// A directory (and everything in it).
+    Directory,
+}
+
+impl BackupFileType {
+    /// Lowercase name, for messages.
+    const fn as_str(self) -> &'static str {
+        match self {
+            Self::File => "file",
+            Self::Directory => "directory",
+        }
+    }
+}
+
+/// A file or directory `up link` moved out of the way.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(super) struct BackupEntry {
+    /// Where it was.
+    pub(super) original_path: Utf8PathBuf,
+    /// Where it was moved to.
+    pub(super) backup_path: Utf8PathBuf,
+    /// What it was.
+    pub(super) file_type: BackupFileType,
+    /// When it was moved.
+    pub(super) time: DateTime<Utc>,
+}
+
+/// The backups made by one run of `up link`.
+#[derive(Debug)]
+pub(super) struct RunBackups {
+    /// The directory for this run's backups (only created if something is backed up).
+    dir: Utf8PathBuf,
+    /// When this run started.
+    time: DateTime<Utc>,
+    /// Backups made so far.
+    entries: Vec<BackupEntry>,
+}
+
+impl RunBackups {
+    /// Backups for a run that started at `time`, in the link backup directory `backup_root`.
+    pub(super) fn new(backup_root: &Utf8Path, time: DateTime<Utc>) -> Self {
+        Self {
+            dir: backup_root.join(
+                time.to_rfc3339_opts(SecondsFormat::AutoSi, true)
+                    // : is not an allowed filename character in Finder.
+                    .replace(':', "_"),
+            ),
+            time,
+            entries: Vec::new(),
+        }
+    }
+
+    /// The directory for this run's backups.
+    pub(super) fn dir(&self) -> &Utf8Path {
+        &self.dir
+    }
+
+    /// Where the file at `rel_path` (relative to `to_dir`) is backed up to.
+    pub(super) fn path(&self, rel_path: &Utf8Path) -> Utf8PathBuf {
+        self.dir.join(rel_path)
+    }
+
+    /// Move `original_path` (at `rel_path` relative to `to_dir`) into the backup directory.
+    pub(super) fn backup(
+        &mut self,
+        original_path: &Utf8Path,
+        rel_path: &Utf8Path,
+        file_type: BackupFileType,
+    ) -> Result<Utf8PathBuf> {
+        let backup_path = self.path(rel_path);
+        let backup_parent = backup_path
+            .parent()
+            .ok_or_else(|| LinkError::MissingParentDir {
+                path: backup_path.clone(),
+            })?;
+        fs::create_dir_all(backup_parent).map_err(|e| LinkError::CreateDirError {
+            path: backup_parent.to_owned(),
+            source: e,
+        })?;
+        debug!(
+            "Moving {file_type} to backup: {original_path} -> {backup_path}",
+            file_type = file_type.as_str()
+        );
+        fs::rename(original_path, &backup_path).map_err(|e| LinkError::RenameError {
+            from_path: original_path.to_owned(),
+            to_path: backup_path.clone(),
+            source: e,
+        })?;
+        self.entries.push(BackupEntry {
+            original_path: original_path.to_owned(),
+            backup_path: backup_path.clone(),
+            file_type,
+            time: self.time,
+        });
+        // Keep the manifest up to date, so it's still there if linking fails partway through.
+        write_manifest(&self.dir, &self.entries)?;
+        Ok(backup_path)
+    }
+
+    /// Number of files backed up in this run.
+    pub(super) const fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether nothing has been backed up in this run.
+    pub(super) const fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Run the `up link backups` or `up link restore` command.
+pub(crate) fn run_subcommand(
+    cmd: LinkSubcommand,
+    up_dir: &Utf8Path,
+    dry_run: bool,
+) -> Result<Vec<Change>> {
+    match cmd {
+        LinkSubcommand::Backups => {
+            list(up_dir)?;
+            Ok(Vec::new())
+        }
+        LinkSubcommand::Restore(options) => restore(up_dir, &options.target, dry_run),
+    }
+}
+
+/// Print the backups made by each run, newest first.
+fn list(up_dir: &Utf8Path) -> Result<()> {
+    let runs = backup_runs(&backup_dir(up_dir))?;
+    if runs.is_empty() {
+        info!("No link backups found in {}.", backup_dir(up_dir));
+    }
+    for (run_dir, entries) in runs.iter().rev() {
+        println!("{run_id}:", run_id = run_id(run_dir));
+        for entry in entries {
+            println!(
+                "  {original} ({file_type})",
+                original = entry.original_path,
+                file_type = entry.file_type.as_str()
+            );
+        }
+    }
+    Ok(())
+}
+
+/**
+Put back backed-up files.
+
+`target` is either a run ID (or a unique prefix of one), in which case everything backed up in
+that run is restored, or a path, in which case the most recent backup of that path (or of the
+files inside it) is restored.
+*/
+fn restore(up_dir: &Utf8Path, target: &str, dry_run: bool) -> Result<Vec<Change>> {
+    let backup_root = backup_dir(up_dir);
+    let runs = backup_runs(&backup_root)?;
+    let matching_runs = match runs.iter().find(|(run_dir, _)| run_id(run_dir) == target) {
+        Some(run) => vec![run],
+        None => runs
+            .iter()
+            .filter(|(run_dir, _)| run_id(run_dir).starts_with(target))
+            .collect(),
+    };
+    let to_restore: Vec<(&Utf8Path, &BackupEntry)> = match matching_runs.as_slice() {
+        [(run_dir, entries)] => entries
+            .iter()
+            .map(|entry| (run_dir.as_path(), entry))
+            .collect(),
+        [] => {
+            let path = absolute_path(target)?;
+            let mut to_restore: Vec<(&Utf8Path, &BackupEntry)> = Vec::new();
+            // Newest runs first, so we only restore the latest backup of each path.
+            for (run_dir, entries) in runs.iter().rev() {
+                for entry in entries {
+                    if entry.original_path.starts_with(&path)
+                        && !to_restore
+                            .iter()
+                            .any(|(_, restored)| restored.original_path == entry.original_path)
+                    {
+                        to_restore.push((run_dir, entry));
+                    }
+                }
+            }
+            if to_restore.is_empty() {
+                bail!(
+                    "No backups of {path}, and no backup run matching '{target}', available \
+                     runs:\n  {runs}",
+                    runs = runs.iter().map(|(run_dir, _)| run_id(run_dir)).join("\n  "),
+                );
+            }
+            to_restore
+        }
+        _ => bail!(
+            "Run ID '{target}' matches multiple backup runs:\n  {runs}",
+            runs = matching_runs
+                .iter()
+                .map(|(run_dir, _)| run_id(run_dir))
+                .join("\n  "),
+        ),
+    };
+
+    let mut changes = Vec::new();
+    for (run_dir, entry) in to_restore {
+        if let Some(change) = restore_entry(run_dir, entry, dry_run)? {
+            changes.push(change);
+        }
+    }
+    for change in &changes {
+        if dry_run {
+            info!("Would change {change}");
+        } else {
+            info!("Restored {change}");
+        }
+    }
+    if !dry_run && backup_root.exists() {
+        remove_empty_dirs(&backup_root)?;
+    }
+    Ok(changes)
+}
+
+/// The most recent backup of `original_path`, and the run directory it's in.
+pub(super) fn latest(
+    backup_root: &Utf8Path,
+    original_path: &Utf8Path,
+) -> Result<Option<(Utf8PathBuf, BackupEntry)>> {
+    for (run_dir, entries) in backup_runs(backup_root)?.into_iter().rev() {
+        if let Some(entry) = entries
+            .into_iter()
+            .find(|entry| entry.original_path == original_path)
+        {
+            return Ok(Some((run_dir, entry)));
+        }
+    }
+    Ok(None)
+}
+
+/**
+Move a backup back to its original path, and remove it from its run's manifest.
+
+A symlink at the original path (e.g. one `up link` made) is replaced, but anything else there is
+left alone, and the backup isn't restored.
+*/
+pub(super) fn restore_entry(
+    run_dir: &Utf8Path,
+    entry: &BackupEntry,
+    dry_run: bool,
+) -> Result<Option<Change>> {
+    let BackupEntry {
+        original_path,
+        backup_path,
+        ..
+    } = entry;
+    if backup_path.symlink_metadata().is_err() {
+        warn!("Backup {backup_path} of {original_path} no longer exists, skipping.");
+        return Ok(None);
+    }
+    let mut change = Change::new("restore", original_path.as_str())
+        .after(format!("restored from {backup_path}"));
+    if let Ok(metadata) = original_path.symlink_metadata() {
+        if !metadata.file_type().is_symlink() {
+            warn!("Not restoring {backup_path}, as something is already at {original_path}.");
+            return Ok(None);
+        }
+        change = change.before(original_path.read_link_utf8()?.as_str());
+        if !dry_run {
+            fs::remove_file(original_path).map_err(|e| LinkError::DeleteError {
+                path: original_path.clone(),
+                source: e,
+            })?;
+        }
+    }
+    if dry_run {
+        return Ok(Some(change));
+    }
+    if let Some(parent) = original_path.parent() {
+        fs::create_dir_all(parent).map_err(|e| LinkError::CreateDirError {
+            path: parent.to_owned(),
+            source: e,
+        })?;
+    }
+    fs::rename(backup_path, original_path).map_err(|e| LinkError::RenameError {
+        from_path: backup_path.clone(),
+        to_path: original_path.clone(),
+        source: e,
+    })?;
+
+    let entries: Vec<BackupEntry> = read_manifest(run_dir)?
+        .into_iter()
+        .filter(|other| other != entry)
+        .collect();
+    if entries.is_empty() {
+        debug!("Everything in {run_dir} has been restored, removing its manifest.");
+        let manifest_path = run_dir.join(MANIFEST_FILE_NAME);
+        fs::remove_file(&manifest_path).map_err(|e| LinkError::DeleteError {
+            path: manifest_path,
+            source: e,
+        })?;
+    } else {
+        write_manifest(run_dir, &entries)?;
+    }
+    Ok(Some(change))
+}
+
+/// Each run's backup directory and the backups in it, oldest first.
+fn backup_runs(backup_root: &Utf8Path) -> Result<Vec<(Utf8PathBuf, Vec<BackupEntry>)>> {
+    let entries = match backup_root.read_dir_utf8() {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => {
+            return Err(e).wrap_err_with(|| format!("Failed to read backups in {backup_root}"));
+        }
+    };
+    let mut runs = Vec::new();
+    for entry in entries {
+        let run_dir = entry?.into_path();
+        if run_dir.join(MANIFEST_FILE_NAME).is_file() {
+            let entries = read_manifest(&run_dir)?;
+            runs.push((run_dir, entries));
+        }
+    }
+    runs.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+    Ok(runs)
+}
+
+/// Read the manifest in `run_dir`, if there is one.
+fn read_manifest(run_dir: &Utf8Path) -> Result<Vec<BackupEntry>> {
+    let path = run_dir.join(MANIFEST_FILE_NAME);
+    match fs::read_to_string(&path) {
+        Ok(contents) => serde_json::from_str(&contents)
+            .wrap_err_with(|| format!("Failed to parse link backup manifest {path}")),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e).wrap_err_with(|| format!("Failed to read link backup manifest {path}")),
+    }
+}
+
+/// Write the manifest in `run_dir`.
+fn write_manifest(run_dir: &Utf8Path, entries: &[BackupEntry]) -> Result<()> {
+    files::write(
+        run_dir.join(MANIFEST_FILE_NAME),
+        serde_json::to_string_pretty(entries)?,
+    )
+}
+
+/// Remove `dir` and any directories inside it, if they're empty.
+pub(super) fn remove_empty_dirs(dir: &Utf8Path) -> Result<()> {
+    for entry in WalkDir::new(dir)
+        .contents_first(true)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_dir())
+    {
+        if fs::read_dir(entry.path())?.next().is_none() {
+            fs::remove_dir(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+/// The ID of a backup run (the name of its directory).
+fn run_id(run_dir: &Utf8Path) -> &str {
+    run_dir.file_name().unwrap_or(run_dir.as_str())
+}
+
+/// `path` made absolute (relative to the current directory).
+fn absolute_path(path: &str) -> Result<Utf8PathBuf> {
+    let path = Utf8PathBuf::from(shellexpand::tilde(path).into_owned());
+    if path.is_absolute() {
+        return Ok(path);
+    }
+    let current_dir = Utf8PathBuf::try_from(
+        std::env::current_dir().wrap_err("Failed to get the current directory")?,
+    )?;
+    Ok(current_dir.join(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BackupEntry;
+    use super::BackupFileType;
+    use super::RunBackups;
+    use camino::Utf8Path;
+    use chrono::DateTime;
+    use color_eyre::Result;
+    use testutils::ensure_eq;
+
+    #[test]
+    fn test_run_backups_path() -> Result<()> {
+        let time = DateTime::parse_from_rfc3339("2024-04-26T11:22:33Z")?.to_utc();
+        let backups = RunBackups::new(Utf8Path::new("/up/backup/link"), time);
+        ensure_eq!(
+            Utf8Path::new("/up/backup/link/2024-04-26T11_22_33Z/.config/a"),
+            backups.path(Utf8Path::new(".config/a"))
+        );
+        let entry = BackupEntry {
+            original_path: "/home/me/.config/a".into(),
+            backup_path: backups.path(Utf8Path::new(".config/a")),
+            file_type: BackupFileType::File,
+            time,
+        };
+        ensure_eq!(
+            entry,
+            serde_json::from_str::<BackupEntry>(&serde_json::to_string(&entry)?)?
+        );
+        Ok(())
+    }
+}
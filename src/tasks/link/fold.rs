@@ -0,0 +1,240 @@
+/*!
+Directory folding (as in GNU Stow): linking a whole directory rather than each file in it.
+
+With `up link --fold`, a directory in `from_dir` whose target in `to_dir` doesn't exist yet is
+symlinked as a unit, so new files tools create inside it end up in the dotfiles repo. A directory
+containing a `.upfold` marker file is always linked as a unit (anything in the way is backed up).
+
+A directory is only folded if everything inside it would be symlinked anyway (nothing in it is
+ignored, rendered from a template, or put in place with another strategy).
+
+If a folded directory later needs to hold files from somewhere else (e.g. another `from_dir`
+links into it, or folding was turned off), it's unfolded: the directory link is replaced with a
+real directory, and the files in the folded directory are linked into it as they would be without
+folding (so ignored files aren't).
+*/
+use crate::opts::LinkOptions;
+use crate::opts::LinkStrategy;
+use crate::tasks::changes::Change;
+use crate::tasks::link::LinkError;
+use crate::tasks::link::layers::Layer;
+use crate::tasks::link::manifest::LinkManifest;
+use crate::tasks::link::strategy;
+use crate::tasks::link::strategy::StrategyMatcher;
+use crate::tasks::link::template;
+use crate::utils::files;
+use camino::Utf8Path;
+use color_eyre::eyre::Result;
+use color_eyre::eyre::eyre;
+use std::fs;
+use std::os::unix;
+use std::path::Path;
+use tracing::info;
+use tracing::warn;
+use walkdir::WalkDir;
+
+/// Marker file in a directory in `from_dir` that makes `up link` link it as a unit.
+pub(super) const FOLD_MARKER_FILE_NAME: &str = ".upfold";
+
+/// Whether the directory at `from_path` has a marker saying it should always be linked as a unit.
+pub(super) fn is_marked(from_path: &Utf8Path) -> bool {
+    from_path.join(FOLD_MARKER_FILE_NAME).is_file()
+}
+
+/**
+The strategy to link the directory at `from_path` as a unit with, or `None` if it can't be (because
+something inside it wouldn't be symlinked with the same strategy).
+
+`skip_reason` says why a path shouldn't be linked (e.g. because it's ignored), if it shouldn't.
+*/
+pub(super) fn fold_strategy(
+    from_path: &Utf8Path,
+    strategies: &StrategyMatcher,
+    skip_reason: &impl Fn(&Path, bool) -> Option<String>,
+) -> Option<LinkStrategy> {
+    let mut fold_strategy = None;
+    for entry in WalkDir::new(from_path).min_depth(1) {
+        let entry = entry.ok()?;
+        if entry.file_name() == FOLD_MARKER_FILE_NAME {
+            continue;
+        }
+        let is_dir = entry.file_type().is_dir();
+        if skip_reason(entry.path(), is_dir).is_some() {
+            return None;
+        }
+        if is_dir {
+            continue;
+        }
+        let path = Utf8Path::from_path(entry.path())?;
+        if template::rendered_path(path).is_some() {
+            return None;
+        }
+        let strategy = strategies.strategy(path);
+        if !matches!(
+            strategy,
+            LinkStrategy::Symlink | LinkStrategy::RelativeSymlink
+        ) || fold_strategy.is_some_and(|fold_strategy| fold_strategy != strategy)
+        {
+            return None;
+        }
+        fold_strategy = Some(strategy);
+    }
+    fold_strategy.or_else(|| {
+        Some(strategies.strategy(from_path)).filter(|strategy| {
+            matches!(
+                strategy,
+                LinkStrategy::Symlink | LinkStrategy::RelativeSymlink
+            )
+        })
+    })
+}
+
+/**
+Whether the directory at `from_path` can be linked as a unit at `to_path`: if nothing is there yet,
+or it's already linked, or the directory is marked to always be linked as a unit.
+*/
+pub(super) fn can_fold(from_path: &Utf8Path, to_path: &Utf8Path, strategy: LinkStrategy) -> bool {
+    if is_marked(from_path) {
+        return true;
+    }
+    let Ok(target) = to_path.read_link_utf8() else {
+        return to_path.symlink_metadata().is_err();
+    };
+    match strategy {
+        LinkStrategy::RelativeSymlink => to_path
+            .parent()
+            .is_some_and(|parent| target == strategy::relative_path(from_path, parent)),
+        LinkStrategy::Symlink | LinkStrategy::Hardlink | LinkStrategy::Copy => target == from_path,
+    }
+}
+
+/**
+If `to_path` (at `rel_path` in `to_dir`) is a directory `up link` folded (a symlink recorded in the
+`manifest` to a directory), replace it with a real directory.
+
+If the folded directory is in one of the `layers` being linked, the walk of that layer links its
+contents as usual. Otherwise it was folded by an earlier run from another `from_dir`, so its
+contents are linked here with that `from_dir`'s rules (and the rest of `config`).
+
+Returns the change made, or `None` if `to_path` isn't a folded directory. In a dry run nothing is
+changed, but the change that would be made is returned.
+*/
+pub(super) fn unfold(
+    to_path: &Utf8Path,
+    rel_path: &Utf8Path,
+    layers: &[Layer],
+    config: &LinkOptions,
+    manifest: &mut LinkManifest,
+    dry_run: bool,
+) -> Result<Option<Change>> {
+    let (Ok(target), Some(source)) = (to_path.read_link_utf8(), manifest.get(to_path)) else {
+        return Ok(None);
+    };
+    let source = source.to_owned();
+    let parent = to_path
+        .parent()
+        .ok_or_else(|| LinkError::MissingParentDir {
+            path: to_path.to_owned(),
+        })?;
+    if !source.is_dir() || files::normalize(&parent.join(&target)) != source {
+        return Ok(None);
+    }
+    let change = Change::new("unfold", to_path.as_str())
+        .before(target.as_str())
+        .after("directory");
+    if dry_run {
+        return Ok(Some(change));
+    }
+    info!("Unfolding {to_path}, which linked to {source}, into a directory.");
+    fs::remove_file(to_path).map_err(|e| LinkError::DeleteError {
+        path: to_path.to_owned(),
+        source: e,
+    })?;
+    fs::create_dir(to_path).map_err(|e| LinkError::CreateDirError {
+        path: to_path.to_owned(),
+        source: e,
+    })?;
+    manifest.remove(to_path);
+    if layers
+        .iter()
+        .any(|layer| source.starts_with(&layer.from_dir))
+    {
+        return Ok(Some(change));
+    }
+    let from_dir = source
+        .ancestors()
+        .nth(rel_path.components().count())
+        .ok_or_else(|| eyre!("Failed to find the directory {source} was linked from"))?;
+    let layer = Layer::new(from_dir.as_str(), config)?;
+    link_contents(&source, to_path, &layer, config.fold, manifest)?;
+    Ok(Some(change))
+}
+
+/**
+Link what's in the directory `source` (in `layer`) into the (empty) directory `to_path`, as linking
+from `layer` would, folding directories if `fold` is set.
+
+Only links can be made here, so templates and files with another strategy are skipped with a
+warning, to be put in place by linking from the layer's `from_dir`.
+*/
+fn link_contents(
+    source: &Utf8Path,
+    to_path: &Utf8Path,
+    layer: &Layer,
+    fold: bool,
+    manifest: &mut LinkManifest,
+) -> Result<()> {
+    let skip_reason = |path: &Path, is_dir: bool| layer.skip_reason(path, is_dir);
+    let mut walker = WalkDir::new(source)
+        .min_depth(1)
+        .into_iter()
+        .filter_entry(|entry| skip_reason(entry.path(), entry.file_type().is_dir()).is_none());
+    while let Some(entry) = walker.next() {
+        let Ok(entry) = entry else {
+            continue;
+        };
+        let from_path =
+            Utf8Path::from_path(entry.path()).ok_or_else(|| eyre!("Invalid path {entry:?}"))?;
+        let child_to_path = to_path.join(from_path.strip_prefix(source)?);
+        let strategy = if entry.file_type().is_dir() {
+            if let Some(strategy) = (fold || is_marked(from_path))
+                .then(|| fold_strategy(from_path, &layer.strategies, &skip_reason))
+                .flatten()
+            {
+                walker.skip_current_dir();
+                strategy
+            } else {
+                fs::create_dir(&child_to_path).map_err(|e| LinkError::CreateDirError {
+                    path: child_to_path.clone(),
+                    source: e,
+                })?;
+                continue;
+            }
+        } else if template::rendered_path(from_path).is_some() {
+            LinkStrategy::Copy
+        } else {
+            layer.strategies.strategy(from_path)
+        };
+        let link_target = match strategy {
+            LinkStrategy::Symlink => from_path.to_owned(),
+            LinkStrategy::RelativeSymlink => {
+                strategy::relative_path(from_path, child_to_path.parent().unwrap_or(to_path))
+            }
+            LinkStrategy::Hardlink | LinkStrategy::Copy => {
+                warn!(
+                    "Not putting {from_path} in the unfolded {to_path}, as it isn't symlinked, \
+                     link from {from_dir} to put it in place.",
+                    from_dir = layer.from_dir
+                );
+                continue;
+            }
+        };
+        unix::fs::symlink(&link_target, &child_to_path).map_err(|e| LinkError::SymlinkError {
+            from_path: link_target.clone(),
+            to_path: child_to_path.clone(),
+            source: e,
+        })?;
+        manifest.insert(&child_to_path, from_path);
+    }
+    Ok(())
+}
@@ -0,0 +1,277 @@
+/*!
+Linking from more than one `from_dir` (layers), e.g. a shared team dotfiles repo and a personal
+one.
+
+Each layer has its own ignore rules, strategies, and permission rules (as they're relative to its
+`from_dir`). When more than one layer has a file for the same target, the `conflicts` policy
+decides which layer it's linked from (or makes the link fail).
+*/
+use crate::opts::LinkConflicts;
+use crate::opts::LinkOptions;
+use crate::tasks::link::TrackedFiles;
+use crate::tasks::link::ignore_matcher;
+use crate::tasks::link::ignore_reason;
+use crate::tasks::link::permissions::Permissions;
+use crate::tasks::link::resolve_directory;
+use crate::tasks::link::strategy::StrategyMatcher;
+use crate::tasks::link::template;
+use crate::tasks::link::tracked_files;
+use crate::tasks::link::unlink::rel_filter_path;
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use color_eyre::eyre::Result;
+use color_eyre::eyre::bail;
+use color_eyre::eyre::eyre;
+use ignore::gitignore::Gitignore;
+use itertools::Itertools;
+use std::collections::BTreeMap;
+use std::path::Path;
+use walkdir::WalkDir;
+
+impl LinkConflicts {
+    /// The name of the policy, as passed to `--conflicts`.
+    const fn as_str(self) -> &'static str {
+        match self {
+            Self::FirstWins => "first-wins",
+            Self::LastWins => "last-wins",
+            Self::Error => "error",
+        }
+    }
+}
+
+/// A directory to link from, and the rules for linking the files in it.
+#[derive(Debug)]
+pub(super) struct Layer {
+    /// The (resolved) directory to link from.
+    pub(super) from_dir: Utf8PathBuf,
+    /// Files not to link.
+    ignore: Gitignore,
+    /// If only tracked files should be linked, the files tracked by git.
+    tracked: Option<TrackedFiles>,
+    /// The strategy for each file.
+    pub(super) strategies: StrategyMatcher,
+    /// The mode for each file.
+    pub(super) permissions: Permissions,
+}
+
+impl Layer {
+    /// Resolve `from_dir`, and build the rules for linking from it.
+    pub(super) fn new(from_dir: &str, config: &LinkOptions) -> Result<Self> {
+        let from_dir = resolve_directory(Utf8PathBuf::from(from_dir), "From")?;
+        Ok(Self {
+            ignore: ignore_matcher(&from_dir, &config.ignore)?,
+            tracked: if config.tracked_only {
+                Some(tracked_files(&from_dir)?)
+            } else {
+                None
+            },
+            strategies: StrategyMatcher::new(
+                &from_dir,
+                config.strategy,
+                &config.strategy_overrides,
+            )?,
+            permissions: Permissions::new(&from_dir, &config.permissions)?,
+            from_dir,
+        })
+    }
+
+    /// Why the file at `path` (in `from_dir`) shouldn't be linked, or `None` if it should be.
+    pub(super) fn skip_reason(&self, path: &Path, is_dir: bool) -> Option<String> {
+        ignore_reason(
+            &self.ignore,
+            self.tracked.as_ref(),
+            &self.from_dir,
+            path,
+            is_dir,
+        )
+    }
+
+    /// The files this layer would link, as their path relative to `to_dir` (without a template's
+    /// suffix) and their source path. Fails if a file and a template would both be linked to the
+    /// same path.
+    fn targets(&self) -> Result<BTreeMap<Utf8PathBuf, Utf8PathBuf>> {
+        let mut targets = BTreeMap::new();
+        for entry in WalkDir::new(&self.from_dir)
+            .min_depth(1)
+            .into_iter()
+            .filter_entry(|entry| {
+                self.skip_reason(entry.path(), entry.file_type().is_dir())
+                    .is_none()
+            })
+            .filter_map(Result::ok)
+            .filter(|entry| !entry.file_type().is_dir())
+        {
+            let source_path =
+                Utf8Path::from_path(entry.path()).ok_or_else(|| eyre!("Invalid path {entry:?}"))?;
+            let rel_path = source_path.strip_prefix(&self.from_dir)?;
+            let target = template::rendered_path(rel_path).unwrap_or_else(|| rel_path.to_owned());
+            if let Some(other) = targets.get(&target) {
+                bail!(
+                    "Both {other} and {source_path} would be linked to {target}, remove (or \
+                     ignore) one of them."
+                );
+            }
+            targets.insert(target, source_path.to_owned());
+        }
+        Ok(targets)
+    }
+
+    /// Why `rel_path` (relative to `to_dir`) isn't linked from this layer, if this layer has a
+    /// source for it.
+    fn ignored_reason(&self, rel_path: &Utf8Path) -> Option<String> {
+        let source_path = [
+            self.from_dir.join(rel_path),
+            self.from_dir
+                .join(format!("{rel_path}{}", template::TEMPLATE_SUFFIX)),
+        ]
+        .into_iter()
+        .find(|path| path.symlink_metadata().is_ok())?;
+        let rel_source = source_path.strip_prefix(&self.from_dir).ok()?;
+        // The shallowest ignored directory (or the file itself).
+        let reason = rel_source
+            .ancestors()
+            .filter(|path| !path.as_str().is_empty())
+            .collect_vec()
+            .into_iter()
+            .rev()
+            .find_map(|path| {
+                self.skip_reason(self.from_dir.join(path).as_std_path(), path != rel_source)
+            })
+            .unwrap_or_else(|| "ignored".to_owned());
+        Some(format!("{source_path} ({reason})"))
+    }
+}
+
+/// The files each layer would link, and which layer each target is linked from.
+#[derive(Debug)]
+pub(super) struct Owners {
+    /// For each layer, its targets (relative to `to_dir`) and their sources.
+    targets: Vec<BTreeMap<Utf8PathBuf, Utf8PathBuf>>,
+    /// The index of the layer each target is linked from.
+    layer_of: BTreeMap<Utf8PathBuf, usize>,
+    /// The policy used to pick owners.
+    conflicts: LinkConflicts,
+    /// Targets more than one layer has, as `target: first_dir and other_dir` (only recorded if
+    /// the policy is [`LinkConflicts::Error`]).
+    clashes: Vec<String>,
+}
+
+impl Owners {
+    /// Work out which layer each target is linked from. With [`LinkConflicts::Error`] the first
+    /// layer is used, and the conflicts are recorded, see [`Self::ensure_no_conflicts`].
+    pub(super) fn new(layers: &[Layer], conflicts: LinkConflicts) -> Result<Self> {
+        let targets = layers
+            .iter()
+            .map(Layer::targets)
+            .collect::<Result<Vec<_>>>()?;
+        let mut owners = BTreeMap::new();
+        let mut clashes = Vec::new();
+        for (index, layer_targets) in targets.iter().enumerate() {
+            for target in layer_targets.keys() {
+                match owners.get(target) {
+                    None => {
+                        owners.insert(target.clone(), index);
+                    }
+                    Some(&owner) => match conflicts {
+                        LinkConflicts::FirstWins => {}
+                        LinkConflicts::LastWins => {
+                            owners.insert(target.clone(), index);
+                        }
+                        LinkConflicts::Error => clashes.push(format!(
+                            "{target}: {first} and {second}",
+                            first = layers.get(owner).map_or("", |l| l.from_dir.as_str()),
+                            second = layers.get(index).map_or("", |l| l.from_dir.as_str()),
+                        )),
+                    },
+                }
+            }
+        }
+        Ok(Self {
+            targets,
+            layer_of: owners,
+            conflicts,
+            clashes,
+        })
+    }
+
+    /// Fail if more than one layer has the same target, and the policy is
+    /// [`LinkConflicts::Error`].
+    pub(super) fn ensure_no_conflicts(&self) -> Result<()> {
+        if !self.clashes.is_empty() {
+            bail!(
+                "More than one link source has the same files (conflicts: {policy}):\n  {clashes}",
+                policy = self.conflicts.as_str(),
+                clashes = self.clashes.join("\n  "),
+            );
+        }
+        Ok(())
+    }
+
+    /// Whether the target at `rel_path` is linked from the layer at `index`.
+    pub(super) fn is_owner(&self, rel_path: &Utf8Path, index: usize) -> bool {
+        self.layer_of.get(rel_path) == Some(&index)
+    }
+
+    /// Whether every target inside the directory `rel_dir` is linked from the layer at `index`.
+    pub(super) fn owns_dir(&self, rel_dir: &Utf8Path, index: usize) -> bool {
+        self.layer_of
+            .range(rel_dir.to_owned()..)
+            .take_while(|(target, _)| target.starts_with(rel_dir))
+            .all(|(_, owner)| *owner == index)
+    }
+
+    /**
+    Print which layer `target` (absolute, or relative to `to_dir`) is linked from, and why other
+    layers with a source for it aren't used.
+    */
+    pub(super) fn explain(
+        &self,
+        layers: &[Layer],
+        to_dir_arg: &Utf8Path,
+        to_dir: &Utf8Path,
+        target: &Utf8Path,
+    ) -> Result<()> {
+        let rel_path = rel_filter_path(target, to_dir_arg, to_dir)?;
+        let to_path = to_dir.join(&rel_path);
+        let count = layers.len();
+        let owner = self.layer_of.get(&rel_path).copied();
+        match owner.and_then(|index| Some((index, layers.get(index)?))) {
+            Some((index, layer)) => println!(
+                "{to_path} is linked from {source} (layer {number} of {count}: {from_dir}).",
+                source = self
+                    .targets
+                    .get(index)
+                    .and_then(|targets| targets.get(&rel_path))
+                    .map_or("", |source| source.as_str()),
+                number = index + 1,
+                from_dir = layer.from_dir,
+            ),
+            None => println!("{to_path} isn't linked from any of the {count} layers."),
+        }
+        for (index, layer) in layers.iter().enumerate() {
+            if Some(index) == owner {
+                continue;
+            }
+            let number = index + 1;
+            let from_dir = &layer.from_dir;
+            if let Some(source) = self
+                .targets
+                .get(index)
+                .and_then(|targets| targets.get(&rel_path))
+            {
+                let outcome = match self.conflicts {
+                    LinkConflicts::FirstWins | LinkConflicts::LastWins => "not used",
+                    LinkConflicts::Error => "so linking fails",
+                };
+                println!(
+                    "  Layer {number} ({from_dir}) also has {source}, {outcome} (conflicts: \
+                     {policy}).",
+                    policy = self.conflicts.as_str()
+                );
+            } else if let Some(reason) = layer.ignored_reason(&rel_path) {
+                println!("  Layer {number} ({from_dir}) has {reason}.");
+            }
+        }
+        Ok(())
+    }
+}
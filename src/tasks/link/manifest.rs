@@ -0,0 +1,105 @@
+//! The symlinks `up link` has made, so links to dotfiles that have since been removed can be
+//! pruned.
+use crate::tasks::changes::Change;
+use crate::utils::files;
+use crate::utils::state::StateMap;
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use color_eyre::eyre::Result;
+use tracing::debug;
+
+/// Name of the file inside the up temp dir that records the symlinks `up link` has made.
+const MANIFEST_FILE_NAME: &str = "link_manifest.json";
+
+/// The symlinks `up link` has made, and the files in `from_dir` they point to.
+#[derive(Debug)]
+pub(super) struct LinkManifest {
+    /// The source of each symlink, keyed by the symlink's path.
+    links: StateMap<Utf8PathBuf, Utf8PathBuf>,
+}
+
+impl LinkManifest {
+    /// Load the recorded links from the up temp dir, starting afresh if there aren't any.
+    pub(super) fn load(up_dir: &Utf8Path) -> Self {
+        Self {
+            links: StateMap::load(up_dir.join(MANIFEST_FILE_NAME), "link manifest"),
+        }
+    }
+
+    /// Record that `to_path` is a symlink to `from_path`.
+    pub(super) fn insert(&mut self, to_path: &Utf8Path, from_path: &Utf8Path) {
+        self.links.insert(to_path.to_owned(), from_path.to_owned());
+    }
+
+    /// The source of the symlink at `to_path`, if we made it.
+    pub(super) fn get(&self, to_path: &Utf8Path) -> Option<&Utf8Path> {
+        self.links.get(to_path).map(Utf8PathBuf::as_path)
+    }
+
+    /// The recorded symlinks in `to_dir` to files in `from_dir`, and their sources.
+    pub(super) fn links<'a>(
+        &'a self,
+        from_dir: &'a Utf8Path,
+        to_dir: &'a Utf8Path,
+    ) -> impl Iterator<Item = (&'a Utf8Path, &'a Utf8Path)> {
+        self.links
+            .iter()
+            .filter(move |(to_path, from_path)| {
+                to_path.starts_with(to_dir) && from_path.starts_with(from_dir)
+            })
+            .map(|(to_path, from_path)| (to_path.as_path(), from_path.as_path()))
+    }
+
+    /// Forget the symlink at `to_path` (e.g. because it was unlinked).
+    pub(super) fn remove(&mut self, to_path: &Utf8Path) {
+        self.links.remove(to_path);
+    }
+
+    /**
+    Remove the symlinks in `to_dir` we made to files in `from_dir` that no longer exist.
+
+    Only symlinks that still point to the missing file are removed, if something else is there
+    now it's left alone (and forgotten). In a dry run nothing is changed, but the links that would
+    be removed are returned.
+    */
+    pub(super) fn prune(
+        &mut self,
+        from_dir: &Utf8Path,
+        to_dir: &Utf8Path,
+        dry_run: bool,
+    ) -> Result<Vec<Change>> {
+        let stale: Vec<(Utf8PathBuf, Utf8PathBuf)> = self
+            .links(from_dir, to_dir)
+            .filter(|(_, from_path)| from_path.symlink_metadata().is_err())
+            .map(|(to_path, from_path)| (to_path.to_owned(), from_path.to_owned()))
+            .collect();
+        let mut changes = Vec::new();
+        for (to_path, from_path) in stale {
+            self.links.remove(&to_path);
+            if !is_broken_link_to(&to_path, &from_path) {
+                debug!("{to_path} is no longer a link to {from_path}, forgetting it.");
+                continue;
+            }
+            if !dry_run {
+                files::remove_broken_symlink_in(&to_path, from_dir)?;
+            }
+            changes.push(Change::new("symlink", to_path.as_str()).before(from_path.as_str()));
+        }
+        Ok(changes)
+    }
+
+    /// Save the links recorded (or forgotten) since loading.
+    pub(super) fn save(&mut self) -> Result<()> {
+        self.links.save()
+    }
+}
+
+/// Whether `to_path` is a symlink that (still) points to `from_path`, which doesn't exist.
+fn is_broken_link_to(to_path: &Utf8Path, from_path: &Utf8Path) -> bool {
+    !to_path.exists()
+        && to_path.read_link_utf8().is_ok_and(|target| {
+            to_path
+                .parent()
+                .is_some_and(|parent| files::normalize(&parent.join(target)) == from_path)
+        })
+}
@@ -0,0 +1,141 @@
+//! File modes for linked dotfiles: enforcing permission rules, and warning about sensitive files
+//! other users can read.
+//!
+//! Ownership isn't managed, only modes (see `up link --permission`).
+use crate::opts::FileMode;
+use crate::opts::PermissionRule;
+use crate::tasks::changes::Change;
+use crate::tasks::link::LinkError;
+use camino::Utf8Path;
+use color_eyre::eyre::Context;
+use color_eyre::eyre::Result;
+use ignore::gitignore::Gitignore;
+use ignore::gitignore::GitignoreBuilder;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use tracing::info;
+use tracing::warn;
+
+/// Patterns for files that shouldn't be readable by other users, even without a permission rule.
+const SENSITIVE_PATTERNS: &[&str] = &[
+    ".ssh/id_*",
+    "!.ssh/id_*.pub",
+    ".ssh/*_key",
+    ".netrc",
+    ".pgpass",
+    ".gnupg/",
+    ".aws/credentials",
+];
+
+/// Permission bits that let users other than the owner access a file.
+const GROUP_OTHER_BITS: u32 = 0o077;
+
+/// Picks the mode for each file from the permission rules, and sets it.
+#[derive(Debug)]
+pub(super) struct Permissions {
+    /// Rule patterns and their modes, in the order they were given.
+    rules: Vec<(Gitignore, PermissionRule)>,
+    /// Files that should only be accessible by their owner.
+    sensitive: Gitignore,
+}
+
+impl Permissions {
+    /// Build the matcher for files in `from_dir`.
+    pub(super) fn new(from_dir: &Utf8Path, rules: &[PermissionRule]) -> Result<Self> {
+        let rules = rules
+            .iter()
+            .map(|rule| {
+                let mut builder = GitignoreBuilder::new(from_dir);
+                builder
+                    .add_line(None, &rule.glob)
+                    .wrap_err_with(|| format!("Invalid link permission glob `{}`", rule.glob))?;
+                Ok((builder.build()?, rule.clone()))
+            })
+            .collect::<Result<_>>()?;
+        let mut builder = GitignoreBuilder::new(from_dir);
+        for pattern in SENSITIVE_PATTERNS {
+            builder.add_line(None, pattern)?;
+        }
+        Ok(Self {
+            rules,
+            sensitive: builder.build()?,
+        })
+    }
+
+    /// The rule for the path at `rel_path` (relative to `from_dir`), the last matching rule wins.
+    fn rule(&self, rel_path: &Utf8Path, is_dir: bool) -> Option<&PermissionRule> {
+        self.rules
+            .iter()
+            .rev()
+            .find(|(glob, _)| glob.matched(rel_path, is_dir).is_ignore())
+            .map(|(_, rule)| rule)
+    }
+
+    /// Warn if the source file at `path` looks sensitive, but has no rule and other users can
+    /// access it.
+    pub(super) fn check_source(&self, path: &Utf8Path, rel_path: &Utf8Path) {
+        if self.rule(rel_path, false).is_some()
+            || !self
+                .sensitive
+                .matched_path_or_any_parents(rel_path, false)
+                .is_ignore()
+        {
+            return;
+        }
+        if let Ok(metadata) = path.metadata()
+            && metadata.permissions().mode() & GROUP_OTHER_BITS != 0
+        {
+            warn!(
+                "{path} looks sensitive, but other users can access it (mode {mode}), consider \
+                 adding a permission rule, e.g. --permission='{rel_path}=600'",
+                mode = FileMode(metadata.permissions().mode() & 0o7777)
+            );
+        }
+    }
+
+    /**
+    Set the mode of `path` to the one its rule gives `rel_path` (relative to `from_dir`), warning if
+    it was more permissive.
+
+    Returns the change made, or `None` if there's no rule or the mode is already right. In a dry
+    run nothing is changed, but the change that would be made is returned.
+    */
+    pub(super) fn enforce(
+        &self,
+        path: &Utf8Path,
+        rel_path: &Utf8Path,
+        is_dir: bool,
+        dry_run: bool,
+    ) -> Result<Option<Change>> {
+        let Ok(metadata) = path.symlink_metadata() else {
+            return Ok(None);
+        };
+        if metadata.file_type().is_symlink() {
+            return Ok(None);
+        }
+        let current = FileMode(metadata.permissions().mode() & 0o7777);
+        let Some(PermissionRule { glob, mode }) = self.rule(rel_path, is_dir) else {
+            return Ok(None);
+        };
+        if current == *mode {
+            return Ok(None);
+        }
+        let change = Change::new("permissions", path.as_str())
+            .before(current.to_string())
+            .after(mode.to_string());
+        if current.0 & !mode.0 != 0 {
+            warn!("{path} is more permissive (mode {current}) than its rule `{glob}={mode}`.");
+        }
+        if dry_run {
+            return Ok(Some(change));
+        }
+        info!("Changing mode of {path} from {current} to {mode}");
+        fs::set_permissions(path, fs::Permissions::from_mode(mode.0)).map_err(|e| {
+            LinkError::IoError {
+                path: path.to_owned(),
+                source: e,
+            }
+        })?;
+        Ok(Some(change))
+    }
+}
@@ -0,0 +1,152 @@
+//! How `up link` puts each file into `to_dir`: which strategy to use, and keeping copies in sync.
+use crate::opts::LinkStrategy;
+use crate::opts::StrategyOverride;
+use crate::utils::state::StateMap;
+use camino::Utf8Component;
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use color_eyre::eyre::Context;
+use color_eyre::eyre::Result;
+use ignore::gitignore::Gitignore;
+use ignore::gitignore::GitignoreBuilder;
+use ring::digest;
+use std::fs;
+
+/// Name of the file inside the up temp dir that records the content hash of each copy made.
+const COPIES_FILE_NAME: &str = "link_copies.json";
+
+impl LinkStrategy {
+    /// The kind of [`Change`](crate::tasks::changes::Change) made by this strategy.
+    pub(crate) const fn change_kind(self) -> &'static str {
+        match self {
+            Self::Symlink | Self::RelativeSymlink => "symlink",
+            Self::Hardlink => "hardlink",
+            Self::Copy => "copy",
+        }
+    }
+}
+
+/// Picks the strategy for each file, from the default strategy and the per-glob overrides.
+#[derive(Debug)]
+pub(super) struct StrategyMatcher {
+    /// Strategy for files that don't match any override.
+    default: LinkStrategy,
+    /// Override patterns and their strategies, in the order they were given.
+    overrides: Vec<(Gitignore, LinkStrategy)>,
+}
+
+impl StrategyMatcher {
+    /// Build the matcher for files in `from_dir`.
+    pub(super) fn new(
+        from_dir: &Utf8Path,
+        default: LinkStrategy,
+        overrides: &[StrategyOverride],
+    ) -> Result<Self> {
+        let overrides = overrides
+            .iter()
+            .map(|StrategyOverride { glob, strategy }| {
+                let mut builder = GitignoreBuilder::new(from_dir);
+                builder
+                    .add_line(None, glob)
+                    .wrap_err_with(|| format!("Invalid link strategy override glob `{glob}`"))?;
+                Ok((builder.build()?, *strategy))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { default, overrides })
+    }
+
+    /// The strategy for the file at `from_path` (the last matching override wins).
+    pub(super) fn strategy(&self, from_path: &Utf8Path) -> LinkStrategy {
+        self.overrides
+            .iter()
+            .rev()
+            .find(|(glob, _)| glob.matched(from_path, false).is_ignore())
+            .map_or(self.default, |(_, strategy)| *strategy)
+    }
+}
+
+/// The content hash of each copy (or rendered template) `up link` has made, so we can tell
+/// whether a copy that no longer matches its source was edited locally, or just needs updating.
+#[derive(Debug)]
+pub(super) struct CopyHashes {
+    /// Content hash of each copy when it was made, keyed by the copy's path.
+    hashes: StateMap<Utf8PathBuf, String>,
+}
+
+impl CopyHashes {
+    /// Load the recorded copies from the up temp dir, starting afresh if there aren't any.
+    pub(super) fn load(up_dir: &Utf8Path) -> Self {
+        Self {
+            hashes: StateMap::load(up_dir.join(COPIES_FILE_NAME), "link copies"),
+        }
+    }
+
+    /// The hash of the copy at `to_path` when it was made, if we made it.
+    pub(super) fn get(&self, to_path: &Utf8Path) -> Option<&str> {
+        self.hashes.get(to_path).map(String::as_str)
+    }
+
+    /// Record that the copy at `to_path` has content with `hash`.
+    pub(super) fn insert(&mut self, to_path: &Utf8Path, hash: String) {
+        self.hashes.insert(to_path.to_owned(), hash);
+    }
+
+    /// Save the copies recorded since loading.
+    pub(super) fn save(&mut self) -> Result<()> {
+        self.hashes.save()
+    }
+}
+
+/// The SHA-256 hash of the contents of the file at `path`, as hex.
+pub(super) fn content_hash(path: &Utf8Path) -> Result<String> {
+    let contents = fs::read(path).wrap_err_with(|| format!("Failed to read {path}"))?;
+    Ok(bytes_hash(&contents))
+}
+
+/// The SHA-256 hash of `bytes`, as hex.
+pub(super) fn bytes_hash(bytes: &[u8]) -> String {
+    hex::encode(digest::digest(&digest::SHA256, bytes))
+}
+
+/// The path to `path` relative to the directory `base` (both should be absolute).
+pub(super) fn relative_path(path: &Utf8Path, base: &Utf8Path) -> Utf8PathBuf {
+    let path_components: Vec<Utf8Component> = path.components().collect();
+    let base_components: Vec<Utf8Component> = base.components().collect();
+    let common = path_components
+        .iter()
+        .zip(&base_components)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut relative = Utf8PathBuf::new();
+    for _ in base_components.iter().skip(common) {
+        relative.push("..");
+    }
+    for component in path_components.iter().skip(common) {
+        relative.push(component);
+    }
+    relative
+}
+
+#[cfg(test)]
+mod tests {
+    use camino::Utf8Path;
+    use color_eyre::Result;
+    use testutils::ensure_eq;
+
+    #[test]
+    fn test_relative_path() -> Result<()> {
+        let relative = |path: &str, base: &str| {
+            super::relative_path(Utf8Path::new(path), Utf8Path::new(base)).into_string()
+        };
+        ensure_eq!(
+            "../code/dotfiles/.bashrc",
+            relative("/h/code/dotfiles/.bashrc", "/h/.config")
+        );
+        ensure_eq!(
+            "../../code/dotfiles/.config/a/b",
+            relative("/h/code/dotfiles/.config/a/b", "/h/.config/a")
+        );
+        ensure_eq!("dotfiles/file", relative("/h/dotfiles/file", "/h"));
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+/*!
+Render `.tmpl` dotfiles, for files that differ between machines.
+
+Templates use [minijinja](https://docs.rs/minijinja) (Jinja2) syntax, and can use the up env as
+`env` and facts about the machine as `facts`, e.g.:
+
+```text
+[user]
+  email = {{ env.GIT_EMAIL }}
+{% if facts.os == "macos" %}
+[credential]
+  helper = osxkeychain
+{% endif %}
+{% for dir in env.EXTRA_SAFE_DIRS | split(":") %}
+[safe]
+  directory = {{ dir }}
+{% endfor %}
+```
+
+Using an env var that isn't set is an error, use `{% if env.FOO is defined %}` for optional ones.
+*/
+use crate::utils::files;
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use color_eyre::eyre::Context;
+use color_eyre::eyre::Result;
+use minijinja::AutoEscape;
+use minijinja::Environment;
+use minijinja::UndefinedBehavior;
+use serde_derive::Serialize;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+
+/// Suffix of files in `from_dir` that are rendered as templates, rather than linked.
+pub(super) const TEMPLATE_SUFFIX: &str = ".tmpl";
+
+/// Facts about the machine, available to templates as `facts`.
+#[derive(Debug, Serialize)]
+struct Facts {
+    /// Operating system, e.g. `macos` or `linux`.
+    os: &'static str,
+    /// CPU architecture, e.g. `aarch64` or `x86_64`.
+    arch: &'static str,
+    /// Hostname of the machine.
+    hostname: String,
+    /// Name of the current user.
+    user: String,
+    /// Home directory of the current user.
+    home: String,
+}
+
+impl Facts {
+    /// Look up the facts for this machine (facts we can't find are left empty).
+    fn gather() -> Self {
+        Self {
+            os: env::consts::OS,
+            arch: env::consts::ARCH,
+            hostname: nix::unistd::gethostname()
+                .ok()
+                .and_then(|hostname| hostname.into_string().ok())
+                .unwrap_or_default(),
+            user: uzers::get_current_username()
+                .and_then(|user| user.into_string().ok())
+                .unwrap_or_default(),
+            home: files::home_dir()
+                .map(Utf8PathBuf::into_string)
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// What templates are rendered with.
+#[derive(Debug, Serialize)]
+struct TemplateContext<'a> {
+    /// The up env.
+    env: &'a HashMap<String, String>,
+    /// Facts about the machine.
+    facts: Facts,
+}
+
+/// Renders the templates in `from_dir`.
+#[derive(Debug)]
+pub(super) struct Renderer<'a> {
+    /// Template environment, configured for dotfiles.
+    jinja: Environment<'static>,
+    /// Values available to templates.
+    context: TemplateContext<'a>,
+}
+
+impl<'a> Renderer<'a> {
+    /// A renderer for templates that can use `env`.
+    pub(super) fn new(env: &'a HashMap<String, String>) -> Self {
+        let mut jinja = Environment::new();
+        jinja.set_keep_trailing_newline(true);
+        jinja.set_undefined_behavior(UndefinedBehavior::Strict);
+        jinja.set_auto_escape_callback(|_| AutoEscape::None);
+        Self {
+            jinja,
+            context: TemplateContext {
+                env,
+                facts: Facts::gather(),
+            },
+        }
+    }
+
+    /// Render the template at `from_path`.
+    pub(super) fn render(&self, from_path: &Utf8Path) -> Result<String> {
+        let source = fs::read_to_string(from_path)
+            .wrap_err_with(|| format!("Failed to read template {from_path}"))?;
+        self.jinja
+            .render_named_str(from_path.as_str(), &source, &self.context)
+            .wrap_err_with(|| format!("Failed to render template {from_path}"))
+    }
+}
+
+/// The path the template at `rel_path` should be rendered to, or `None` if it isn't a template.
+pub(super) fn rendered_path(rel_path: &Utf8Path) -> Option<Utf8PathBuf> {
+    rel_path
+        .as_str()
+        .strip_suffix(TEMPLATE_SUFFIX)
+        .filter(|path| !path.is_empty() && !path.ends_with('/'))
+        .map(Utf8PathBuf::from)
+}
@@ -0,0 +1,224 @@
+//! Undo `up link`: remove the links it made, and restore the files they replaced.
+use crate::opts::UnlinkOptions;
+use crate::tasks::changes::Change;
+use crate::tasks::link::LinkError;
+use crate::tasks::link::backup_dir;
+use crate::tasks::link::backups;
+use crate::tasks::link::backups::BackupFileType;
+use crate::tasks::link::manifest::LinkManifest;
+use crate::tasks::link::resolve_directory;
+use crate::utils::files;
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use color_eyre::eyre::Result;
+use color_eyre::eyre::eyre;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashSet;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use tracing::debug;
+use tracing::info;
+use walkdir::WalkDir;
+
+/**
+Remove the links in `to_dir` that point into `from_dir` (symlinks, and hard links to files in
+`from_dir`), and move the files `up link` backed up back into place.
+
+Links are found from the files in `from_dir`, and from the link manifest, so symlinks to files
+that have since been removed from `from_dir` are removed too. If `paths` are given, only links at
+(or inside) those paths are removed. In a dry run nothing is changed, but the changes that would
+be made are reported.
+*/
+pub(crate) fn run(options: UnlinkOptions, up_dir: &Utf8Path) -> Result<Vec<Change>> {
+    let UnlinkOptions {
+        from_dir,
+        to_dir,
+        paths,
+        dry_run,
+    } = options;
+    let from_dir = resolve_directory(Utf8PathBuf::from(from_dir), "From")?;
+    let to_dir_arg = Utf8PathBuf::from(to_dir);
+    let to_dir = resolve_directory(to_dir_arg.clone(), "To")?;
+    let backup_dir = backup_dir(up_dir);
+    let mut manifest = LinkManifest::load(up_dir);
+    let filters = paths
+        .iter()
+        .map(|path| rel_filter_path(path, &to_dir_arg, &to_dir))
+        .collect::<Result<Vec<_>>>()?;
+    let included =
+        |rel_path: &Utf8Path| filters.is_empty() || filters.iter().any(|f| rel_path.starts_with(f));
+
+    // The links to remove, keyed by their path relative to to_dir, with their kind and target.
+    let mut links = BTreeMap::new();
+    let mut walker = WalkDir::new(&from_dir).min_depth(1).into_iter();
+    while let Some(entry) = walker.next() {
+        let Ok(entry) = entry else {
+            continue;
+        };
+        let from_path = Utf8Path::from_path(entry.path())
+            .ok_or_else(|| eyre!("Invalid UTF-8 in path {entry:?}"))?;
+        let rel_path = from_path.strip_prefix(&from_dir)?;
+        if !included(rel_path) {
+            continue;
+        }
+        let Some(link) = our_link(&to_dir.join(rel_path), from_path, &from_dir) else {
+            continue;
+        };
+        if entry.file_type().is_dir() {
+            // A directory that was linked as a unit (folded).
+            walker.skip_current_dir();
+        }
+        links.insert(rel_path.to_owned(), link);
+    }
+    // Recorded links to files that are no longer in from_dir (e.g. deleted or renamed dotfiles).
+    let recorded = manifest
+        .links(&from_dir, &to_dir)
+        .map(|(to_path, from_path)| (to_path.to_owned(), from_path.to_owned()))
+        .collect::<Vec<_>>();
+    for (to_path, from_path) in recorded {
+        let rel_path = to_path.strip_prefix(&to_dir)?;
+        if !included(rel_path) || links.contains_key(rel_path) {
+            continue;
+        }
+        if let Some(link) = our_link(&to_path, &from_path, &from_dir) {
+            links.insert(rel_path.to_owned(), link);
+        } else {
+            debug!("{to_path} is no longer a link into {from_dir}, forgetting it.");
+            manifest.remove(&to_path);
+        }
+    }
+
+    let mut changes = Vec::new();
+    // Paths in to_dir that have been (or in a dry run, would be) removed.
+    let mut removed = HashSet::new();
+    // Parent directories of the removed links, which might have replaced backed-up files.
+    let mut parent_dirs = BTreeSet::new();
+    for (rel_path, (kind, target)) in links {
+        let to_path = to_dir.join(&rel_path);
+        let mut change = Change::new(kind, to_path.as_str()).before(target);
+        let backup = backups::latest(&backup_dir, &to_path)?;
+        if let Some((_, entry)) = &backup {
+            change = change.after(format!("restored from {}", entry.backup_path));
+        }
+        if !dry_run {
+            debug!("Removing {kind} {to_path}");
+            fs::remove_file(&to_path).map_err(|e| LinkError::DeleteError {
+                path: to_path.clone(),
+                source: e,
+            })?;
+            manifest.remove(&to_path);
+            if let Some((run_dir, entry)) = &backup {
+                backups::restore_entry(run_dir, entry, dry_run)?;
+            }
+        }
+        if backup.is_none() {
+            removed.insert(to_path.clone());
+        }
+        parent_dirs.extend(
+            rel_path
+                .ancestors()
+                .skip(1)
+                .filter(|dir| !dir.as_str().is_empty())
+                .map(Utf8Path::to_path_buf),
+        );
+        changes.push(change);
+    }
+
+    // Restore backed-up files that were replaced by a directory of links, once the directory is
+    // empty again (deepest directories first, as they might empty their parents).
+    for rel_dir in parent_dirs.iter().rev() {
+        let to_path = to_dir.join(rel_dir);
+        let Some((run_dir, entry)) = backups::latest(&backup_dir, &to_path)? else {
+            continue;
+        };
+        if entry.file_type != BackupFileType::File {
+            continue;
+        }
+        let empty = fs::read_dir(&to_path)?.filter_map(Result::ok).all(|entry| {
+            Utf8PathBuf::from_path_buf(entry.path()).is_ok_and(|p| removed.contains(&p))
+        });
+        if !empty {
+            continue;
+        }
+        if !dry_run {
+            fs::remove_dir(&to_path).map_err(|e| LinkError::DeleteError {
+                path: to_path.clone(),
+                source: e,
+            })?;
+            backups::restore_entry(&run_dir, &entry, dry_run)?;
+        }
+        changes.push(
+            Change::new("directory", to_path.as_str())
+                .before("directory")
+                .after(format!("restored from {}", entry.backup_path)),
+        );
+    }
+
+    for change in &changes {
+        if dry_run {
+            info!("Would change {change}");
+        } else {
+            info!("Unlinked {change}");
+        }
+    }
+    if !dry_run {
+        manifest.save()?;
+        if backup_dir.exists() {
+            backups::remove_empty_dirs(&backup_dir)?;
+        }
+    }
+    Ok(changes)
+}
+
+/**
+If `to_path` is a link `up link` made to `from_path` (a symlink into `from_dir`, even if
+`from_path` no longer exists, or a hard link to `from_path`), returns the kind of link and what it
+points to.
+
+Paths inside a directory that links into `from_dir` (a folded directory) are the files in
+`from_dir` themselves, so they're never treated as links.
+*/
+fn our_link(
+    to_path: &Utf8Path,
+    from_path: &Utf8Path,
+    from_dir: &Utf8Path,
+) -> Option<(&'static str, String)> {
+    if to_path
+        .parent()?
+        .canonicalize_utf8()
+        .ok()?
+        .starts_with(from_dir)
+    {
+        return None;
+    }
+    let metadata = to_path.symlink_metadata().ok()?;
+    if metadata.file_type().is_symlink() {
+        let target = to_path.read_link_utf8().ok()?;
+        let resolved = files::normalize(&to_path.parent()?.join(&target));
+        return resolved
+            .starts_with(from_dir)
+            .then(|| ("symlink", target.into_string()));
+    }
+    let from_metadata = from_path.symlink_metadata().ok()?;
+    (metadata.is_file()
+        && metadata.dev() == from_metadata.dev()
+        && metadata.ino() == from_metadata.ino())
+    .then(|| ("hardlink", from_path.to_string()))
+}
+
+/// Make a path filter relative to `to_dir` (it can be absolute, or already relative).
+pub(super) fn rel_filter_path(
+    path: &Utf8Path,
+    to_dir_arg: &Utf8Path,
+    to_dir: &Utf8Path,
+) -> Result<Utf8PathBuf> {
+    if path.is_relative() {
+        return Ok(files::normalize(path));
+    }
+    let path = files::normalize(path);
+    path.strip_prefix(to_dir)
+        .or_else(|_| path.strip_prefix(to_dir_arg))
+        .map(Utf8Path::to_path_buf)
+        .map_err(|_| eyre!("Path {path} isn't in `to_dir` ({to_dir})."))
+}
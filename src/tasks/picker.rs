@@ -0,0 +1,503 @@
+/*!
+Interactive task picker for `up run --interactive`.
+
+Lists the loaded tasks with their descriptions, tags, and status in the most recent run that
+included them. Typing fuzzy-filters the list, and the preview pane shows the selected task's
+config file and the output it saved last time.
+*/
+use crate::tasks::runs;
+use crate::tasks::runs::TaskResultStatus;
+use crate::tasks::task::Task;
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use color_eyre::eyre::Result;
+use color_eyre::eyre::bail;
+use fuzzy_matcher::FuzzyMatcher;
+use fuzzy_matcher::skim::SkimMatcherV2;
+use itertools::Itertools;
+use ratatui::DefaultTerminal;
+use ratatui::Frame;
+use ratatui::crossterm::event;
+use ratatui::crossterm::event::Event;
+use ratatui::crossterm::event::KeyCode;
+use ratatui::crossterm::event::KeyEvent;
+use ratatui::crossterm::event::KeyEventKind;
+use ratatui::crossterm::event::KeyModifiers;
+use ratatui::layout::Constraint;
+use ratatui::layout::Layout;
+use ratatui::style::Color;
+use ratatui::style::Modifier;
+use ratatui::style::Style;
+use ratatui::style::Stylize;
+use ratatui::text::Line;
+use ratatui::text::Span;
+use ratatui::widgets::Block;
+use ratatui::widgets::List;
+use ratatui::widgets::ListItem;
+use ratatui::widgets::ListState;
+use ratatui::widgets::Paragraph;
+use ratatui::widgets::Wrap;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::io::IsTerminal;
+use tracing::debug;
+
+/// Number of lines of a task's last output to show in the preview.
+const PREVIEW_OUTPUT_LINES: usize = 200;
+
+/// A task as shown in the picker.
+#[derive(Debug)]
+struct PickerItem {
+    /// Task name.
+    name: String,
+    /// Task description, if any.
+    description: Option<String>,
+    /// Task tags.
+    tags: Vec<String>,
+    /// Path to the task config file.
+    path: Utf8PathBuf,
+    /// Status of the task in the most recent run that included it.
+    last_status: Option<TaskResultStatus>,
+    /// Output file from the most recent run that included the task.
+    last_output: Option<Utf8PathBuf>,
+}
+
+impl PickerItem {
+    /// The text the filter query is matched against.
+    fn search_text(&self) -> String {
+        let mut text = self.name.clone();
+        if let Some(description) = &self.description {
+            text.push(' ');
+            text.push_str(description);
+        }
+        for tag in &self.tags {
+            text.push_str(" #");
+            text.push_str(tag);
+        }
+        text
+    }
+}
+
+/// What the user did with the picker.
+#[derive(Debug, PartialEq, Eq)]
+enum Outcome {
+    /// Keep going.
+    Continue,
+    /// Run the picked tasks.
+    Run,
+    /// Run nothing.
+    Cancel,
+}
+
+/// State of the picker.
+struct Picker {
+    /// All the tasks, sorted by name.
+    items: Vec<PickerItem>,
+    /// Current filter query.
+    query: String,
+    /// Indices into `items` of the tasks matching the query, best match first.
+    matches: Vec<usize>,
+    /// Names of the tasks picked so far.
+    picked: BTreeSet<String>,
+    /// Cursor position in `matches`.
+    list_state: ListState,
+    /// Fuzzy matcher for the filter query.
+    matcher: SkimMatcherV2,
+}
+
+/**
+Let the user pick which of the loaded `tasks` to run, returning only the picked tasks.
+
+Returns `None` if the user cancels. Fails if up isn't running in a terminal.
+*/
+pub(crate) fn pick(
+    temp_dir: &Utf8Path,
+    mut tasks: HashMap<String, Task>,
+) -> Result<Option<HashMap<String, Task>>> {
+    if !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+        bail!("`up run --interactive` needs to be run in a terminal.");
+    }
+    let mut picker = Picker::new(items(temp_dir, &tasks)?);
+
+    let mut terminal = ratatui::try_init()?;
+    let result = picker.run(&mut terminal);
+    ratatui::try_restore()?;
+
+    let names = match result? {
+        Outcome::Run => picker.picked_names(),
+        Outcome::Cancel | Outcome::Continue => return Ok(None),
+    };
+    debug!("Picked tasks: {names:?}");
+    tasks.retain(|name, _| names.contains(name));
+    Ok(Some(tasks))
+}
+
+/// Build the picker items for `tasks`, with their status from previous runs.
+fn items(temp_dir: &Utf8Path, tasks: &HashMap<String, Task>) -> Result<Vec<PickerItem>> {
+    // Look back through runs (newest first) until we've found a result for every task.
+    let mut last_results: HashMap<String, (TaskResultStatus, Utf8PathBuf)> = HashMap::new();
+    for run_dir in runs::run_dirs(temp_dir)?.into_iter().rev() {
+        if last_results.len() == tasks.len() {
+            break;
+        }
+        let Ok(results) = runs::read_results(&run_dir.join(runs::RUN_RESULTS_FILE_NAME)) else {
+            continue;
+        };
+        for (name, result) in results.tasks {
+            if tasks.contains_key(&name) && !last_results.contains_key(&name) {
+                let output = runs::task_output_path(&run_dir, &name);
+                last_results.insert(name, (result.status, output));
+            }
+        }
+    }
+
+    Ok(tasks
+        .values()
+        .sorted_by(|a, b| a.name.cmp(&b.name))
+        .map(|task| {
+            let (last_status, last_output) = last_results
+                .remove(&task.name)
+                .map_or((None, None), |(status, output)| {
+                    (Some(status), Some(output))
+                });
+            PickerItem {
+                name: task.name.clone(),
+                description: task.config.description.clone(),
+                tags: task.config.tags.clone().unwrap_or_default(),
+                path: task.path.clone(),
+                last_status,
+                last_output,
+            }
+        })
+        .collect())
+}
+
+impl Picker {
+    /// Create a picker showing `items`, with nothing picked.
+    fn new(items: Vec<PickerItem>) -> Self {
+        let mut picker = Self {
+            items,
+            query: String::new(),
+            matches: Vec::new(),
+            picked: BTreeSet::new(),
+            list_state: ListState::default(),
+            matcher: SkimMatcherV2::default().smart_case(),
+        };
+        picker.update_matches();
+        picker
+    }
+
+    /// Show the picker until the user runs the picked tasks or cancels.
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<Outcome> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            if let Event::Key(key) = event::read()?
+                && key.kind == KeyEventKind::Press
+            {
+                let outcome = self.handle_key(key);
+                if outcome != Outcome::Continue {
+                    return Ok(outcome);
+                }
+            }
+        }
+    }
+
+    /// Update the state for a key press.
+    fn handle_key(&mut self, key: KeyEvent) -> Outcome {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Esc => return Outcome::Cancel,
+            KeyCode::Char('c') if ctrl => return Outcome::Cancel,
+            KeyCode::Enter => return Outcome::Run,
+            KeyCode::Up => self.move_cursor(-1),
+            KeyCode::Char('p') if ctrl => self.move_cursor(-1),
+            KeyCode::Down => self.move_cursor(1),
+            KeyCode::Char('n') if ctrl => self.move_cursor(1),
+            KeyCode::Tab => {
+                self.toggle_current();
+                self.move_cursor(1);
+            }
+            KeyCode::Char('a') if ctrl => self.toggle_all_matches(),
+            KeyCode::Backspace => {
+                self.query.pop();
+                self.update_matches();
+            }
+            KeyCode::Char(c) if !ctrl => {
+                self.query.push(c);
+                self.update_matches();
+            }
+            _ => {}
+        }
+        Outcome::Continue
+    }
+
+    /// Recompute the tasks matching the query, keeping the cursor in range.
+    fn update_matches(&mut self) {
+        let mut scored = self
+            .items
+            .iter()
+            .enumerate()
+            .filter_map(|(i, item)| {
+                if self.query.is_empty() {
+                    return Some((0, i));
+                }
+                self.matcher
+                    .fuzzy_match(&item.search_text(), &self.query)
+                    .map(|score| (score, i))
+            })
+            .collect_vec();
+        // Best score first, ties stay in name order.
+        scored.sort_by(|(a, _), (b, _)| b.cmp(a));
+        self.matches = scored.into_iter().map(|(_, i)| i).collect();
+        self.list_state
+            .select((!self.matches.is_empty()).then_some(0));
+    }
+
+    /// Move the cursor `offset` places, wrapping around at either end.
+    fn move_cursor(&mut self, offset: isize) {
+        if self.matches.is_empty() {
+            return;
+        }
+        let current = self.list_state.selected().unwrap_or(0);
+        let new = current
+            .cast_signed()
+            .saturating_add(offset)
+            .rem_euclid(self.matches.len().cast_signed());
+        self.list_state.select(Some(new.cast_unsigned()));
+    }
+
+    /// The item under the cursor.
+    fn current(&self) -> Option<&PickerItem> {
+        let index = self.matches.get(self.list_state.selected()?)?;
+        self.items.get(*index)
+    }
+
+    /// Pick the task under the cursor, or unpick it if it was already picked.
+    fn toggle_current(&mut self) {
+        if let Some(name) = self.current().map(|item| item.name.clone())
+            && !self.picked.remove(&name)
+        {
+            self.picked.insert(name);
+        }
+    }
+
+    /// Pick all the tasks matching the query, or unpick them if they were all picked.
+    fn toggle_all_matches(&mut self) {
+        let names = self
+            .matches
+            .iter()
+            .filter_map(|i| self.items.get(*i))
+            .map(|item| item.name.clone())
+            .collect_vec();
+        if names.iter().all(|name| self.picked.contains(name)) {
+            for name in &names {
+                self.picked.remove(name);
+            }
+        } else {
+            self.picked.extend(names);
+        }
+    }
+
+    /// The tasks to run: the picked tasks, or the task under the cursor if none were picked.
+    fn picked_names(&self) -> BTreeSet<String> {
+        if self.picked.is_empty() {
+            self.current()
+                .map(|item| item.name.clone())
+                .into_iter()
+                .collect()
+        } else {
+            self.picked.clone()
+        }
+    }
+
+    /// Draw the picker.
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, help] =
+            Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(frame.area());
+        let [left, preview] =
+            Layout::horizontal([Constraint::Percentage(45), Constraint::Percentage(55)])
+                .areas(main);
+        let [query, list] =
+            Layout::vertical([Constraint::Length(3), Constraint::Min(1)]).areas(left);
+
+        frame.render_widget(
+            Paragraph::new(format!("> {}", self.query)).block(Block::bordered().title(format!(
+                " Filter ({}/{}, {} picked) ",
+                self.matches.len(),
+                self.items.len(),
+                self.picked.len()
+            ))),
+            query,
+        );
+
+        let list_items = self
+            .matches
+            .iter()
+            .filter_map(|i| self.items.get(*i))
+            .map(|item| self.list_item(item))
+            .collect_vec();
+        frame.render_stateful_widget(
+            List::new(list_items)
+                .block(Block::bordered().title(" Tasks "))
+                .highlight_style(Style::new().add_modifier(Modifier::REVERSED)),
+            list,
+            &mut self.list_state,
+        );
+
+        let (title, text) = self.current().map_or_else(
+            || (" Preview ".to_owned(), Vec::new()),
+            |item| (format!(" {} ", item.path), preview_lines(item)),
+        );
+        frame.render_widget(
+            Paragraph::new(text)
+                .block(Block::bordered().title(title))
+                .wrap(Wrap { trim: false }),
+            preview,
+        );
+
+        frame.render_widget(
+            Line::from(
+                "Type to filter, ↑/↓ move, Tab pick, Ctrl-a pick all, Enter run, Esc cancel",
+            )
+            .dim(),
+            help,
+        );
+    }
+
+    /// A line in the task list.
+    fn list_item(&self, item: &PickerItem) -> ListItem<'static> {
+        let check = if self.picked.contains(&item.name) {
+            "[x] "
+        } else {
+            "[ ] "
+        };
+        let mut spans = vec![Span::raw(check), Span::raw(item.name.clone()).bold()];
+        if let Some(status) = item.last_status {
+            spans.push(Span::raw(" "));
+            spans.push(Span::styled(
+                status.as_str(),
+                Style::new().fg(status_colour(status)),
+            ));
+        }
+        if let Some(description) = &item.description {
+            spans.push(Span::raw(format!("  {description}")).dim());
+        }
+        for tag in &item.tags {
+            spans.push(Span::raw(format!(" #{tag}")).fg(Color::Cyan));
+        }
+        ListItem::new(Line::from(spans))
+    }
+}
+
+/// The colour to show a task status in.
+const fn status_colour(status: TaskResultStatus) -> Color {
+    match status {
+        TaskResultStatus::Passed => Color::Green,
+        TaskResultStatus::Failed => Color::Red,
+        TaskResultStatus::Skipped => Color::Blue,
+        TaskResultStatus::Pending | TaskResultStatus::Running => Color::Yellow,
+    }
+}
+
+/// The task's config file, followed by the end of its last output.
+fn preview_lines(item: &PickerItem) -> Vec<Line<'static>> {
+    let mut lines = match fs::read_to_string(&item.path) {
+        Ok(config) => config
+            .lines()
+            .map(|l| Line::raw(l.to_owned()))
+            .collect_vec(),
+        Err(e) => vec![Line::raw(format!("Failed to read {}: {e}", item.path)).red()],
+    };
+    lines.push(Line::raw(""));
+    let Some(output_path) = &item.last_output else {
+        lines.push(Line::raw("No previous runs.").dim());
+        return lines;
+    };
+    lines.push(Line::raw(format!("Last output ({output_path}):")).bold());
+    match fs::read(output_path) {
+        Ok(output) => {
+            let output = String::from_utf8_lossy(&output);
+            let output_lines = output.lines().collect_vec();
+            let skip = output_lines.len().saturating_sub(PREVIEW_OUTPUT_LINES);
+            lines.extend(
+                output_lines
+                    .into_iter()
+                    .skip(skip)
+                    .map(|l| Line::raw(l.to_owned())),
+            );
+        }
+        Err(e) => lines.push(Line::raw(format!("No output saved: {e}")).dim()),
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Picker;
+    use super::PickerItem;
+    use color_eyre::Result;
+    use ratatui::crossterm::event::KeyCode;
+    use ratatui::crossterm::event::KeyEvent;
+    use ratatui::crossterm::event::KeyModifiers;
+    use testutils::ensure_eq;
+
+    /// A picker item with no previous runs.
+    fn item(name: &str, description: &str, tags: &[&str]) -> PickerItem {
+        PickerItem {
+            name: name.to_owned(),
+            description: Some(description.to_owned()),
+            tags: tags.iter().map(|&tag| tag.to_owned()).collect(),
+            path: format!("{name}.yaml").into(),
+            last_status: None,
+            last_output: None,
+        }
+    }
+
+    /// Names of the tasks currently matching the query, in order.
+    fn match_names(picker: &Picker) -> Vec<&str> {
+        picker
+            .matches
+            .iter()
+            .filter_map(|i| picker.items.get(*i))
+            .map(|item| item.name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_filter_and_pick() -> Result<()> {
+        let mut picker = Picker::new(vec![
+            item("brew", "Install Homebrew packages", &["macos"]),
+            item("link", "Link dotfiles", &["dotfiles"]),
+            item("rust", "Update Rust toolchains", &[]),
+        ]);
+        ensure_eq!(vec!["brew", "link", "rust"], match_names(&picker));
+
+        // Nothing picked, so Enter runs the task under the cursor.
+        ensure_eq!(
+            vec!["brew"],
+            picker.picked_names().into_iter().collect::<Vec<_>>()
+        );
+
+        // Descriptions and tags are searched too.
+        for c in "dotf".chars() {
+            picker.handle_key(KeyEvent::from(KeyCode::Char(c)));
+        }
+        ensure_eq!(vec!["link"], match_names(&picker));
+        picker.handle_key(KeyEvent::from(KeyCode::Tab));
+
+        picker.query.clear();
+        picker.update_matches();
+        picker.handle_key(KeyEvent::from(KeyCode::Char('#')));
+        picker.handle_key(KeyEvent::from(KeyCode::Char('m')));
+        ensure_eq!(vec!["brew"], match_names(&picker));
+        picker.handle_key(KeyEvent::new(KeyCode::Char('a'), KeyModifiers::CONTROL));
+
+        ensure_eq!(
+            vec!["brew", "link"],
+            picker.picked_names().into_iter().collect::<Vec<_>>()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,386 @@
+/*!
+Run libraries, the code that runs a task with a `run_lib` set.
+
+Libraries implement [`RunLib`], and are looked up by name in a [`RunLibs`] registry. The
+registry starts out with the libraries built into up, and programs embedding up can register
+their own with [`RunLibs::register`] (or [`crate::runner::Runner::register_lib`]).
+
+If no registered library matches, up falls back to an external `up-lib-<name>` executable.
+*/
+use crate::generate;
+use crate::opts::GenerateGitConfig;
+use crate::opts::LinkOptions;
+use crate::opts::UnlinkOptions;
+use crate::opts::UpdateSelfOptions;
+use crate::tasks;
+use crate::tasks::ResolveEnv;
+use crate::tasks::TaskError as E;
+use crate::tasks::changes::Change;
+use crate::tasks::changes::ChangeRecorder;
+use crate::tasks::defaults::DefaultsConfig;
+use crate::tasks::git::GitConfig;
+use crate::tasks::task::RunSettings;
+use crate::tasks::task::TaskStatus;
+use camino::Utf8Path;
+use color_eyre::eyre::Result;
+use serde::de::DeserializeOwned;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::fmt;
+use tracing::info;
+
+/**
+A library that tasks can use by setting `run_lib: <name>`.
+
+The task's `data:` block is deserialized into [`RunLib::Data`], has its env vars expanded with
+[`ResolveEnv`], and is then passed to [`RunLib::run`]. Libraries report what they changed with
+[`RunContext::record_changes`].
+
+# Example
+
+```
+use color_eyre::eyre::Result;
+use serde_derive::Deserialize;
+use up::tasks::ResolveEnv;
+use up::tasks::changes::Change;
+use up::tasks::run_lib::RunContext;
+use up::tasks::run_lib::RunLib;
+use up::tasks::task::TaskStatus;
+
+#[derive(Default, Deserialize)]
+struct HelloData {
+    name: String,
+}
+
+impl ResolveEnv for HelloData {}
+
+struct Hello;
+
+impl RunLib for Hello {
+    type Data = HelloData;
+
+    fn name(&self) -> &str {
+        "hello"
+    }
+
+    fn run(&self, data: HelloData, context: &RunContext) -> Result<TaskStatus> {
+        if context.dry_run {
+            return Ok(TaskStatus::Skipped);
+        }
+        println!("Hello {}", data.name);
+        context.record_changes([Change::new("greeting", data.name)]);
+        Ok(TaskStatus::Passed)
+    }
+}
+```
+*/
+pub trait RunLib: Send + Sync {
+    /// The type the task's `data:` block is deserialized into.
+    type Data: ResolveEnv + Default + DeserializeOwned;
+
+    /// The name tasks use to select this library, i.e. the value of `run_lib`.
+    fn name(&self) -> &str;
+
+    /// Whether tasks using this library must have a `data:` block. If not, tasks without one
+    /// get `Data::default()`.
+    fn requires_data(&self) -> bool {
+        true
+    }
+
+    /**
+    Whether this library handles dry runs itself (by checking [`RunContext::dry_run`]).
+
+    If not, tasks using it are skipped in dry runs.
+    */
+    fn supports_dry_run(&self) -> bool {
+        false
+    }
+
+    /**
+    Run a task, returning its status.
+
+    Return [`TaskStatus::Passed`] if the task did something, [`TaskStatus::Skipped`] if there
+    was nothing to do, and an error if it failed.
+    */
+    fn run(&self, data: Self::Data, context: &RunContext) -> Result<TaskStatus>;
+}
+
+/// What a [`RunLib`] gets to know about the task it is running.
+#[derive(Debug)]
+pub struct RunContext<'a> {
+    /// Name of the task being run.
+    pub task_name: &'a str,
+    /// The task's environment, as resolved from the up config.
+    pub env: &'a HashMap<String, String>,
+    /// The task's temporary directory, which is also where its output file goes.
+    pub task_tempdir: &'a Utf8Path,
+    /// Whether task stdout/stderr should inherit from up's stdout/stderr.
+    pub console: bool,
+    /// Only report what would be changed.
+    pub dry_run: bool,
+    /// The up temp dir, for state kept between runs (unlike `task_tempdir`, which is per-run).
+    pub up_dir: &'a Utf8Path,
+    /// Where changes made by the task are recorded.
+    changes: &'a ChangeRecorder,
+}
+
+impl<'a> RunContext<'a> {
+    /// The context for a task run with `settings`.
+    pub(crate) fn new(
+        task_name: &'a str,
+        env: &'a HashMap<String, String>,
+        task_tempdir: &'a Utf8Path,
+        settings: &'a RunSettings,
+        changes: &'a ChangeRecorder,
+    ) -> Self {
+        Self {
+            task_name,
+            env,
+            task_tempdir,
+            console: settings.console,
+            dry_run: settings.dry_run,
+            up_dir: &settings.up_dir,
+            changes,
+        }
+    }
+
+    /// Record things the task changed (or in a dry run, would have changed).
+    pub fn record_changes(&self, changes: impl IntoIterator<Item = Change>) {
+        self.changes.record(changes);
+    }
+
+    /// Record the changes a built-in library made. It passed if it changed anything, and
+    /// skipped otherwise.
+    fn finish_with(&self, changes: Vec<Change>) -> TaskStatus {
+        if changes.is_empty() {
+            return TaskStatus::Skipped;
+        }
+        self.record_changes(changes);
+        TaskStatus::Passed
+    }
+}
+
+/// Object-safe version of [`RunLib`], so libraries with different data types can share a
+/// registry.
+trait DynRunLib: Send + Sync {
+    /// See [`RunLib::supports_dry_run`].
+    fn supports_dry_run(&self) -> bool;
+
+    /// Parse the task's data and run the library with it.
+    fn run_data(
+        &self,
+        data: Option<serde_yaml::Value>,
+        env_fn: &dyn Fn(&str) -> Result<String, E>,
+        context: &RunContext,
+    ) -> Result<TaskStatus, E>;
+}
+
+impl<L: RunLib> DynRunLib for L {
+    fn supports_dry_run(&self) -> bool {
+        RunLib::supports_dry_run(self)
+    }
+
+    fn run_data(
+        &self,
+        data: Option<serde_yaml::Value>,
+        env_fn: &dyn Fn(&str) -> Result<String, E>,
+        context: &RunContext,
+    ) -> Result<TaskStatus, E> {
+        let data: L::Data =
+            parse_task_config(data, context.task_name, !self.requires_data(), env_fn)?;
+        self.run(data, context).map_err(|e| E::TaskError {
+            name: context.task_name.to_owned(),
+            lib: self.name().to_owned(),
+            source: e,
+        })
+    }
+}
+
+/// The run libraries available to tasks, keyed by name.
+pub struct RunLibs {
+    /// Registered libraries.
+    libs: BTreeMap<String, Box<dyn DynRunLib>>,
+}
+
+impl RunLibs {
+    /// A registry containing only the libraries built into up.
+    #[must_use]
+    pub fn builtin() -> Self {
+        let mut libs = Self {
+            libs: BTreeMap::new(),
+        };
+        libs.register(DefaultsLib);
+        libs.register(GenerateGitLib);
+        libs.register(GitLib);
+        libs.register(LinkLib);
+        libs.register(UpdateSelfLib);
+        libs
+    }
+
+    /// Add a library, replacing any existing library with the same name.
+    pub fn register(&mut self, lib: impl RunLib + 'static) {
+        self.libs.insert(lib.name().to_owned(), Box::new(lib));
+    }
+
+    /// Names of the registered libraries.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.libs.keys().map(String::as_str)
+    }
+
+    /**
+    Run the library called `lib`, if one is registered.
+
+    Returns `None` if there is no library with that name.
+    */
+    pub(crate) fn run(
+        &self,
+        lib: &str,
+        data: Option<serde_yaml::Value>,
+        env_fn: &dyn Fn(&str) -> Result<String, E>,
+        context: &RunContext,
+    ) -> Option<Result<TaskStatus, E>> {
+        let run_lib = self.libs.get(lib)?;
+        if context.dry_run && !run_lib.supports_dry_run() {
+            info!("Skipping as run_lib '{lib}' doesn't support dry runs.");
+            return Some(Ok(TaskStatus::Skipped));
+        }
+        Some(run_lib.run_data(data, env_fn, context))
+    }
+}
+
+impl Default for RunLibs {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+impl fmt::Debug for RunLibs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.names()).finish()
+    }
+}
+
+/// Convert a task's `data:` block into a task config.
+/// Set `has_default` to `true` if the task should fall back to `Default::default()`, or `false` if
+/// it should error when no value was passed.
+pub(crate) fn parse_task_config<F, T: ResolveEnv + Default + DeserializeOwned>(
+    maybe_data: Option<serde_yaml::Value>,
+    task_name: &str,
+    has_default: bool,
+    env_fn: F,
+) -> Result<T, E>
+where
+    F: Fn(&str) -> Result<String, E>,
+{
+    let data = match maybe_data {
+        Some(data) => data,
+        None if has_default => return Ok(T::default()),
+        None => {
+            return Err(E::TaskDataRequired {
+                task: task_name.to_owned(),
+            });
+        }
+    };
+
+    let mut raw_opts: T =
+        serde_yaml::from_value(data).map_err(|e| E::DeserializeError { source: e })?;
+    raw_opts.resolve_env(env_fn)?;
+    Ok(raw_opts)
+}
+
+/// The built-in `defaults` library, which sets macOS defaults.
+struct DefaultsLib;
+
+impl RunLib for DefaultsLib {
+    type Data = DefaultsConfig;
+
+    fn name(&self) -> &'static str {
+        "defaults"
+    }
+
+    fn run(&self, data: DefaultsConfig, context: &RunContext) -> Result<TaskStatus> {
+        Ok(context.finish_with(tasks::defaults::run(data, context.task_tempdir)?))
+    }
+}
+
+/// The built-in `generate_git` library, which generates git tasks from existing repos.
+struct GenerateGitLib;
+
+impl RunLib for GenerateGitLib {
+    type Data = Vec<GenerateGitConfig>;
+
+    fn name(&self) -> &'static str {
+        "generate_git"
+    }
+
+    fn run(&self, data: Vec<GenerateGitConfig>, context: &RunContext) -> Result<TaskStatus> {
+        Ok(context.finish_with(generate::git::run(&data)?))
+    }
+}
+
+/// The built-in `git` library, which clones and updates git repos.
+struct GitLib;
+
+impl RunLib for GitLib {
+    type Data = Vec<GitConfig>;
+
+    fn name(&self) -> &'static str {
+        "git"
+    }
+
+    fn run(&self, data: Vec<GitConfig>, context: &RunContext) -> Result<TaskStatus> {
+        Ok(context.finish_with(tasks::git::run(&data)?))
+    }
+}
+
+/// The built-in `link` library, which symlinks dotfiles.
+struct LinkLib;
+
+impl RunLib for LinkLib {
+    type Data = LinkOptions;
+
+    fn name(&self) -> &'static str {
+        "link"
+    }
+
+    fn supports_dry_run(&self) -> bool {
+        true
+    }
+
+    fn run(&self, mut data: LinkOptions, context: &RunContext) -> Result<TaskStatus> {
+        data.dry_run = context.dry_run;
+        if data.unlink {
+            let mut changes = Vec::new();
+            for from_dir in data.from_dirs {
+                let options = UnlinkOptions {
+                    from_dir,
+                    to_dir: data.to_dir.clone(),
+                    paths: Vec::new(),
+                    dry_run: data.dry_run,
+                };
+                changes.extend(tasks::link::unlink::run(options, context.up_dir)?);
+            }
+            return Ok(context.finish_with(changes));
+        }
+        Ok(context.finish_with(tasks::link::run(data, context.env, context.up_dir)?))
+    }
+}
+
+/// The built-in `self` library, which updates up itself.
+struct UpdateSelfLib;
+
+impl RunLib for UpdateSelfLib {
+    type Data = UpdateSelfOptions;
+
+    fn name(&self) -> &'static str {
+        "self"
+    }
+
+    fn requires_data(&self) -> bool {
+        false
+    }
+
+    fn run(&self, data: UpdateSelfOptions, context: &RunContext) -> Result<TaskStatus> {
+        Ok(context.finish_with(tasks::update_self::run(&data)?.into_iter().collect()))
+    }
+}
@@ -0,0 +1,402 @@
+//! Records the results of each `up run` in the runs directory, so later commands can find out
+//! what happened in previous runs.
+use crate::tasks::TaskError as E;
+use crate::tasks::changes::Change;
+use crate::tasks::task::Task;
+use crate::tasks::task::TaskStatus;
+use crate::utils::files;
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use chrono::DateTime;
+use chrono::SecondsFormat;
+use chrono::Utc;
+use color_eyre::eyre::Context;
+use color_eyre::eyre::Result;
+use color_eyre::eyre::eyre;
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fs;
+use std::fs::File;
+use std::fs::TryLockError;
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::debug;
+use tracing::info;
+use tracing::trace;
+use tracing::warn;
+
+/// Name of the directory inside the up temp dir that contains one directory per run.
+const RUNS_DIR_NAME: &str = "runs";
+
+/// Name of the file in each run directory that records the results of that run's tasks.
+pub const RUN_RESULTS_FILE_NAME: &str = "run_results.json";
+
+/// Name of the file in each task's directory that captures the task's stdout and stderr.
+pub const TASK_OUTPUT_FILE_NAME: &str = "task_stdout_stderr.txt";
+
+/// Name of the file in each task's directory that records the terminal session of the task's
+/// `run_cmd` (for tasks run with `record: true` or `up run --record`).
+pub const TASK_RECORDING_FILE_NAME: &str = "task_session.cast";
+
+/// Name of the file in each task's directory that records the terminal session of the task's
+/// `run_if_cmd`.
+pub const TASK_RUN_IF_RECORDING_FILE_NAME: &str = "task_run_if_session.cast";
+
+/// Name of the file in each run directory that the `up` process doing the run holds a lock on
+/// until it exits, so other processes know not to clean the run up.
+const RUN_LOCK_FILE_NAME: &str = "run.lock";
+
+/// Which tasks from the previous run to run again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rerun {
+    /// Tasks that failed or didn't finish.
+    Failed,
+    /// Tasks that failed or didn't finish, and any tasks that require them.
+    FailedAndDependents,
+}
+
+/// The recorded results of a single `up run`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunResults {
+    /// When the run started.
+    pub start_time: DateTime<Utc>,
+    /// Results for each task that the run selected, keyed by task name.
+    pub tasks: BTreeMap<String, TaskResult>,
+}
+
+/// The recorded result of a single task in a run.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TaskResult {
+    /// How far the task got.
+    pub status: TaskResultStatus,
+    /// How long the task took, if it finished.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration: Option<Duration>,
+    /// The error message, if the task failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// What the task changed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub changes: Vec<Change>,
+}
+
+/// Serializable version of a task's status.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskResultStatus {
+    /// The task was selected, but hasn't started yet.
+    Pending,
+    /// The task is currently running.
+    Running,
+    /// Completed successfully.
+    Passed,
+    /// Skipped.
+    Skipped,
+    /// Completed unsuccessfully.
+    Failed,
+}
+
+impl TaskResultStatus {
+    /// The status as shown to users, matching the serialized name.
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Running => "running",
+            Self::Passed => "passed",
+            Self::Skipped => "skipped",
+            Self::Failed => "failed",
+        }
+    }
+
+    /// Whether the task failed or didn't get to finish.
+    #[must_use]
+    pub const fn is_failed_or_unfinished(self) -> bool {
+        matches!(self, Self::Pending | Self::Running | Self::Failed)
+    }
+}
+
+impl From<&TaskStatus> for TaskResultStatus {
+    fn from(status: &TaskStatus) -> Self {
+        match status {
+            TaskStatus::Incomplete => Self::Pending,
+            TaskStatus::Skipped => Self::Skipped,
+            TaskStatus::Passed => Self::Passed,
+            TaskStatus::Failed(_) => Self::Failed,
+        }
+    }
+}
+
+/// The directory containing the per-run directories.
+#[must_use]
+pub fn runs_dir(temp_dir: &Utf8Path) -> Utf8PathBuf {
+    temp_dir.join(RUNS_DIR_NAME)
+}
+
+/// The directory used by the run that started at `start_time`.
+#[must_use]
+pub fn run_dir(temp_dir: &Utf8Path, start_time: &DateTime<Utc>) -> Utf8PathBuf {
+    runs_dir(temp_dir).join(
+        start_time
+            .to_rfc3339_opts(SecondsFormat::AutoSi, true)
+            // : is not an allowed filename character in Finder.
+            .replace(':', "_"),
+    )
+}
+
+/// The file capturing a task's output in a run directory.
+#[must_use]
+pub fn task_output_path(run_dir: &Utf8Path, task_name: &str) -> Utf8PathBuf {
+    run_dir.join(task_name).join(TASK_OUTPUT_FILE_NAME)
+}
+
+/// The files recording a task's terminal sessions in a run directory, in the order the commands
+/// run (`run_if_cmd`, then `run_cmd`).
+#[must_use]
+pub fn task_recording_paths(run_dir: &Utf8Path, task_name: &str) -> [Utf8PathBuf; 2] {
+    let task_dir = run_dir.join(task_name);
+    [
+        task_dir.join(TASK_RUN_IF_RECORDING_FILE_NAME),
+        task_dir.join(TASK_RECORDING_FILE_NAME),
+    ]
+}
+
+/// Whether the run in `run_dir` is still in progress (its `up` process still holds its lock).
+/// Runs recorded by versions of up that didn't take a lock are never in progress.
+#[must_use]
+pub fn is_in_progress(run_dir: &Utf8Path) -> bool {
+    File::open(run_dir.join(RUN_LOCK_FILE_NAME))
+        .is_ok_and(|file| matches!(file.try_lock_shared(), Err(TryLockError::WouldBlock)))
+}
+
+/**
+All the run directories, oldest first.
+
+Run directories are named after their start times, so sorting them by name sorts them by time.
+*/
+pub fn run_dirs(temp_dir: &Utf8Path) -> Result<Vec<Utf8PathBuf>> {
+    let runs_dir = runs_dir(temp_dir);
+    if !runs_dir.is_dir() {
+        debug!("Runs directory {runs_dir} doesn't exist, no previous runs.");
+        return Ok(Vec::new());
+    }
+    let mut run_dirs = Vec::new();
+    for entry in runs_dir.read_dir_utf8().map_err(|e| E::ReadDir {
+        path: runs_dir.clone(),
+        source: e,
+    })? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            run_dirs.push(entry.into_path());
+        }
+    }
+    run_dirs.sort_unstable();
+    Ok(run_dirs)
+}
+
+/// Find the most recent run that recorded results, ignoring the run directory `exclude` (normally
+/// the current run).
+pub fn latest_run(
+    temp_dir: &Utf8Path,
+    exclude: Option<&Utf8Path>,
+) -> Result<Option<(Utf8PathBuf, RunResults)>> {
+    for run_dir in run_dirs(temp_dir)?.into_iter().rev() {
+        if Some(run_dir.as_path()) == exclude {
+            continue;
+        }
+        let results_path = run_dir.join(RUN_RESULTS_FILE_NAME);
+        if !results_path.exists() {
+            trace!("Run {run_dir} has no results file, skipping.");
+            continue;
+        }
+        match read_results(&results_path) {
+            Ok(results) => return Ok(Some((run_dir, results))),
+            Err(e) => warn!("Ignoring unreadable run results {results_path}: {e:?}"),
+        }
+    }
+    Ok(None)
+}
+
+/// Read a run results file.
+pub fn read_results(path: &Utf8Path) -> Result<RunResults> {
+    let contents = fs::read_to_string(path).map_err(|e| E::ReadFile {
+        path: path.to_owned(),
+        source: e,
+    })?;
+    serde_json::from_str(&contents).wrap_err_with(|| eyre!("Failed to parse run results {path}"))
+}
+
+/**
+Work out which tasks to run again, based on the results of the previous run.
+
+`tasks` is the full set of tasks that could be run, used to find the tasks that require the
+failed tasks.
+*/
+pub(crate) fn rerun_tasks(
+    temp_dir: &Utf8Path,
+    rerun: Rerun,
+    tasks: &HashMap<String, Task>,
+) -> Result<HashSet<String>> {
+    let (run_dir, results) = latest_run(temp_dir, None)?.ok_or_else(|| {
+        eyre!("Asked to rerun failed tasks, but no previous run results were found in {temp_dir}.")
+    })?;
+    debug!("Using previous run results from {run_dir}");
+
+    let mut selected: HashSet<String> = results
+        .tasks
+        .into_iter()
+        .filter(|(_, result)| result.status.is_failed_or_unfinished())
+        .map(|(name, _)| name)
+        .collect();
+    debug!("Failed or unfinished tasks in previous run: {selected:?}");
+    if selected.is_empty() {
+        info!("No tasks failed in the previous run ({run_dir}), so there is nothing to rerun.");
+    }
+
+    if rerun == Rerun::FailedAndDependents {
+        // Keep adding tasks that require a selected task until nothing changes.
+        loop {
+            let dependents: Vec<String> = tasks
+                .values()
+                .filter(|task| !selected.contains(&task.name))
+                .filter(|task| {
+                    task.config
+                        .requires
+                        .iter()
+                        .flatten()
+                        .any(|required| selected.contains(required))
+                })
+                .map(|task| task.name.clone())
+                .collect();
+            if dependents.is_empty() {
+                break;
+            }
+            debug!("Adding dependent tasks: {dependents:?}");
+            selected.extend(dependents);
+        }
+    }
+    Ok(selected)
+}
+
+/// Records task results for the current run, writing them to disk after every change so that
+/// an interrupted run still leaves a record of what finished. Dry runs are only recorded in
+/// memory, so they don't affect later runs (e.g. `up run --failed`).
+#[derive(Debug)]
+pub(crate) struct RunRecorder {
+    /// Path to the run results file.
+    path: Utf8PathBuf,
+    /// Results so far.
+    results: Mutex<RunResults>,
+    /// Whether to write the results to disk (false for dry runs).
+    persist: bool,
+    /// Held until the recorder is dropped, marking the run as in progress.
+    _lock: File,
+}
+
+impl RunRecorder {
+    /// Start recording a run, marking all the selected tasks as pending. Results are only written
+    /// to disk if `persist` is set.
+    pub(crate) fn new<'a>(
+        run_dir: &Utf8Path,
+        start_time: DateTime<Utc>,
+        task_names: impl IntoIterator<Item = &'a str>,
+        persist: bool,
+    ) -> Result<Self> {
+        let tasks = task_names
+            .into_iter()
+            .map(|name| {
+                (
+                    name.to_owned(),
+                    TaskResult {
+                        status: TaskResultStatus::Pending,
+                        duration: None,
+                        error: None,
+                        changes: Vec::new(),
+                    },
+                )
+            })
+            .collect();
+        let lock_path = run_dir.join(RUN_LOCK_FILE_NAME);
+        let lock = files::create(&lock_path, None)?;
+        lock.lock()
+            .wrap_err_with(|| eyre!("Failed to lock run directory {run_dir}"))?;
+        let recorder = Self {
+            path: run_dir.join(RUN_RESULTS_FILE_NAME),
+            results: Mutex::new(RunResults { start_time, tasks }),
+            persist,
+            _lock: lock,
+        };
+        recorder.save()?;
+        Ok(recorder)
+    }
+
+    /// Mark a task as having started.
+    pub(crate) fn started(&self, task_name: &str) {
+        self.record(
+            task_name,
+            TaskResult {
+                status: TaskResultStatus::Running,
+                duration: None,
+                error: None,
+                changes: Vec::new(),
+            },
+        );
+    }
+
+    /// Record the final status of a task.
+    pub(crate) fn finished(&self, task: &Task, duration: Duration) {
+        let error = match &task.status {
+            TaskStatus::Failed(e) => Some(e.to_string()),
+            _ => None,
+        };
+        self.record(
+            &task.name,
+            TaskResult {
+                status: TaskResultStatus::from(&task.status),
+                duration: Some(duration),
+                error,
+                changes: task.changes.clone(),
+            },
+        );
+    }
+
+    /// A copy of the results recorded so far.
+    pub(crate) fn results(&self) -> Result<RunResults> {
+        Ok(self
+            .results
+            .lock()
+            .map_err(|_| eyre!("Run results lock was poisoned."))?
+            .clone())
+    }
+
+    /// Update a task's result, logging rather than failing the run if we can't save it.
+    fn record(&self, task_name: &str, result: TaskResult) {
+        {
+            let mut results = self
+                .results
+                .lock()
+                .expect("Run results lock should never be poisoned.");
+            results.tasks.insert(task_name.to_owned(), result);
+        }
+        if let Err(e) = self.save() {
+            warn!("Failed to save run results for task '{task_name}': {e:?}");
+        }
+    }
+
+    /// Write the current results to disk, unless this run isn't persisted.
+    fn save(&self) -> Result<()> {
+        if !self.persist {
+            return Ok(());
+        }
+        let results = self
+            .results
+            .lock()
+            .map_err(|_| eyre!("Run results lock was poisoned."))?;
+        let contents = serde_json::to_string_pretty(&*results)?;
+        files::create_dir_all(files::parent(&self.path)?)?;
+        files::write(&self.path, contents)
+    }
+}
@@ -3,16 +3,20 @@
 //! Up task execution.
 use crate::exec::UpDuct;
 use crate::exec::cmd_log;
-use crate::generate;
+use crate::faketty;
+use crate::faketty::asciicast::Recording;
 use crate::log;
-use crate::opts::GenerateGitConfig;
-use crate::opts::LinkOptions;
-use crate::opts::UpdateSelfOptions;
-use crate::tasks;
-use crate::tasks::ResolveEnv;
 use crate::tasks::TaskError as E;
-use crate::tasks::defaults::DefaultsConfig;
-use crate::tasks::git::GitConfig;
+use crate::tasks::changes;
+use crate::tasks::changes::Change;
+use crate::tasks::changes::ChangeRecorder;
+use crate::tasks::external;
+use crate::tasks::run_lib::RunContext;
+use crate::tasks::run_lib::RunLibs;
+use crate::tasks::run_lib::parse_task_config;
+use crate::tasks::runs;
+use crate::utils::errors::log_error;
+use crate::utils::user;
 use camino::Utf8Path;
 use camino::Utf8PathBuf;
 use color_eyre::eyre::Result;
@@ -32,6 +36,7 @@ use tracing::Level;
 use tracing::debug;
 use tracing::info;
 use tracing::trace;
+use tracing::warn;
 
 /// Possible statuses an asynchronously running task can have.
 #[derive(Debug)]
@@ -46,6 +51,29 @@ pub enum TaskStatus {
     Failed(E),
 }
 
+/// Settings that apply to every task in a run.
+#[allow(clippy::struct_excessive_bools)] // Run-wide switches, each checked separately by tasks.
+#[derive(Debug, Default)]
+pub struct RunSettings {
+    /// Whether task stdout/stderr should inherit from up's stdout/stderr.
+    pub console: bool,
+    /**
+    Only report what would be changed. External run libraries are asked to do a dry run, other
+    tasks are skipped after their `run_if_cmd`.
+    */
+    pub dry_run: bool,
+    /// Record every task's terminal session, as if they all set `record: true`.
+    pub record: bool,
+    /// Skip tasks that need privileges (see [`Task::needs_privileges`]).
+    pub no_sudo: bool,
+    /// Directories to search (before `$PATH`) for external `up-lib-<name>` run libraries.
+    pub lib_dirs: Vec<Utf8PathBuf>,
+    /// Run libraries that tasks can use, checked before external libraries.
+    pub libs: RunLibs,
+    /// The up temp dir, where run libraries keep state between runs.
+    pub up_dir: Utf8PathBuf,
+}
+
 /// A task's state.
 #[derive(Debug)]
 pub struct Task {
@@ -59,10 +87,13 @@ pub struct Task {
     pub start_time: Instant,
     /// Current task status.
     pub status: TaskStatus,
+    /// What the task changed (or in a dry run, would have changed).
+    pub changes: Vec<Change>,
 }
 
 /// Configuration a task can have, a `~/.config/up/tasks/<name>.yaml` will deserialize to this
 /// struct.
+#[allow(clippy::struct_excessive_bools)] // Mirrors the yaml, where each is its own key.
 #[derive(Debug, Serialize, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct TaskConfig {
@@ -78,8 +109,12 @@ pub struct TaskConfig {
     /// Whether to run this by default, or only if required.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub auto_run: Option<bool>,
-    /// Run library: up library to use for this task. Either use this or
-    /// `run_cmd` + `run_if_cmd`.
+    /**
+    Run library: up library to use for this task. Either use this or `run_cmd` + `run_if_cmd`.
+
+    This can be a library built into up, or an external library provided by an `up-lib-<name>`
+    executable in the up config directory or in `$PATH`.
+    */
     #[serde(skip_serializing_if = "Option::is_none")]
     pub run_lib: Option<String>,
     /**
@@ -95,16 +130,66 @@ pub struct TaskConfig {
 
     The task will be marked as skipped if the exit code from `$UP_EXIT_CODE_SKIPPED` is returned.
     Any other exit code means the command failed to run.
+
+    The command can report what it changed by writing tab-separated `kind`, `target`, `before`,
+    and `after` lines to the file at `$UP_CHANGES`.
     */
     #[serde(skip_serializing_if = "Option::is_none")]
     pub run_cmd: Option<Vec<String>>,
     /// Description of the task.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
+    /// Labels for grouping related tasks, e.g. `[macos, fonts]`. Shown and searchable in
+    /// `up run --interactive`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
     /// Set to true to prompt for superuser privileges before running.
     /// This will allow all subtasks that up executes in this iteration.
     #[serde(default = "default_false")]
     pub needs_sudo: bool,
+    /**
+    User to run `run_if_cmd` and `run_cmd` as, e.g. `root`.
+
+    Commands are wrapped with `sudo -u <user>` (or `runuser -u <user>` if up is running as root),
+    keeping the task's env vars. Unlike `needs_sudo`, only this task's commands get extra
+    privileges. up prompts for your password once at the start of the run if needed, tasks fail
+    rather than prompting if the cached credentials expire.
+
+    Can't be used with `run_lib`, as run libraries run inside up.
+    */
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub run_as: Option<String>,
+    /**
+    Extra paths that `up watch` should watch for this task, e.g. the `from_dir` of a link task.
+
+    The task is re-run when anything under these paths changes. The task's own config file is
+    always watched. Relative paths are relative to the directory containing the up config, and
+    env vars are expanded as in other task fields.
+    */
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub watch: Option<Vec<String>>,
+    /**
+    Run `run_if_cmd` and `run_cmd` in a pseudo-terminal, for tools that disable colours and
+    progress output (or refuse to run) when not connected to a terminal.
+
+    Only stdout and stderr are terminals, stdin is `/dev/null` as for other tasks. Output is
+    still saved to the task's output file. Has no effect with `up run --console`, as commands
+    then use up's own terminal.
+    */
+    #[serde(default = "default_false", skip_serializing_if = "is_false")]
+    pub tty: bool,
+    /// Remove ANSI escape sequences (colours, cursor movement, etc.) from the output saved by
+    /// tasks with `tty: true`.
+    #[serde(default = "default_false", skip_serializing_if = "is_false")]
+    pub strip_ansi: bool,
+    /**
+    Record the terminal session of `run_if_cmd` and `run_cmd` with timing, so it can be
+    replayed with `up logs --replay <task>`. Implies `tty: true`.
+
+    Each command's session is saved as an asciicast v2 file next to the task's output file.
+    */
+    #[serde(default = "default_false", skip_serializing_if = "is_false")]
+    pub record: bool,
     // This field must be the last one in this struct in order for the yaml serializer in the
     // generate functions to be able to serialise it properly.
     /// Set of data provided to the Run library.
@@ -120,6 +205,15 @@ const fn default_false() -> bool {
     false
 }
 
+/// Used to skip serializing unset flags above.
+#[expect(
+    clippy::trivially_copy_pass_by_ref,
+    reason = "serde passes a reference"
+)]
+const fn is_false(value: &bool) -> bool {
+    !*value
+}
+
 /// Shell commands we run.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CommandType {
@@ -158,31 +252,46 @@ impl Task {
                 .ok_or_else(|| eyre!("Task had no path."))?
                 .to_owned(),
         };
+        if config.run_as.is_some() && config.run_lib.is_some() {
+            return Err(E::RunAsWithRunLib { name }.into());
+        }
         let task = Self {
             name,
             path: path.to_owned(),
             config,
             start_time,
             status: TaskStatus::Incomplete,
+            changes: Vec::new(),
         };
         debug!("Task '{name}': {task:?}", name = &task.name);
         Ok(task)
     }
 
-    /// Run a task.
+    /**
+    Run a task.
+
+    Changes are collected from the run library, and from the `$UP_CHANGES` file in the task's
+    temporary directory (see [`crate::tasks::changes`]).
+    */
     pub fn run<F>(
         &mut self,
         env_fn: F,
         env: &HashMap<String, String>,
         task_tempdir: &Utf8Path,
-        console: bool,
+        settings: &RunSettings,
     ) where
         F: Fn(&str) -> Result<String, E>,
     {
-        match self.try_run(env_fn, env, task_tempdir, console) {
+        let changes = ChangeRecorder::default();
+        match self.try_run(env_fn, env, task_tempdir, settings, &changes) {
             Ok(status) => self.status = status,
             Err(e) => self.status = TaskStatus::Failed(e),
         }
+        match changes::read_changes_file(&changes::changes_file_path(task_tempdir)) {
+            Ok(file_changes) => changes.record(file_changes),
+            Err(e) => warn!("Ignoring changes written by task.{}", log_error(&e)),
+        }
+        self.changes = changes.into_changes();
     }
 
     /// Try to run the task.
@@ -191,7 +300,8 @@ impl Task {
         env_fn: F,
         env: &HashMap<String, String>,
         task_tempdir: &Utf8Path,
-        console: bool,
+        settings: &RunSettings,
+        changes: &ChangeRecorder,
     ) -> Result<TaskStatus, E>
     where
         F: Fn(&str) -> Result<String, E>,
@@ -199,14 +309,21 @@ impl Task {
         let name = &self.name;
         info!("Running");
 
-        if let Some(mut cmd) = self.config.run_if_cmd.clone() {
+        if settings.no_sudo && self.needs_privileges() {
+            info!("Skipping task as it needs privileges and --no-sudo was passed.");
+            return Ok(TaskStatus::Skipped);
+        }
+
+        if settings.dry_run && self.config.run_if_cmd.is_some() {
+            info!("Not running run_if command in dry run, assuming the task would run.");
+        } else if let Some(mut cmd) = self.config.run_if_cmd.clone() {
             debug!("Running run_if command.");
             for s in &mut cmd {
                 *s = env_fn(s)?;
             }
             // TODO(gib): Allow choosing how to validate run_if_cmd output (stdout, zero exit
             // code, non-zero exit code).
-            if !self.run_command(CommandType::RunIf, &cmd, env, task_tempdir, console)? {
+            if !self.run_command(CommandType::RunIf, &cmd, env, task_tempdir, settings)? {
                 debug!("Skipping task as run_if command failed.");
                 return Ok(TaskStatus::Skipped);
             }
@@ -217,38 +334,25 @@ impl Task {
         if let Some(lib) = &self.config.run_lib {
             let maybe_data = self.config.data.clone();
 
-            let status = match lib.as_str() {
-                "defaults" => {
-                    let data: DefaultsConfig =
-                        parse_task_config(maybe_data, &self.name, false, env_fn)?;
-                    tasks::defaults::run(data, task_tempdir)
-                }
-
-                "generate_git" => {
-                    let data: Vec<GenerateGitConfig> =
-                        parse_task_config(maybe_data, &self.name, false, env_fn)?;
-                    generate::git::run(&data)
-                }
-
-                "git" => {
-                    let data: Vec<GitConfig> =
-                        parse_task_config(maybe_data, &self.name, false, env_fn)?;
-                    tasks::git::run(&data)
-                }
-
-                "link" => {
-                    let data: LinkOptions =
-                        parse_task_config(maybe_data, &self.name, false, env_fn)?;
-                    tasks::link::run(data, task_tempdir)
-                }
+            let context = RunContext::new(&self.name, env, task_tempdir, settings, changes);
+            if let Some(status) = settings
+                .libs
+                .run(lib, maybe_data.clone(), &env_fn, &context)
+            {
+                return status;
+            }
 
-                "self" => {
-                    let data: UpdateSelfOptions =
+            let status = match external::find_lib(lib, &settings.lib_dirs) {
+                Some(executable) => {
+                    let data: serde_yaml::Value =
                         parse_task_config(maybe_data, &self.name, true, env_fn)?;
-                    tasks::update_self::run(&data)
+                    external::run(&executable, &data, &context)
                 }
-
-                _ => Err(eyre!("This run_lib is invalid or not yet implemented.")),
+                None => Err(eyre!(
+                    "This run_lib isn't built into up or registered, and no `up-lib-{lib}` \
+                     executable was found in {lib_dirs:?} or $PATH.",
+                    lib_dirs = settings.lib_dirs,
+                )),
             }
             .map_err(|e| E::TaskError {
                 name: self.name.clone(),
@@ -259,11 +363,15 @@ impl Task {
         }
 
         if let Some(mut cmd) = self.config.run_cmd.clone() {
+            if settings.dry_run {
+                info!("Skipping run command in dry run.");
+                return Ok(TaskStatus::Skipped);
+            }
             debug!("Running '{name}' run command.");
             for s in &mut cmd {
                 *s = env_fn(s)?;
             }
-            if self.run_command(CommandType::Run, &cmd, env, task_tempdir, console)? {
+            if self.run_command(CommandType::Run, &cmd, env, task_tempdir, settings)? {
                 return Ok(TaskStatus::Passed);
             }
             return Ok(TaskStatus::Skipped);
@@ -274,6 +382,17 @@ impl Task {
         })
     }
 
+    /// Whether the task needs sudo, or runs its commands as another user.
+    #[must_use]
+    pub fn needs_privileges(&self) -> bool {
+        self.config.needs_sudo
+            || self
+                .config
+                .run_as
+                .as_deref()
+                .is_some_and(|user| !user::is_current_user(user))
+    }
+
     /**
     Run a command.
     If the `command_type` is `RunIf`, then `Ok(false)` may be returned if the command was skipped.
@@ -284,11 +403,19 @@ impl Task {
         cmd: &[String],
         env: &HashMap<String, String>,
         task_tempdir: &Utf8Path,
-        console: bool,
+        settings: &RunSettings,
     ) -> Result<bool, E> {
         let now = Instant::now();
-        let task_output_file = task_tempdir.join("task_stdout_stderr.txt");
+        let task_output_file = task_tempdir.join(runs::TASK_OUTPUT_FILE_NAME);
 
+        let run_as_cmd;
+        let cmd = match &self.config.run_as {
+            Some(user) => {
+                run_as_cmd = user::run_as_cmd(user, cmd, env.keys());
+                &run_as_cmd
+            }
+            None => cmd,
+        };
         let command = cmd_log(
             Level::DEBUG,
             cmd.first().ok_or(E::EmptyCmd)?,
@@ -298,12 +425,28 @@ impl Task {
         .full_env(env)
         .unchecked();
 
-        let output = if console {
+        let record = self.config.record || settings.record;
+        let output = if settings.console {
             command.run_with_inherit()
+        } else if self.config.tty || record {
+            let recording = record.then(|| Recording {
+                path: task_tempdir.join(match command_type {
+                    CommandType::RunIf => runs::TASK_RUN_IF_RECORDING_FILE_NAME,
+                    CommandType::Run => runs::TASK_RECORDING_FILE_NAME,
+                }),
+                title: format!("{name} {command_type}", name = self.name),
+            });
+            faketty::run_in_pty(
+                &command,
+                &task_output_file,
+                self.config.strip_ansi,
+                recording.as_ref(),
+            )
         } else {
+            // Share one file handle so stdout and stderr don't overwrite each other.
             command
                 .stdin_null()
-                .stderr_path(&task_output_file)
+                .stderr_to_stdout()
                 .run_with_path(&task_output_file)
         };
 
@@ -383,31 +526,3 @@ impl Task {
         }
     }
 }
-
-/// Convert a task's `data:` block into a task config.
-/// Set `has_default` to `true` if the task should fall back to `Default::default()`, or `false` if
-/// it should error when no value was passed.
-fn parse_task_config<F, T: ResolveEnv + Default + for<'de> serde::Deserialize<'de>>(
-    maybe_data: Option<serde_yaml::Value>,
-    task_name: &str,
-    has_default: bool,
-    env_fn: F,
-) -> Result<T, E>
-where
-    F: Fn(&str) -> Result<String, E>,
-{
-    let data = match maybe_data {
-        Some(data) => data,
-        None if has_default => return Ok(T::default()),
-        None => {
-            return Err(E::TaskDataRequired {
-                task: task_name.to_owned(),
-            });
-        }
-    };
-
-    let mut raw_opts: T =
-        serde_yaml::from_value(data).map_err(|e| E::DeserializeError { source: e })?;
-    raw_opts.resolve_env(env_fn)?;
-    Ok(raw_opts)
-}
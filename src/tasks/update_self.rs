@@ -3,7 +3,7 @@ use self::UpdateSelfError as E;
 use crate::cmd;
 use crate::opts::UpdateSelfOptions;
 use crate::tasks::ResolveEnv;
-use crate::tasks::task::TaskStatus;
+use crate::tasks::changes::Change;
 use camino::Utf8PathBuf;
 use chrono::Utc;
 use color_eyre::eyre::Context;
@@ -37,15 +37,15 @@ const CURRENT_VERSION: &str = env!("CARGO_PKG_VERSION");
 impl ResolveEnv for UpdateSelfOptions {}
 
 /// Downloads the latest version of the binary from the specified URL and
-/// replaces the current executable path with it.
-pub(crate) fn run(opts: &UpdateSelfOptions) -> Result<TaskStatus> {
+/// replaces the current executable path with it, returning the change if it was updated.
+pub(crate) fn run(opts: &UpdateSelfOptions) -> Result<Option<Change>> {
     let up_path = Utf8PathBuf::try_from(env::current_exe()?)?.canonicalize_utf8()?;
 
     // If the current binary's location is where it was originally compiled, assume it is a dev
     // build, and thus skip the update.
     if !opts.always_update && up_path.starts_with(env!("CARGO_MANIFEST_DIR")) {
         debug!("Skipping up update, current version '{up_path}' is a dev build.",);
-        return Ok(TaskStatus::Skipped);
+        return Ok(None);
     }
 
     let client = reqwest::blocking::Client::builder()
@@ -68,7 +68,7 @@ pub(crate) fn run(opts: &UpdateSelfOptions) -> Result<TaskStatus> {
                 "Skipping up update, current version '{CURRENT_VERSION}' is not older than latest \
                  GitHub version '{latest_github_release}'",
             );
-            return Ok(TaskStatus::Skipped);
+            return Ok(None);
         }
         trace!("Updating up from '{CURRENT_VERSION}' to '{latest_github_release}'",);
     }
@@ -100,13 +100,17 @@ pub(crate) fn run(opts: &UpdateSelfOptions) -> Result<TaskStatus> {
             from: temp_path.clone(),
             to: up_path.clone(),
         })?;
-        Ok(TaskStatus::Passed)
+        Ok(Some(
+            Change::new("up", up_path.as_str())
+                .before(CURRENT_VERSION)
+                .after(new_version),
+        ))
     } else {
         debug!(
             "Skipping up update, current version '{CURRENT_VERSION}' and new version \
              '{new_version}'",
         );
-        Ok(TaskStatus::Skipped)
+        Ok(None)
     }
 }
 
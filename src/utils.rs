@@ -4,5 +4,6 @@ pub mod errors;
 pub mod files;
 pub(crate) mod log;
 pub(crate) mod mac;
+pub(crate) mod state;
 pub mod time;
 pub(crate) mod user;
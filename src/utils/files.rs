@@ -2,8 +2,12 @@
 
 use crate::UP_BUNDLE_ID;
 use crate::errors::UpError;
+use camino::Utf8Component;
 use camino::Utf8Path;
 use camino::Utf8PathBuf;
+use chrono::DateTime;
+use chrono::SecondsFormat;
+use chrono::Utc;
 use color_eyre::Result;
 use color_eyre::eyre::Context;
 use color_eyre::eyre::eyre;
@@ -35,9 +39,45 @@ pub fn home_dir() -> Result<Utf8PathBuf> {
     Ok(home_dir)
 }
 
-/// The directory to which we write log files.
+/**
+The default directory for state that up keeps between runs (run results, backups, etc).
+
+On Linux this follows the XDG base directory spec, so it is `$XDG_STATE_HOME/up` (by default
+`~/.local/state/up`). Elsewhere it is `up` inside the system temporary directory.
+*/
+pub fn state_dir() -> Result<Utf8PathBuf> {
+    let state_dir = if cfg!(target_os = "linux") {
+        dirs::state_dir()
+            .ok_or_else(|| eyre!("Expected to be able to calculate the user's state directory."))?
+    } else {
+        std::env::temp_dir()
+    };
+    Ok(Utf8PathBuf::try_from(state_dir)?.join("up"))
+}
+
+/**
+The directory to which we write log files.
+
+This is `~/Library/Logs/co.fahn.up` on macOS, and `logs` inside the [`state_dir()`] elsewhere.
+*/
 pub fn log_dir() -> Result<Utf8PathBuf> {
-    Ok(home_dir()?.join("Library/Logs").join(UP_BUNDLE_ID))
+    if cfg!(target_os = "macos") {
+        Ok(home_dir()?.join("Library/Logs").join(UP_BUNDLE_ID))
+    } else {
+        Ok(state_dir()?.join("logs"))
+    }
+}
+
+/// The path to the log file for the up command started at `start_time`, e.g.
+/// `up_2024-04-26T11_22_24.834348Z.log` inside the [`log_dir()`].
+pub fn log_path(start_time: &DateTime<Utc>) -> Result<Utf8PathBuf> {
+    Ok(log_dir()?.join(format!(
+        "up_{}.log",
+        start_time
+            .to_rfc3339_opts(SecondsFormat::AutoSi, true)
+            // : is not an allowed filename character in Finder.
+            .replace(':', "_")
+    )))
 }
 
 /// Get a parent path or provide a useful error message.
@@ -75,6 +115,51 @@ pub fn remove_broken_symlink(path: &Utf8Path) -> Result<(), UpError> {
     Ok(())
 }
 
+/**
+Remove `path` if it's a broken symlink pointing into `dir`, e.g. a link we made to a file in `dir`
+that has since been removed. Returns whether the symlink was removed.
+
+Anything else at `path` (including symlinks pointing outside `dir`, and symlinks that still work)
+is left alone, so this only cleans up our own links.
+*/
+pub fn remove_broken_symlink_in(path: &Utf8Path, dir: &Utf8Path) -> Result<bool, UpError> {
+    let is_broken_symlink = path
+        .symlink_metadata()
+        .is_ok_and(|metadata| metadata.file_type().is_symlink())
+        && !path.exists();
+    if !is_broken_symlink {
+        return Ok(false);
+    }
+    let target = path.read_link_utf8().map_err(|e| UpError::IoError {
+        path: path.to_owned(),
+        source: e,
+    })?;
+    let resolved = normalize(&path.parent().unwrap_or(path).join(target));
+    if !resolved.starts_with(dir) {
+        trace!("Leaving broken symlink {path}, as it points to {resolved}, outside {dir}");
+        return Ok(false);
+    }
+    remove_broken_symlink(path)?;
+    Ok(true)
+}
+
+/// Remove `.` and `..` components from a path, without looking at the filesystem.
+pub(crate) fn normalize(path: &Utf8Path) -> Utf8PathBuf {
+    let mut normalized = Utf8PathBuf::new();
+    for component in path.components() {
+        match component {
+            Utf8Component::CurDir => {}
+            Utf8Component::ParentDir => {
+                normalized.pop();
+            }
+            Utf8Component::Prefix(_) | Utf8Component::RootDir | Utf8Component::Normal(_) => {
+                normalized.push(component);
+            }
+        }
+    }
+    normalized
+}
+
 /// Ensure that a file exists with the specified permissions, creating it and its parent directories
 /// as needed.
 pub fn create(file_path: &Utf8Path, mode: Option<u32>) -> Result<File> {
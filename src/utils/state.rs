@@ -0,0 +1,165 @@
+/*!
+JSON maps kept in the up temp dir to remember things between runs (e.g. task durations, or the
+links `up link` has made).
+
+Tasks run in parallel, so more than one may update the same file at once. Rather than writing
+back everything it loaded, a [`StateMap`] records which entries were changed, and applies just
+those changes to the latest contents of the file (under a lock) when it's saved.
+*/
+use crate::utils::files;
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use color_eyre::eyre::Context;
+use color_eyre::eyre::Result;
+use color_eyre::eyre::eyre;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::borrow::Borrow;
+use std::collections::BTreeMap;
+use std::fs;
+use tracing::debug;
+use tracing::warn;
+
+/// A map saved as JSON, see the [module docs](self).
+#[derive(Debug)]
+pub(crate) struct StateMap<K, V> {
+    /// Path to the file.
+    path: Utf8PathBuf,
+    /// What the file records, used in log messages.
+    what: &'static str,
+    /// The entries, as loaded and then changed.
+    entries: BTreeMap<K, V>,
+    /// The entries changed since loading, with their new value (`None` if removed).
+    changed: BTreeMap<K, Option<V>>,
+}
+
+impl<K, V> StateMap<K, V>
+where
+    K: Ord + Clone + Serialize + DeserializeOwned,
+    V: Clone + Serialize + DeserializeOwned,
+{
+    /// Load the map from `path`, starting afresh if there isn't one (or it can't be read).
+    pub(crate) fn load(path: Utf8PathBuf, what: &'static str) -> Self {
+        let entries = read(&path, what);
+        Self {
+            path,
+            what,
+            entries,
+            changed: BTreeMap::new(),
+        }
+    }
+
+    /// The value for `key`, if there is one.
+    pub(crate) fn get<Q: Ord + ?Sized>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+    {
+        self.entries.get(key)
+    }
+
+    /// Iterate over the entries, in order.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.entries.iter()
+    }
+
+    /// Set the value for `key`.
+    pub(crate) fn insert(&mut self, key: K, value: V) {
+        self.changed.insert(key.clone(), Some(value.clone()));
+        self.entries.insert(key, value);
+    }
+
+    /// Remove the value for `key`, if there is one.
+    pub(crate) fn remove<Q: Ord + ToOwned<Owned = K> + ?Sized>(&mut self, key: &Q)
+    where
+        K: Borrow<Q>,
+    {
+        if self.entries.remove(key).is_some() {
+            self.changed.insert(key.to_owned(), None);
+        }
+    }
+
+    /**
+    Apply the changes made since loading to the latest contents of the file, and save it.
+
+    Takes a lock (on a `.lock` file next to it) so that changes saved at the same time by other
+    tasks or up processes aren't lost. Does nothing if nothing was changed.
+    */
+    pub(crate) fn save(&mut self) -> Result<()> {
+        if self.changed.is_empty() {
+            return Ok(());
+        }
+        let lock_path = Utf8PathBuf::from(format!("{}.lock", self.path));
+        let lock = files::create(&lock_path, None)?;
+        lock.lock()
+            .wrap_err_with(|| eyre!("Failed to lock {lock_path}"))?;
+
+        let mut entries: BTreeMap<K, V> = read(&self.path, self.what);
+        for (key, value) in std::mem::take(&mut self.changed) {
+            match value {
+                Some(value) => entries.insert(key, value),
+                None => entries.remove(&key),
+            };
+        }
+        // Write to a temporary file and rename it, so anyone reading without the lock sees
+        // either the old or the new contents.
+        let tmp_path = Utf8PathBuf::from(format!("{}.tmp", self.path));
+        files::write(&tmp_path, serde_json::to_string_pretty(&entries)?)?;
+        fs::rename(&tmp_path, &self.path)
+            .wrap_err_with(|| eyre!("Failed to move {tmp_path} to {}", self.path))?;
+        self.entries = entries;
+        Ok(())
+    }
+}
+
+/// Read the map in `path`, or an empty map if there isn't one (or it can't be read).
+fn read<K: Ord + DeserializeOwned, V: DeserializeOwned>(
+    path: &Utf8Path,
+    what: &str,
+) -> BTreeMap<K, V> {
+    match fs::read_to_string(path) {
+        Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+            warn!("Ignoring unreadable {what} file {path}: {e}");
+            BTreeMap::new()
+        }),
+        Err(e) => {
+            debug!("No {what} read from {path}: {e}");
+            BTreeMap::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StateMap;
+    use color_eyre::Result;
+    use testutils::ensure_eq;
+
+    /// Changes saved by one map aren't lost when another map loaded earlier is saved.
+    #[test]
+    fn test_save_merges() -> Result<()> {
+        let temp_dir = testutils::temp_dir("up", testutils::function_path!())?;
+        let path = temp_dir.join("state.json");
+        let mut first = StateMap::<String, u32>::load(path.clone(), "test");
+        first.insert("kept".to_owned(), 1);
+        first.insert("removed".to_owned(), 2);
+        first.save()?;
+
+        let mut second = StateMap::<String, u32>::load(path.clone(), "test");
+        let mut third = StateMap::<String, u32>::load(path.clone(), "test");
+        second.insert("second".to_owned(), 3);
+        second.save()?;
+        third.insert("third".to_owned(), 4);
+        third.remove("removed");
+        third.save()?;
+
+        let loaded = StateMap::<String, u32>::load(path, "test");
+        ensure_eq!(
+            vec![("kept", 1), ("second", 3), ("third", 4)],
+            loaded
+                .iter()
+                .map(|(key, value)| (key.as_str(), *value))
+                .collect::<Vec<_>>()
+        );
+        Ok(())
+    }
+}
@@ -2,6 +2,9 @@
 
 use chrono::TimeDelta;
 use color_eyre::Result;
+use color_eyre::eyre::Context;
+use color_eyre::eyre::bail;
+use color_eyre::eyre::ensure;
 use color_eyre::eyre::eyre;
 use std::time::Duration;
 
@@ -13,6 +16,44 @@ pub fn human_readable_duration(duration: Duration) -> Result<String> {
     human_readable_timedelta(timedelta)
 }
 
+/**
+Parse a duration written like the output of [`human_readable_duration`], e.g. `30d`, `12h`, or
+`1w 2d`.
+
+Supported units are `s`, `m`, `h`, `d` and `w`.
+*/
+pub fn parse_duration(duration: &str) -> Result<Duration> {
+    ensure!(
+        !duration.trim().is_empty(),
+        "Expected a duration like `7d`, but it was empty."
+    );
+    let mut total = Duration::ZERO;
+    for part in duration.split_whitespace() {
+        let unit_start = part
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or_else(|| eyre!("Duration `{part}` is missing a unit, e.g. `{part}d`."))?;
+        let (count, unit) = part.split_at(unit_start);
+        let count: u64 = count
+            .parse()
+            .wrap_err_with(|| eyre!("Invalid number in duration `{part}`."))?;
+        let unit_seconds = match unit {
+            "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            "d" => 24 * 60 * 60,
+            "w" => 7 * 24 * 60 * 60,
+            _ => {
+                bail!("Unknown unit `{unit}` in duration `{part}`, expected one of s, m, h, d, w.")
+            }
+        };
+        let seconds = count
+            .checked_mul(unit_seconds)
+            .ok_or_else(|| eyre!("Duration `{part}` is too large."))?;
+        total += Duration::from_secs(seconds);
+    }
+    Ok(total)
+}
+
 /// Convert a `TimeDelta` into a human readable string if possible.
 fn human_readable_timedelta(mut timedelta: TimeDelta) -> Result<String> {
     // Output string to build.
@@ -104,8 +145,10 @@ fn human_readable_timedelta(mut timedelta: TimeDelta) -> Result<String> {
 mod tests {
     use crate::utils::time::human_readable_duration;
     use crate::utils::time::human_readable_timedelta;
+    use crate::utils::time::parse_duration;
     use chrono::TimeDelta;
     use color_eyre::Result;
+    use color_eyre::eyre::ensure;
     use std::time::Duration;
     use testutils::ensure_eq;
 
@@ -126,7 +169,7 @@ mod tests {
         ensure_eq!("5µs", human_readable_duration(Duration::from_nanos(5999))?);
         ensure_eq!("5ms", human_readable_duration(Duration::from_micros(5678))?);
         ensure_eq!("10s", human_readable_duration(Duration::from_secs(10))?);
-        ensure_eq!("5m", human_readable_duration(Duration::from_secs(300))?);
+        ensure_eq!("5m", human_readable_duration(Duration::from_mins(5))?);
         ensure_eq!(
             "6h",
             human_readable_duration(Duration::from_secs(6 * HOURS))?
@@ -201,4 +244,30 @@ mod tests {
         );
         Ok(())
     }
+
+    #[test]
+    fn test_parse_duration() -> Result<()> {
+        ensure_eq!(Duration::from_secs(30), parse_duration("30s")?);
+        ensure_eq!(Duration::from_secs(12 * HOURS), parse_duration("12h")?);
+        ensure_eq!(Duration::from_secs(30 * DAYS), parse_duration("30d")?);
+        ensure_eq!(
+            Duration::from_secs(WEEKS + 2 * DAYS + 5 * MINUTES),
+            parse_duration(" 1w 2d  5m ")?
+        );
+
+        // Round trips with the human readable output.
+        let duration = Duration::from_secs(5 * WEEKS + 4 * HOURS + 50);
+        ensure_eq!(
+            duration,
+            parse_duration(&human_readable_duration(duration)?)?
+        );
+
+        for invalid in ["", "7", "d", "7 d", "7y", "-1d", "1.5h"] {
+            ensure!(
+                parse_duration(invalid).is_err(),
+                "Expected `{invalid}` to be an invalid duration."
+            );
+        }
+        Ok(())
+    }
 }
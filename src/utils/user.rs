@@ -4,6 +4,8 @@ use crate::cmd_debug;
 use crate::exec::UpDuct;
 use color_eyre::Result;
 use duct::Expression;
+use itertools::Itertools;
+use std::env;
 use std::thread;
 use std::time::Duration;
 use tracing::debug;
@@ -37,13 +39,35 @@ User local may run the following commands on Mac-mini-5:
 
 */
 pub(crate) fn get_and_keep_sudo(yes: bool) -> Result<()> {
+    if !get_sudo(yes)? {
+        return Ok(());
+    }
+    thread::spawn(|| {
+        // Only refresh sudo for max 24 hours.
+        for _ in 1..1440 {
+            thread::sleep(Duration::from_mins(1));
+            if let Err(e) = cmd_debug!("sudo", "-vn").run_with(Expression::stdout_to_stderr) {
+                warn!("Refreshing sudo with 'sudo -vn' failed with: {e:#}");
+            }
+        }
+    });
+    Ok(())
+}
+
+/**
+Prompt user for sudo if necessary (see [`get_and_keep_sudo`]), without refreshing it in the
+background.
+
+Returns whether sudo credentials were cached, i.e. whether they will expire.
+*/
+pub(crate) fn get_sudo(yes: bool) -> Result<bool> {
     let tty = cmd_debug!("tty")
         .read()
         .unwrap_or_else(|e| format!("Failed with: {e}"));
     debug!("Current tty is: {tty}");
     if current_user_is_root() {
         debug!("Not getting sudo as we're already running as root.");
-        return Ok(());
+        return Ok(false);
     }
 
     // Run `sudo -n true` && `sudo -kn true`:
@@ -56,7 +80,7 @@ pub(crate) fn get_and_keep_sudo(yes: bool) -> Result<()> {
         .is_ok()
     {
         info!("Looks like passwordless sudo is enabled, not prompting for sudo.");
-        return Ok(());
+        return Ok(false);
     }
 
     // If `--yes` flag set, use `sudo -n` so we don't prompt for password input.
@@ -65,16 +89,7 @@ pub(crate) fn get_and_keep_sudo(yes: bool) -> Result<()> {
     info!("Prompting for your sudo password (the one you use to log in to this Mac)...");
 
     cmd_debug!("sudo", sudo_arg).run_with(Expression::stdout_to_stderr)?;
-    thread::spawn(|| {
-        // Only refresh sudo for max 24 hours.
-        for _ in 1..1440 {
-            thread::sleep(Duration::from_secs(60));
-            if let Err(e) = cmd_debug!("sudo", "-vn").run_with(Expression::stdout_to_stderr) {
-                warn!("Refreshing sudo with 'sudo -vn' failed with: {e:#}");
-            }
-        }
-    });
-    Ok(())
+    Ok(true)
 }
 
 /// Return whether we are running as root.
@@ -83,3 +98,103 @@ pub(crate) fn current_user_is_root() -> bool {
     trace!("Found current user ID to be: {current_user_id}");
     current_user_id == 0
 }
+
+/// Return whether `user` is the user we are running as.
+pub(crate) fn is_current_user(user: &str) -> bool {
+    uzers::get_user_by_name(user).is_some_and(|user| user.uid() == uzers::get_current_uid())
+}
+
+/**
+Wrap `cmd` so it runs as `user`, keeping the env vars named in `env_keys`.
+
+Uses `runuser` if we're running as root on Linux, or `sudo` otherwise. Sudo is run with `-n`, so
+it fails rather than prompting for a password, as task commands have no stdin (see
+[`get_sudo`]). Returns `cmd` unchanged if we're already running as `user`.
+*/
+pub(crate) fn run_as_cmd<'a>(
+    user: &str,
+    cmd: &[String],
+    env_keys: impl IntoIterator<Item = &'a String>,
+) -> Vec<String> {
+    if is_current_user(user) {
+        return cmd.to_vec();
+    }
+    let use_runuser = cfg!(target_os = "linux") && current_user_is_root();
+    // Task commands don't get our `$PATH`, and `runuser` is often in an `sbin` directory.
+    let program = find_in_path(if use_runuser { "runuser" } else { "sudo" });
+    wrap_cmd(program, user, cmd, env_keys, use_runuser)
+}
+
+/// The path to `program` in our `$PATH`, or `program` if it wasn't found.
+fn find_in_path(program: &str) -> String {
+    env::var_os("PATH")
+        .and_then(|path| {
+            env::split_paths(&path)
+                .map(|dir| dir.join(program))
+                .find(|path| path.is_file())
+        })
+        .and_then(|path| path.into_os_string().into_string().ok())
+        .unwrap_or_else(|| program.to_owned())
+}
+
+/**
+Implementation of [`run_as_cmd`], `program` is the path to `runuser` if `use_runuser` is set, or
+to `sudo` otherwise.
+*/
+fn wrap_cmd<'a>(
+    program: String,
+    user: &str,
+    cmd: &[String],
+    env_keys: impl IntoIterator<Item = &'a String>,
+    use_runuser: bool,
+) -> Vec<String> {
+    let mut wrapped = if use_runuser {
+        // runuser keeps the environment unless run with `--login`.
+        vec![program, "-u".to_owned(), user.to_owned()]
+    } else {
+        let mut env_keys: Vec<&String> = env_keys.into_iter().collect();
+        env_keys.sort_unstable();
+        let mut wrapped = vec![program, "-n".to_owned(), "-u".to_owned(), user.to_owned()];
+        if !env_keys.is_empty() {
+            wrapped.push(format!("--preserve-env={}", env_keys.iter().join(",")));
+        }
+        wrapped
+    };
+    wrapped.push("--".to_owned());
+    wrapped.extend(cmd.iter().cloned());
+    wrapped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::wrap_cmd;
+    use testutils::ensure_eq;
+
+    #[test]
+    fn test_wrap_cmd() -> color_eyre::Result<()> {
+        let cmd = ["id".to_owned(), "-un".to_owned()];
+        let env_keys = ["PATH".to_owned(), "HOME".to_owned()];
+        ensure_eq!(
+            vec![
+                "sudo",
+                "-n",
+                "-u",
+                "root",
+                "--preserve-env=HOME,PATH",
+                "--",
+                "id",
+                "-un"
+            ],
+            wrap_cmd("sudo".to_owned(), "root", &cmd, &env_keys, false)
+        );
+        ensure_eq!(
+            vec!["runuser", "-u", "nobody", "--", "id", "-un"],
+            wrap_cmd("runuser".to_owned(), "nobody", &cmd, &env_keys, true)
+        );
+        ensure_eq!(
+            vec!["sudo", "-n", "-u", "nobody", "--", "id", "-un"],
+            wrap_cmd("sudo".to_owned(), "nobody", &cmd, &[], false)
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,233 @@
+//! Watch the up config and task inputs, and re-run tasks when they change.
+use crate::config::UpConfig;
+use crate::env::get_env;
+use crate::opts::Opts;
+use crate::opts::start_time::StartTime;
+use crate::tasks;
+use crate::tasks::TasksAction;
+use crate::tasks::TasksDir;
+use crate::utils::errors::log_error;
+use crate::utils::files;
+use crate::utils::time::human_readable_duration;
+use camino::Utf8PathBuf;
+use color_eyre::eyre::Result;
+use color_eyre::eyre::eyre;
+use itertools::Itertools;
+use notify::Event;
+use notify::EventKind;
+use notify::RecommendedWatcher;
+use notify::RecursiveMode;
+use notify::Watcher;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::sync::mpsc;
+use std::sync::mpsc::Receiver;
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::Duration;
+use std::time::Instant;
+use tracing::debug;
+use tracing::error;
+use tracing::info;
+use tracing::trace;
+use tracing::warn;
+
+/**
+Watch for changes and re-run the affected tasks until interrupted.
+
+On Linux the watcher uses inotify, on macOS it uses `FSEvents`.
+*/
+pub(crate) fn run(opts: Opts, debounce: Duration) -> Result<()> {
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    let mut watched_paths = HashMap::new();
+
+    let mut watch_set = WatchSet::load(&opts)?;
+    watch_set.add_watches(&mut watcher, &mut watched_paths)?;
+    info!(
+        "Watching {count} paths for changes to {tasks_count} tasks, press Ctrl-C to stop.",
+        count = watched_paths.len(),
+        tasks_count = watch_set.task_paths.len(),
+    );
+
+    while let Some(changed) = next_changes(&rx, debounce) {
+        trace!("Changed paths: {changed:?}");
+
+        // Reload so that we pick up any tasks that were added, edited, or removed.
+        match WatchSet::load(&opts) {
+            Ok(new_watch_set) => watch_set = new_watch_set,
+            Err(e) => {
+                error!("Failed to reload the up config.{}", log_error(&e));
+                continue;
+            }
+        }
+        watch_set.add_watches(&mut watcher, &mut watched_paths)?;
+
+        let affected = watch_set.affected_tasks(&changed);
+        if affected.is_empty() {
+            debug!("No tasks affected by changes to {changed:?}");
+            continue;
+        }
+        rerun(&opts, affected);
+    }
+    Ok(())
+}
+
+/**
+Wait for the next batch of changes, returning `None` if the watcher has stopped.
+
+Once something changes we keep collecting events until nothing has changed for `debounce`, so
+that e.g. a `git checkout` only triggers a single re-run. Access events are ignored, as we read
+the config files ourselves every time we re-run.
+*/
+fn next_changes(
+    rx: &Receiver<notify::Result<Event>>,
+    debounce: Duration,
+) -> Option<Vec<Utf8PathBuf>> {
+    let mut changed = Vec::new();
+    loop {
+        let result = if changed.is_empty() {
+            rx.recv().ok()?
+        } else {
+            match rx.recv_timeout(debounce) {
+                Ok(result) => result,
+                Err(RecvTimeoutError::Timeout) => return Some(changed),
+                Err(RecvTimeoutError::Disconnected) => return None,
+            }
+        };
+        let event = match result {
+            Ok(event) => event,
+            Err(e) => {
+                warn!("Error watching for changes.{}", log_error(&e));
+                continue;
+            }
+        };
+        if matches!(event.kind, EventKind::Access(_)) {
+            continue;
+        }
+        for path in event.paths {
+            if let Ok(path) = Utf8PathBuf::from_path_buf(path)
+                && !changed.contains(&path)
+            {
+                changed.push(path);
+            }
+        }
+    }
+}
+
+/// Re-run a set of tasks, logging a one-line summary of how it went.
+fn rerun(opts: &Opts, affected: BTreeSet<String>) {
+    let names = affected.iter().join(", ");
+    let now = Instant::now();
+    let result = UpConfig::from(opts.clone()).and_then(|mut config| {
+        config.tasks = Some(affected.into_iter().collect());
+        config.rerun = None;
+        config.bootstrap = false;
+        // Each re-run gets its own run directory.
+        config.start_time = StartTime::default();
+        tasks::run(&config, TasksDir::Tasks, TasksAction::Run)
+    });
+    let elapsed = now.elapsed();
+    let elapsed = human_readable_duration(elapsed).unwrap_or_else(|_| format!("{elapsed:?}"));
+    match result {
+        Ok(()) => info!("Re-ran [{names}] in {elapsed}: ok"),
+        Err(e) => error!("Re-ran [{names}] in {elapsed}: failed.{}", log_error(&e)),
+    }
+}
+
+/// The paths that should trigger each task to re-run.
+#[derive(Debug)]
+struct WatchSet {
+    /// Canonical path to the up config file, changes to it re-run every task.
+    up_yaml_path: Utf8PathBuf,
+    /// Canonical path to the directory containing the up config file.
+    config_dir: Utf8PathBuf,
+    /// The paths that trigger each task, keyed by task name.
+    task_paths: BTreeMap<String, Vec<Utf8PathBuf>>,
+}
+
+impl WatchSet {
+    /// Load the current config and tasks, and work out which paths each task depends on.
+    fn load(opts: &Opts) -> Result<Self> {
+        let config = UpConfig::from(opts.clone())?;
+        let up_yaml_path = config
+            .up_yaml_path
+            .as_ref()
+            .ok_or_else(|| eyre!("Watching requires an up config file."))?;
+        let up_yaml_path = up_yaml_path.canonicalize_utf8()?;
+        let config_dir = files::parent(&up_yaml_path)?.to_owned();
+
+        let env = get_env(
+            config.config_yaml.inherit_env.as_ref(),
+            config.config_yaml.env.as_ref(),
+        )?;
+        let tasks_dir = tasks::tasks_dir(&config, TasksDir::Tasks)?;
+
+        let mut task_paths = BTreeMap::new();
+        for (name, task) in tasks::load_tasks(&config, &tasks_dir)? {
+            // The task config may be a symlink into a dotfiles repo, watch the real file.
+            let mut paths = vec![task.path.canonicalize_utf8().unwrap_or(task.path)];
+            for path in task.config.watch.iter().flatten() {
+                let path = config_dir.join(tasks::resolve_env_string(path, &env)?);
+                match path.canonicalize_utf8() {
+                    Ok(path) => paths.push(path),
+                    Err(e) => warn!("Task '{name}' watch path {path} can't be watched: {e}"),
+                }
+            }
+            task_paths.insert(name, paths);
+        }
+
+        Ok(Self {
+            up_yaml_path,
+            config_dir,
+            task_paths,
+        })
+    }
+
+    /// Start watching any paths that we aren't already watching.
+    fn add_watches(
+        &self,
+        watcher: &mut RecommendedWatcher,
+        watched_paths: &mut HashMap<Utf8PathBuf, RecursiveMode>,
+    ) -> Result<()> {
+        for path in std::iter::once(&self.config_dir).chain(self.task_paths.values().flatten()) {
+            // Editors often replace files rather than writing to them, which breaks watches on
+            // the file itself, so watch the directory containing it.
+            let (watch_path, mode) = if path.is_dir() {
+                (path.as_path(), RecursiveMode::Recursive)
+            } else {
+                (files::parent(path)?, RecursiveMode::NonRecursive)
+            };
+            let already_watched = watched_paths.iter().any(|(dir, dir_mode)| {
+                dir == watch_path
+                    || (*dir_mode == RecursiveMode::Recursive && watch_path.starts_with(dir))
+            });
+            if already_watched {
+                continue;
+            }
+            debug!("Watching {watch_path} ({mode:?})");
+            watcher.watch(watch_path.as_std_path(), mode)?;
+            watched_paths.insert(watch_path.to_owned(), mode);
+        }
+        Ok(())
+    }
+
+    /// The names of the tasks that should be re-run after `changed` paths changed.
+    fn affected_tasks(&self, changed: &[Utf8PathBuf]) -> BTreeSet<String> {
+        if changed.contains(&self.up_yaml_path) {
+            info!("Up config changed, re-running all tasks.");
+            return self.task_paths.keys().cloned().collect();
+        }
+        self.task_paths
+            .iter()
+            .filter(|(_, task_paths)| {
+                changed.iter().any(|path| {
+                    task_paths
+                        .iter()
+                        .any(|task_path| path.starts_with(task_path))
+                })
+            })
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+}
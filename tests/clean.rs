@@ -0,0 +1,142 @@
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use color_eyre::Result;
+use std::fs;
+use testutils::AssertCmdExt;
+use testutils::ensure_eq;
+use testutils::ensure_utils;
+
+/// Check that `up clean` previews and then removes old runs, old logs, and empty backups.
+#[test]
+fn test_up_clean() -> Result<()> {
+    let temp_dir = testutils::temp_dir("up", testutils::function_path!()).unwrap();
+    testutils::copy_all(
+        &testutils::fixtures_subdir(testutils::function_path!())?,
+        &temp_dir,
+    )
+    .unwrap();
+
+    let runs_dir = temp_dir.join("up/runs");
+    let run_names = [
+        "2024-01-01T00_00_00Z",
+        "2024-01-02T00_00_00Z",
+        "2024-01-03T00_00_00Z",
+        "2024-01-04T00_00_00Z",
+        "2024-01-05T00_00_00Z",
+    ];
+    for run_name in run_names {
+        fs::create_dir_all(runs_dir.join(run_name).join("some_task"))?;
+        fs::write(runs_dir.join(run_name).join("run_results.json"), "{}")?;
+    }
+    // A dry run records no results, so it doesn't count towards the runs kept.
+    let dry_run_name = "2024-01-05T12_00_00Z";
+    fs::create_dir_all(runs_dir.join(dry_run_name).join("some_task"))?;
+
+    let log_dir = log_dir(&temp_dir);
+    fs::create_dir_all(&log_dir)?;
+    let log_names = [
+        "up_2024-01-01T00_00_00Z.log",
+        "up_2024-01-02T00_00_00Z.log",
+        "up_2024-01-03T00_00_00Z.log",
+        "up_2024-01-04T00_00_00Z.log",
+    ];
+    for log_name in log_names {
+        fs::write(log_dir.join(log_name), "")?;
+    }
+
+    let backup_dir = temp_dir.join("up/backup");
+    fs::create_dir_all(backup_dir.join("link/empty/nested"))?;
+    fs::create_dir_all(backup_dir.join("defaults"))?;
+    fs::write(backup_dir.join("defaults/backup.plist"), "")?;
+
+    // A dry run uses the up config's retention policy, and removes nothing.
+    let stdout = run_clean(&temp_dir, &["--dry-run"])?;
+    ensure_utils::contains_all(
+        &stdout,
+        &[
+            run_names[0],
+            run_names[1],
+            dry_run_name,
+            log_names[0],
+            "backup/link/empty/nested",
+        ],
+    )?;
+    ensure_eq!(false, stdout.contains(run_names[2]));
+    ensure_eq!(run_names.len() + 1, fs::read_dir(&runs_dir)?.count());
+
+    // Command-line options override the up config.
+    run_clean(&temp_dir, &["--yes", "--keep-runs", "2"])?;
+    ensure_eq!(vec![run_names[3], run_names[4]], dir_names(&runs_dir)?,);
+    // The dry run's log is the most recent log, so only one of the older logs is kept.
+    for log_name in &log_names[..3] {
+        ensure_utils::nothing_at(&log_dir.join(log_name))?;
+    }
+    ensure_utils::file(&log_dir.join(log_names[3]), "")?;
+    ensure_utils::nothing_at(&backup_dir.join("link"))?;
+    ensure_utils::file(&backup_dir.join("defaults/backup.plist"), "")?;
+
+    // Runs newer than `--keep-for` are kept even if there are too many.
+    fs::create_dir(runs_dir.join("2024-01-06T00_00_00Z"))?;
+    fs::write(runs_dir.join("2024-01-06T00_00_00Z/run_results.json"), "{}")?;
+    let stdout = run_clean(
+        &temp_dir,
+        &["--yes", "--keep-runs", "0", "--keep-for", "1d"],
+    )?;
+    ensure_eq!("", stdout);
+    ensure_eq!(3, fs::read_dir(&runs_dir)?.count());
+
+    // Without --yes, nothing is removed unless the user confirms (which they can't without a
+    // terminal).
+    let mut cmd = clean_cmd(&temp_dir)?;
+    cmd.args(["--keep-runs", "0"]);
+    cmd.assert().eprint_stdout_stderr().try_failure()?;
+    ensure_eq!(3, fs::read_dir(&runs_dir)?.count());
+
+    // Runs another up process is still doing aren't removed.
+    let lock = fs::File::create(runs_dir.join("2024-01-06T00_00_00Z/run.lock"))?;
+    lock.lock()?;
+    run_clean(&temp_dir, &["--yes", "--keep-runs", "0"])?;
+    ensure_eq!(vec!["2024-01-06T00_00_00Z"], dir_names(&runs_dir)?);
+
+    Ok(())
+}
+
+/// Run `up clean` with a fake home and state directory, returning stdout.
+fn run_clean(temp_dir: &Utf8Path, args: &[&str]) -> Result<String> {
+    let mut cmd = clean_cmd(temp_dir)?;
+    cmd.args(args);
+    let assert = cmd.assert().eprint_stdout_stderr().try_success()?;
+    Ok(String::from_utf8(assert.get_output().stdout.clone())?)
+}
+
+/// An `up clean` command with a fake home and state directory.
+fn clean_cmd(temp_dir: &Utf8Path) -> Result<assert_cmd::Command> {
+    let mut cmd = testutils::crate_binary_cmd("up", temp_dir)?;
+    cmd.env("HOME", temp_dir.join("home"));
+    cmd.env("XDG_STATE_HOME", temp_dir.join("state"));
+    cmd.args([
+        "--config",
+        temp_dir.join("up_config_dir/up.yaml").as_str(),
+        "clean",
+    ]);
+    Ok(cmd)
+}
+
+/// Where up writes logs when run by `run_clean()`.
+fn log_dir(temp_dir: &Utf8Path) -> Utf8PathBuf {
+    if cfg!(target_os = "macos") {
+        temp_dir.join("home/Library/Logs/co.fahn.up")
+    } else {
+        temp_dir.join("state/up/logs")
+    }
+}
+
+/// Sorted names of the entries in a directory.
+fn dir_names(dir: &Utf8Path) -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    for entry in dir.read_dir_utf8()? {
+        names.push(entry?.file_name().to_owned());
+    }
+    names.sort_unstable();
+    Ok(names)
+}
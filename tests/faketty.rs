@@ -0,0 +1,52 @@
+use camino::Utf8Path;
+use color_eyre::Result;
+use testutils::AssertCmdExt;
+use testutils::ensure_eq;
+use testutils::ensure_utils;
+
+/// Check that `up faketty` gives the command terminals, and exits with its exit code.
+#[test]
+fn test_faketty() -> Result<()> {
+    let temp_dir = testutils::temp_dir("up", testutils::function_path!())?;
+
+    // Stdout and stderr are terminals, stdin (not a terminal in tests) is passed through as-is.
+    let (stdout, stderr) = faketty(
+        &temp_dir,
+        &[
+            "sh",
+            "-c",
+            "[ -t 1 ] && [ -t 2 ] && [ ! -t 0 ] && read line && echo \"out $line\" && echo err >&2",
+        ],
+        Some(0),
+    )?;
+    ensure_eq!("out input\r\n", stdout);
+    ensure_utils::contains(&stderr, "err\r\n")?;
+
+    // Both go to stdout with --merge-output.
+    let (stdout, _stderr) = faketty(
+        &temp_dir,
+        &["--merge-output", "sh", "-c", "echo out && echo err >&2"],
+        Some(0),
+    )?;
+    ensure_eq!("out\r\nerr\r\n", stdout);
+
+    // Exit codes are passed through, and commands killed by signals exit with 128 + signal.
+    faketty(&temp_dir, &["sh", "-c", "exit 3"], Some(3))?;
+    faketty(&temp_dir, &["sh", "-c", "kill -TERM $$"], Some(128 + 15))?;
+
+    Ok(())
+}
+
+/// Run `up faketty` with `input` on stdin, check the exit code, and return stdout and
+/// stderr.
+fn faketty(temp_dir: &Utf8Path, args: &[&str], code: Option<i32>) -> Result<(String, String)> {
+    let mut cmd = testutils::crate_binary_cmd("up", temp_dir)?;
+    cmd.arg("faketty").args(args).write_stdin("input\n");
+    let assert = cmd.assert().eprint_stdout_stderr();
+    ensure_eq!(code, assert.get_output().status.code());
+    let output = assert.get_output();
+    Ok((
+        String::from_utf8(output.stdout.clone())?,
+        String::from_utf8(output.stderr.clone())?,
+    ))
+}
@@ -0,0 +1 @@
+nvim config
@@ -0,0 +1,62 @@
+use camino::Utf8Path;
+use color_eyre::Result;
+use testutils::AssertCmdExt;
+use testutils::ensure_eq;
+use testutils::ensure_utils;
+
+/// Check that `up graph` prints the task graph, marking bootstrap, manual, and filtered tasks,
+/// and colouring tasks by the last run with `--last-run`.
+#[test]
+fn test_up_graph() -> Result<()> {
+    let temp_dir = testutils::temp_dir("up", testutils::function_path!())?;
+    testutils::copy_all(
+        &testutils::fixtures_subdir(testutils::function_path!())?,
+        &temp_dir,
+    )?;
+
+    ensure_eq!(
+        r##"digraph up {
+  rankdir=LR;
+  node [shape=box];
+  "build" [label="build", style="rounded"];
+  "deploy" [label="deploy\nauto_run: false", style="rounded,dashed"];
+  "extra" [label="extra\nfiltered out", style="rounded", fontcolor="#9aa0a6", color="#9aa0a6"];
+  "setup" [label="setup\nbootstrap 1", style="rounded", penwidth=2];
+  "tools" [label="tools\nbootstrap 2", style="rounded", penwidth=2];
+  "tools" -> "build" [label="requires"];
+  "build" -> "deploy" [label="requires"];
+  "setup" -> "tools" [style=dashed, label="then"];
+}
+"##,
+        graph(&temp_dir, &["--exclude-tasks=extra"])?
+    );
+
+    // The fixture's last run: setup (5s) then tools (10s) then build (30s) is the slowest chain.
+    let mermaid = graph(&temp_dir, &["--format=mermaid", "--last-run"])?;
+    ensure_utils::contains_all(
+        &mermaid,
+        &[
+            "flowchart LR\n",
+            "  t0[\"build<br/>failed in 30s\"]\n",
+            "  t2[\"extra<br/>passed in 40s\"]\n",
+            "  t3 -.->|then| t4\n",
+            "  class t0,t3,t4 critical\n",
+            "  linkStyle 0 stroke:#d93025,stroke-width:4px\n",
+            "  linkStyle 2 stroke:#d93025,stroke-width:4px\n",
+        ],
+    )?;
+    Ok(())
+}
+
+/// Run `up graph` with `args`, returning what it printed.
+fn graph(temp_dir: &Utf8Path, args: &[&str]) -> Result<String> {
+    let mut cmd = testutils::crate_binary_cmd("up", temp_dir)?;
+    cmd.args([
+        "--config",
+        temp_dir.join("up_config_dir/up.yaml").as_str(),
+        "graph",
+    ])
+    .args(args);
+    let assert = cmd.assert().eprint_stdout_stderr().try_success()?;
+    Ok(String::from_utf8(assert.get_output().stdout.clone())?)
+}
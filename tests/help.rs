@@ -2,6 +2,7 @@ use camino::Utf8Path;
 use color_eyre::Result;
 use predicates::prelude::*;
 use testutils::AssertCmdExt;
+use testutils::ensure_eq;
 
 #[test]
 fn test_help_test() -> Result<()> {
@@ -23,6 +24,69 @@ fn test_version_test() -> Result<()> {
     Ok(())
 }
 
+/// Check that dynamic completions suggest task names from the config being completed, and run
+/// libraries (including external ones next to it).
+#[test]
+fn test_dynamic_completions() -> Result<()> {
+    let temp_dir = testutils::temp_dir("up", testutils::function_path!())?;
+    testutils::copy_all(
+        &testutils::fixtures_subdir(testutils::function_path!())?,
+        &temp_dir,
+    )?;
+    let config = temp_dir.join("up_config_dir/up.yaml");
+
+    ensure_eq!(
+        "link\tLink dotfiles (run_lib: link)\nrust\nrustup\tUpdate rustup\n",
+        complete(
+            &temp_dir,
+            &["up", "-c", config.as_str(), "run", "--tasks", ""]
+        )?
+    );
+    ensure_eq!(
+        "link,rust\nlink,rustup\tUpdate rustup\n",
+        complete(
+            &temp_dir,
+            &[
+                "up",
+                "--config",
+                config.as_str(),
+                "run",
+                "--exclude-tasks",
+                "link,r"
+            ]
+        )?
+    );
+    ensure_eq!(
+        "rustup\tUpdate rustup\n",
+        complete(&temp_dir, &["up", "-c", config.as_str(), "logs", "rustu"])?
+    );
+    ensure_eq!(
+        "link,generate_git\nlink,git\nlink,greet\n",
+        complete(
+            &temp_dir,
+            &["up", "-c", config.as_str(), "run", "--run-libs", "link,g"]
+        )?
+    );
+
+    // The shell code that calls back into up.
+    let mut cmd = testutils::crate_binary_cmd("up", &temp_dir)?;
+    cmd.args(["doc", "completions", "--dynamic", "fish"]);
+    cmd.assert()
+        .eprint_stdout_stderr()
+        .try_success()?
+        .try_stdout(predicate::str::contains("COMPLETE=fish"))?;
+
+    Ok(())
+}
+
+/// Ask up to complete the last of `args`, as fish would, returning the completions.
+fn complete(temp_dir: &Utf8Path, args: &[&str]) -> Result<String> {
+    let mut cmd = testutils::crate_binary_cmd("up", temp_dir)?;
+    cmd.env("COMPLETE", "fish").arg("--").args(args);
+    let assert = cmd.assert().eprint_stdout_stderr().try_success()?;
+    Ok(String::from_utf8(assert.get_output().stdout.clone())?)
+}
+
 fn check_help(arg: &str, temp_dir: &Utf8Path) -> Result<()> {
     let mut cmd = testutils::crate_binary_cmd("up", temp_dir)?;
     cmd.arg(arg);
@@ -2,9 +2,14 @@ use assert_cmd::assert::Assert;
 use camino::Utf8Path;
 use camino::Utf8PathBuf;
 use color_eyre::Result;
+use color_eyre::eyre::ensure;
+use color_eyre::eyre::eyre;
 use std::fs;
 use std::fs::File;
 use std::os::unix;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::fs::PermissionsExt;
+use testutils::ensure_eq;
 use testutils::ensure_utils;
 
 /// Set up a basic `home_dir`, run the link function against it, and make sure we
@@ -15,7 +20,7 @@ fn test_new_link() -> Result<()> {
         get_home_dotfile_dirs(testutils::function_path!())?;
     // Create empty dir (can't check in as git doesn't store dirs without contents.
     fs::create_dir(home_dir.join("existing_dir")).unwrap();
-    run_link_cmd(&dotfile_dir, &home_dir, &temp_dir, LinkResult::Success)?;
+    run_link_cmd(&dotfile_dir, &home_dir, &temp_dir, &[], LinkResult::Success)?;
 
     // Existing files shouldn't be touched.
     ensure_utils::file(&home_dir.join("existing_file"), "existing file 1\n")?;
@@ -37,12 +42,14 @@ fn test_new_link() -> Result<()> {
 fn test_backup_files() -> Result<()> {
     let (home_dir, dotfile_dir, backup_dir, temp_dir) =
         get_home_dotfile_dirs(testutils::function_path!())?;
-    run_link_cmd(&dotfile_dir, &home_dir, &temp_dir, LinkResult::Success)?;
+    run_link_cmd(&dotfile_dir, &home_dir, &temp_dir, &[], LinkResult::Success)?;
 
     // Backup dir should stay.
     ensure_utils::dir(&backup_dir)?;
-    // Files in backup should be overwritten with the new backups.
-    ensure_utils::file(&backup_dir.join("already_in_backup"), "new backup\n")?;
+    let run_dir = run_backup_dir(&backup_dir)?;
+    // Files backed up by earlier runs should be kept, and the new backups made in this run's dir.
+    ensure_utils::file(&backup_dir.join("already_in_backup"), "previous backup\n")?;
+    ensure_utils::file(&run_dir.join("already_in_backup"), "new backup\n")?;
     // Symlinks in home should be overwritten.
     ensure_utils::link(
         &home_dir.join("existing_symlink"),
@@ -54,7 +61,7 @@ fn test_backup_files() -> Result<()> {
         &dotfile_dir.join("already_in_backup"),
     )?;
     // Symlinks in home should not be moved to backup.
-    ensure_utils::nothing_at(&backup_dir.join("existing_symlink"))?;
+    ensure_utils::nothing_at(&run_dir.join("existing_symlink"))?;
 
     // Existing subdir backup files should not be overwritten.
     ensure_utils::file(
@@ -68,7 +75,7 @@ fn test_backup_files() -> Result<()> {
     )?;
     // Subdirectory files should be moved to backup.
     ensure_utils::file(
-        &backup_dir.join("subdir/new_subdir_file"),
+        &run_dir.join("subdir/new_subdir_file"),
         "previous subdir file\n",
     )?;
     // Subdirectory files should be added into existing directories.
@@ -79,7 +86,7 @@ fn test_backup_files() -> Result<()> {
 
     // Nested subdirectory files should be moved to backup.
     ensure_utils::file(
-        &backup_dir.join("subdir/subdir2/subdir2_file"),
+        &run_dir.join("subdir/subdir2/subdir2_file"),
         "old subdir2 file\n",
     )?;
     // Nested subdirectory files should be added into existing directories.
@@ -91,6 +98,59 @@ fn test_backup_files() -> Result<()> {
     Ok(())
 }
 
+/// Make sure `up link backups` lists each run's backups, and `up link restore` puts them back
+/// (by path, or for a whole run).
+#[test]
+fn test_link_backups_restore() -> Result<()> {
+    use testutils::AssertCmdExt;
+
+    let (home_dir, dotfile_dir, backup_dir, temp_dir) =
+        get_home_dotfile_dirs(testutils::function_path!())?;
+    let link_cmd = |args: &[&str]| -> Result<Assert> {
+        let mut cmd = testutils::crate_binary_cmd("up", &temp_dir)?;
+        cmd.arg("link").args(args);
+        Ok(cmd.assert().eprint_stdout_stderr().try_success()?)
+    };
+    let stderr = run_link_cmd(&dotfile_dir, &home_dir, &temp_dir, &[], LinkResult::Success)?
+        .get_output()
+        .stderr
+        .clone();
+    ensure_utils::contains(&String::from_utf8_lossy(&stderr), "Backed up 3 files to ")?;
+    let run_dir = run_backup_dir(&backup_dir)?;
+    let run_id = run_dir.file_name().unwrap_or_default();
+    ensure_utils::file(&run_dir.join(".bashrc"), "old bashrc\n")?;
+
+    let assert = link_cmd(&["backups"])?;
+    let stdout = String::from_utf8_lossy(&assert.get_output().stdout);
+    ensure_utils::contains_all(
+        &stdout,
+        &[
+            &format!("{run_id}:"),
+            &format!("{home_dir}/.bashrc (file)"),
+            &format!("{home_dir}/.config/app (directory)"),
+            &format!("{home_dir}/.config/other (file)"),
+        ],
+    )?;
+
+    // A dry run shouldn't change anything.
+    link_cmd(&["restore", "--dry-run", home_dir.join(".bashrc").as_str()])?;
+    ensure_utils::link(&home_dir.join(".bashrc"), &dotfile_dir.join(".bashrc"))?;
+
+    // Restoring a path should only restore backups at or inside it.
+    link_cmd(&["restore", home_dir.join(".config").as_str()])?;
+    ensure_utils::file(&home_dir.join(".config/app/old.toml"), "old app config\n")?;
+    ensure_utils::file(&home_dir.join(".config/other"), "old other\n")?;
+    ensure_utils::link(&home_dir.join(".bashrc"), &dotfile_dir.join(".bashrc"))?;
+
+    // Restoring a run (by a prefix of its ID) should restore everything else backed up in it.
+    let run_prefix = run_id.get(..10).unwrap_or(run_id);
+    link_cmd(&["restore", run_prefix])?;
+    ensure_utils::file(&home_dir.join(".bashrc"), "old bashrc\n")?;
+    ensure_utils::nothing_at(&backup_dir)?;
+
+    Ok(())
+}
+
 #[test]
 fn test_hidden_and_nested() -> Result<()> {
     let (home_dir, dotfile_dir, backup_dir, temp_dir) =
@@ -101,12 +161,13 @@ fn test_hidden_and_nested() -> Result<()> {
         home_dir.join("existing_link"),
     )
     .unwrap();
-    run_link_cmd(&dotfile_dir, &home_dir, &temp_dir, LinkResult::Success)?;
+    run_link_cmd(&dotfile_dir, &home_dir, &temp_dir, &[], LinkResult::Success)?;
 
     // Backup dir should stay.
     ensure_utils::dir(&backup_dir)?;
+    let run_dir = run_backup_dir(&backup_dir)?;
     // Hidden files/dirs should still be moved to backup.
-    ensure_utils::file(&backup_dir.join(".config/.file"), "old file\n")?;
+    ensure_utils::file(&run_dir.join(".config/.file"), "old file\n")?;
     // Hidden files/dirs should still be linked to.
     ensure_utils::link(
         &home_dir.join(".config/.file"),
@@ -133,10 +194,7 @@ fn test_hidden_and_nested() -> Result<()> {
     )?;
     // Files inside directories that are converted to file links should be moved to
     // backup.
-    ensure_utils::file(
-        &backup_dir.join("dir_to_file/file"),
-        "dir_to_file dir file\n",
-    )?;
+    ensure_utils::file(&run_dir.join("dir_to_file/file"), "dir_to_file dir file\n")?;
     // Files should be overwritten with directories containing file links.
     ensure_utils::dir(&home_dir.join("file_to_dir"))?;
     // Links should be inserted inside directories that overwrite files.
@@ -145,10 +203,7 @@ fn test_hidden_and_nested() -> Result<()> {
         &dotfile_dir.join("file_to_dir/file2"),
     )?;
     // Files that are converted to directories should be moved to backup.
-    ensure_utils::file(
-        &backup_dir.join("file_to_dir"),
-        "file_to_dir original file\n",
-    )?;
+    ensure_utils::file(&run_dir.join("file_to_dir"), "file_to_dir original file\n")?;
 
     // Directories should overwrite links.
     ensure_utils::dir(&home_dir.join("link_to_dir"))?;
@@ -158,7 +213,7 @@ fn test_hidden_and_nested() -> Result<()> {
         &dotfile_dir.join("link_to_dir/file3"),
     )?;
     // Links that are converted to directories should not be moved to backup.
-    ensure_utils::nothing_at(&backup_dir.join("link_to_dir"))?;
+    ensure_utils::nothing_at(&run_dir.join("link_to_dir"))?;
 
     // Directories should overwrite bad links.
     ensure_utils::dir(&home_dir.join("badlink_to_dir"))?;
@@ -168,7 +223,667 @@ fn test_hidden_and_nested() -> Result<()> {
         &dotfile_dir.join("badlink_to_dir/file4"),
     )?;
     // Links that are converted to directories should not be moved to backup.
-    ensure_utils::nothing_at(&backup_dir.join("badlink_to_dir"))?;
+    ensure_utils::nothing_at(&run_dir.join("badlink_to_dir"))?;
+
+    Ok(())
+}
+
+/// Make sure files matching the `.upignore` file or `--ignore` patterns aren't linked, and
+/// that a dry run lists the ignored files without changing anything.
+#[test]
+fn test_link_ignore() -> Result<()> {
+    let (home_dir, dotfile_dir, backup_dir, temp_dir) =
+        get_home_dotfile_dirs(testutils::function_path!())?;
+    // Can't check in a `.git` dir inside the repo, so create it here.
+    fs::create_dir(dotfile_dir.join(".git")).unwrap();
+    fs::write(dotfile_dir.join(".git/HEAD"), "ref: refs/heads/main\n").unwrap();
+    let ignore_args = ["--ignore=.git/,notes.txt"];
+
+    let assert = run_link_cmd(
+        &dotfile_dir,
+        &home_dir,
+        &temp_dir,
+        &["--dry-run", ignore_args[0]],
+        LinkResult::Success,
+    )?;
+    ensure_utils::contains_all(
+        &String::from_utf8_lossy(&assert.get_output().stderr),
+        &[
+            "Ignoring .git/ (matched `.git/` in ignore option)",
+            "Ignoring notes.txt (matched `notes.txt` in ignore option)",
+            "Ignoring README.md (matched `README.md` in ",
+            "Ignoring .github/ (matched `.github/` in ",
+            "Ignoring .bashrc.swp (matched `*.swp` in ",
+            "Ignoring .upignore (matched `/.upignore` in ignore option)",
+            "Would change symlink ",
+        ],
+    )?;
+    // Nothing should change in a dry run.
+    ensure_utils::file(&home_dir.join(".bashrc"), "existing bashrc\n")?;
+    ensure_utils::nothing_at(&home_dir.join(".config"))?;
+    ensure_utils::nothing_at(&backup_dir)?;
+
+    run_link_cmd(
+        &dotfile_dir,
+        &home_dir,
+        &temp_dir,
+        &ignore_args,
+        LinkResult::Success,
+    )?;
+    ensure_utils::link(&home_dir.join(".bashrc"), &dotfile_dir.join(".bashrc"))?;
+    ensure_utils::link(
+        &home_dir.join(".config/nvim/init.lua"),
+        &dotfile_dir.join(".config/nvim/init.lua"),
+    )?;
+    // Negated patterns should still be linked.
+    ensure_utils::link(&home_dir.join("keep.swp"), &dotfile_dir.join("keep.swp"))?;
+    for ignored in [
+        ".git",
+        ".github",
+        ".upignore",
+        ".bashrc.swp",
+        "README.md",
+        "notes.txt",
+    ] {
+        ensure_utils::nothing_at(&home_dir.join(ignored))?;
+    }
+
+    Ok(())
+}
+
+/// Make sure that with `--tracked-only` only files in the git index (or in submodules) are
+/// linked, and that we warn about tracked files with uncommitted changes.
+#[test]
+fn test_link_tracked_only() -> Result<()> {
+    let (home_dir, dotfile_dir, _backup_dir, temp_dir) =
+        get_home_dotfile_dirs(testutils::function_path!())?;
+    // Can't check in a git repo inside the repo, so create it here.
+    fs::write(dotfile_dir.join(".gitignore"), "secret.env\n").unwrap();
+    fs::write(dotfile_dir.join("secret.env"), "TOKEN=hunter2\n").unwrap();
+    run_git_cmd(&dotfile_dir, &["init", "--quiet"])?;
+    // A submodule, whose files are tracked in its own repo.
+    let submodule_dir = dotfile_dir.join("submodule");
+    fs::create_dir(&submodule_dir).unwrap();
+    fs::write(submodule_dir.join("plugin"), "plugin\n").unwrap();
+    run_git_cmd(&submodule_dir, &["init", "--quiet"])?;
+    run_git_cmd(&submodule_dir, &["add", "plugin"])?;
+    run_git_cmd(
+        &submodule_dir,
+        &[
+            "-c",
+            "user.name=up",
+            "-c",
+            "user.email=up@example.com",
+            "commit",
+            "--quiet",
+            "--message=Add plugin",
+        ],
+    )?;
+    run_git_cmd(
+        &dotfile_dir,
+        &[
+            "add",
+            "tracked_file",
+            "modified_file",
+            "nested/tracked_file",
+            "submodule",
+        ],
+    )?;
+    fs::write(dotfile_dir.join("modified_file"), "uncommitted change\n").unwrap();
+
+    let assert = run_link_cmd(
+        &dotfile_dir,
+        &home_dir,
+        &temp_dir,
+        &["--tracked-only"],
+        LinkResult::Success,
+    )?;
+    ensure_utils::contains_all(
+        &String::from_utf8_lossy(&assert.get_output().stderr),
+        &[
+            "Tracked file has uncommitted changes, linking it anyway: ",
+            "/modified_file",
+        ],
+    )?;
+
+    for tracked in [
+        "tracked_file",
+        "modified_file",
+        "nested/tracked_file",
+        "submodule/plugin",
+    ] {
+        ensure_utils::link(&home_dir.join(tracked), &dotfile_dir.join(tracked))?;
+    }
+    for untracked in [
+        ".git",
+        "submodule/.git",
+        ".gitignore",
+        "secret.env",
+        "untracked_file",
+        "nested/untracked_file",
+    ] {
+        ensure_utils::nothing_at(&home_dir.join(untracked))?;
+    }
+
+    Ok(())
+}
+
+/// Make sure the link strategy and per-glob overrides are used, and that copies are kept in sync
+/// with their sources, backing up copies that were edited locally.
+#[test]
+fn test_link_strategy() -> Result<()> {
+    let (home_dir, dotfile_dir, backup_dir, temp_dir) =
+        get_home_dotfile_dirs(testutils::function_path!())?;
+    let args = [
+        "--strategy=relative-symlink",
+        "--strategy-override=*.service=copy",
+        "--strategy-override=.ssh/config=hardlink",
+    ];
+    run_link_cmd(
+        &dotfile_dir,
+        &home_dir,
+        &temp_dir,
+        &args,
+        LinkResult::Success,
+    )?;
+
+    ensure_utils::link(
+        &home_dir.join(".bashrc"),
+        Utf8Path::new("../dotfile_dir/.bashrc"),
+    )?;
+    ensure_utils::link(
+        &home_dir.join(".config/app/settings.toml"),
+        Utf8Path::new("../../../dotfile_dir/.config/app/settings.toml"),
+    )?;
+    ensure_utils::file(&home_dir.join("units/up.service"), "unit v1\n")?;
+    ensure_eq!(
+        fs::metadata(dotfile_dir.join(".ssh/config"))?.ino(),
+        fs::metadata(home_dir.join(".ssh/config"))?.ino()
+    );
+
+    // Copies of changed sources should be updated.
+    fs::write(dotfile_dir.join("units/up.service"), "unit v2\n")?;
+    run_link_cmd(
+        &dotfile_dir,
+        &home_dir,
+        &temp_dir,
+        &args,
+        LinkResult::Success,
+    )?;
+    ensure_utils::file(&home_dir.join("units/up.service"), "unit v2\n")?;
+    ensure_utils::nothing_at(&backup_dir)?;
+
+    // Copies that were edited locally should be backed up before being updated.
+    fs::write(home_dir.join("units/up.service"), "local edit\n")?;
+    fs::write(dotfile_dir.join("units/up.service"), "unit v3\n")?;
+    let assert = run_link_cmd(
+        &dotfile_dir,
+        &home_dir,
+        &temp_dir,
+        &args,
+        LinkResult::Success,
+    )?;
+    ensure_utils::contains(
+        &String::from_utf8_lossy(&assert.get_output().stderr),
+        "has been edited since it was copied",
+    )?;
+    ensure_utils::file(&home_dir.join("units/up.service"), "unit v3\n")?;
+    ensure_utils::file(
+        &run_backup_dir(&backup_dir)?.join("units/up.service"),
+        "local edit\n",
+    )?;
+
+    Ok(())
+}
+
+/// Make sure links to files removed from `from_dir` are pruned (after a dry run preview), unless
+/// `--no-prune` is passed, and that broken links we didn't make are left alone.
+#[test]
+fn test_link_prune() -> Result<()> {
+    let (home_dir, dotfile_dir, _backup_dir, temp_dir) =
+        get_home_dotfile_dirs(testutils::function_path!())?;
+    run_link_cmd(&dotfile_dir, &home_dir, &temp_dir, &[], LinkResult::Success)?;
+    ensure_utils::link(
+        &home_dir.join(".config/old"),
+        &dotfile_dir.join(".config/old"),
+    )?;
+    // A broken link into from_dir that up link didn't make.
+    unix::fs::symlink(dotfile_dir.join("missing"), home_dir.join("not_ours"))?;
+    fs::remove_file(dotfile_dir.join(".config/old"))?;
+
+    let assert = run_link_cmd(
+        &dotfile_dir,
+        &home_dir,
+        &temp_dir,
+        &["--dry-run"],
+        LinkResult::Success,
+    )?;
+    ensure_utils::contains(
+        &String::from_utf8_lossy(&assert.get_output().stderr),
+        &format!("Would change symlink {home_dir}/.config/old: "),
+    )?;
+    ensure_eq!(
+        dotfile_dir.join(".config/old"),
+        home_dir.join(".config/old").read_link_utf8()?
+    );
+
+    run_link_cmd(
+        &dotfile_dir,
+        &home_dir,
+        &temp_dir,
+        &["--no-prune"],
+        LinkResult::Success,
+    )?;
+    ensure_eq!(
+        dotfile_dir.join(".config/old"),
+        home_dir.join(".config/old").read_link_utf8()?
+    );
+
+    run_link_cmd(&dotfile_dir, &home_dir, &temp_dir, &[], LinkResult::Success)?;
+    ensure_utils::nothing_at(&home_dir.join(".config/old"))?;
+    ensure_utils::link(
+        &home_dir.join(".config/keep"),
+        &dotfile_dir.join(".config/keep"),
+    )?;
+    ensure_eq!(
+        dotfile_dir.join("missing"),
+        home_dir.join("not_ours").read_link_utf8()?
+    );
+
+    Ok(())
+}
+
+/// Make sure directories are linked as a unit with `--fold` (or a `.upfold` marker), and unfolded
+/// when another source links into them, or they're linked without folding.
+#[test]
+fn test_link_fold() -> Result<()> {
+    use testutils::AssertCmdExt;
+
+    let (home_dir, dotfile_dir, backup_dir, temp_dir) =
+        get_home_dotfile_dirs(testutils::function_path!())?;
+    let dotfile2_dir = temp_dir.join("dotfile2_dir").canonicalize_utf8()?;
+    let nvim_dir = home_dir.join(".config/nvim");
+
+    run_link_cmd(
+        &dotfile_dir,
+        &home_dir,
+        &temp_dir,
+        &["--fold"],
+        LinkResult::Success,
+    )?;
+    ensure_utils::link(&nvim_dir, &dotfile_dir.join(".config/nvim"))?;
+    ensure_utils::link(&home_dir.join(".vim"), &dotfile_dir.join(".vim"))?;
+    ensure_utils::file(
+        &run_backup_dir(&backup_dir)?.join(".vim/old_vimrc"),
+        "\" old vimrc\n",
+    )?;
+    ensure_utils::file(&home_dir.join(".config/other_app/config"), "other\n")?;
+
+    // Files added to a folded directory since are only linked when unfolding it if they would be
+    // without folding.
+    let nvim_source_dir = dotfile_dir.join(".config/nvim");
+    fs::write(nvim_source_dir.join(".init.lua.swp"), "swap\n")?;
+    fs::write(nvim_source_dir.join("notes.md.tmpl"), "notes\n")?;
+
+    // A second source linking into a folded directory should unfold it.
+    let assert = run_link_cmd(
+        &dotfile2_dir,
+        &home_dir,
+        &temp_dir,
+        &["--fold"],
+        LinkResult::Success,
+    )?;
+    ensure_utils::contains(
+        &String::from_utf8_lossy(&assert.get_output().stderr),
+        &format!("Unfolding {nvim_dir}, which linked to {dotfile_dir}/.config/nvim"),
+    )?;
+    ensure_utils::dir(&nvim_dir)?;
+    ensure_utils::link(
+        &nvim_dir.join("init.lua"),
+        &dotfile_dir.join(".config/nvim/init.lua"),
+    )?;
+    ensure_utils::link(&nvim_dir.join("lua"), &dotfile_dir.join(".config/nvim/lua"))?;
+    ensure_utils::link(
+        &nvim_dir.join("local.lua"),
+        &dotfile2_dir.join(".config/nvim/local.lua"),
+    )?;
+    ensure_utils::nothing_at(&nvim_dir.join(".init.lua.swp"))?;
+    ensure_utils::nothing_at(&nvim_dir.join("notes.md.tmpl"))?;
+    ensure_utils::nothing_at(&nvim_dir.join("notes.md"))?;
+
+    // A dry run should report the links that would be made inside a directory it would unfold,
+    // rather than the files seen through the directory's link, without changing anything.
+    let assert = run_link_cmd(
+        &dotfile_dir,
+        &home_dir,
+        &temp_dir,
+        &["--dry-run"],
+        LinkResult::Success,
+    )?;
+    let stderr = String::from_utf8_lossy(&assert.get_output().stderr);
+    let lua_dir = nvim_dir.join("lua");
+    let plugins_source = dotfile_dir.join(".config/nvim/lua/plugins.lua");
+    ensure_utils::contains_all(
+        &stderr,
+        &[
+            &format!("Would change unfold {lua_dir}: "),
+            &format!("Would change symlink {lua_dir}/plugins.lua: {plugins_source}"),
+        ],
+    )?;
+    ensure_eq!(false, stderr.contains("backed up to"));
+    ensure_utils::link(&lua_dir, &dotfile_dir.join(".config/nvim/lua"))?;
+
+    // Linking without folding should unfold our folded directories, but not marked ones.
+    run_link_cmd(&dotfile_dir, &home_dir, &temp_dir, &[], LinkResult::Success)?;
+    ensure_utils::dir(&nvim_dir.join("lua"))?;
+    ensure_utils::link(
+        &nvim_dir.join("lua/plugins.lua"),
+        &dotfile_dir.join(".config/nvim/lua/plugins.lua"),
+    )?;
+    ensure_utils::link(&home_dir.join(".vim"), &dotfile_dir.join(".vim"))?;
+    ensure_utils::file(&nvim_dir.join("notes.md"), "notes\n")?;
+
+    // Unlinking should remove the folded directory link, and restore what it replaced.
+    testutils::crate_binary_cmd("up", &temp_dir)?
+        .args([
+            "unlink",
+            "--from",
+            dotfile_dir.as_str(),
+            "--to",
+            home_dir.as_str(),
+        ])
+        .assert()
+        .eprint_stdout_stderr()
+        .try_success()?;
+    ensure_utils::file(&home_dir.join(".vim/old_vimrc"), "\" old vimrc\n")?;
+    ensure_utils::file(&dotfile_dir.join(".vim/vimrc"), "\" vimrc\n")?;
+    ensure_utils::nothing_at(&nvim_dir.join("init.lua"))?;
+    ensure_utils::link(
+        &nvim_dir.join("local.lua"),
+        &dotfile2_dir.join(".config/nvim/local.lua"),
+    )?;
+
+    Ok(())
+}
+
+/// Make sure permission rules set the mode of sources, copies, and created directories (after a
+/// dry run preview), and that sensitive files without a rule are reported.
+#[test]
+fn test_link_permissions() -> Result<()> {
+    let (home_dir, dotfile_dir, _backup_dir, temp_dir) =
+        get_home_dotfile_dirs(testutils::function_path!())?;
+    let mode =
+        |path: &Utf8Path| -> Result<u32> { Ok(fs::metadata(path)?.permissions().mode() & 0o7777) };
+    // Git doesn't keep modes, so set them here.
+    for file in [".ssh/config", ".ssh/id_ed25519", ".netrc"] {
+        fs::set_permissions(dotfile_dir.join(file), fs::Permissions::from_mode(0o644))?;
+    }
+    let args = [
+        "--permission=.ssh=700",
+        "--permission=.ssh/config=600",
+        "--permission=.netrc=600",
+        "--strategy-override=.netrc=copy",
+    ];
+
+    let dry_run_args = [args.as_slice(), &["--dry-run"]].concat();
+    let assert = run_link_cmd(
+        &dotfile_dir,
+        &home_dir,
+        &temp_dir,
+        &dry_run_args,
+        LinkResult::Success,
+    )?;
+    ensure_utils::contains(
+        &String::from_utf8_lossy(&assert.get_output().stderr),
+        &format!("Would change permissions {dotfile_dir}/.ssh/config: 644 -> 600"),
+    )?;
+    ensure_eq!(0o644, mode(&dotfile_dir.join(".ssh/config"))?);
+
+    let assert = run_link_cmd(
+        &dotfile_dir,
+        &home_dir,
+        &temp_dir,
+        &args,
+        LinkResult::Success,
+    )?;
+    ensure_utils::contains_all(
+        &String::from_utf8_lossy(&assert.get_output().stderr),
+        &[
+            &format!("{dotfile_dir}/.ssh/config is more permissive (mode 644) than its rule"),
+            &format!("{dotfile_dir}/.ssh/id_ed25519 looks sensitive"),
+        ],
+    )?;
+    ensure_eq!(0o600, mode(&dotfile_dir.join(".ssh/config"))?);
+    ensure_eq!(0o600, mode(&dotfile_dir.join(".netrc"))?);
+    ensure_eq!(0o600, mode(&home_dir.join(".netrc"))?);
+    ensure_eq!(0o700, mode(&home_dir.join(".ssh"))?);
+    ensure_eq!(0o644, mode(&dotfile_dir.join(".ssh/id_ed25519"))?);
+
+    Ok(())
+}
+
+/// Make sure files are linked from every layer, that the conflict policy picks which layer a file
+/// they share is linked from, and that `--explain` says where a target comes from.
+#[test]
+fn test_link_layers() -> Result<()> {
+    let (home_dir, dotfile_dir, _backup_dir, temp_dir) =
+        get_home_dotfile_dirs(testutils::function_path!())?;
+    let dotfile2_dir = temp_dir.join("dotfile2_dir").canonicalize_utf8()?;
+    let run = |args: &[&str], result: LinkResult| {
+        let mut all_args = vec!["--from", dotfile2_dir.as_str()];
+        all_args.extend_from_slice(args);
+        run_link_cmd(&dotfile_dir, &home_dir, &temp_dir, &all_args, result)
+    };
+
+    // Later layers win by default, and directories both layers link into aren't folded.
+    run(&["--fold"], LinkResult::Success)?;
+    ensure_utils::link(
+        &home_dir.join(".gitconfig"),
+        &dotfile2_dir.join(".gitconfig"),
+    )?;
+    ensure_utils::link(&home_dir.join(".bashrc"), &dotfile_dir.join(".bashrc"))?;
+    ensure_utils::link(&home_dir.join(".vimrc"), &dotfile_dir.join(".vimrc"))?;
+    ensure_utils::dir(&home_dir.join(".config/app"))?;
+    ensure_utils::link(
+        &home_dir.join(".config/app/config"),
+        &dotfile_dir.join(".config/app/config"),
+    )?;
+    ensure_utils::link(
+        &home_dir.join(".config/app/local"),
+        &dotfile2_dir.join(".config/app/local"),
+    )?;
+
+    run(&["--conflicts=first-wins"], LinkResult::Success)?;
+    ensure_utils::link(
+        &home_dir.join(".gitconfig"),
+        &dotfile_dir.join(".gitconfig"),
+    )?;
+
+    let assert = run(&["--conflicts=error"], LinkResult::Failure)?;
+    ensure_utils::contains(
+        &String::from_utf8_lossy(&assert.get_output().stderr),
+        &format!(".gitconfig: {dotfile_dir} and {dotfile2_dir}"),
+    )?;
+    ensure_utils::link(
+        &home_dir.join(".gitconfig"),
+        &dotfile_dir.join(".gitconfig"),
+    )?;
+
+    let assert = run(&["--explain", ".gitconfig"], LinkResult::Success)?;
+    let stdout = String::from_utf8_lossy(&assert.get_output().stdout);
+    ensure_utils::contains(
+        &stdout,
+        &format!(
+            "{home_dir}/.gitconfig is linked from {dotfile2_dir}/.gitconfig (layer 2 of 2: \
+             {dotfile2_dir})."
+        ),
+    )?;
+    ensure_utils::contains(
+        &stdout,
+        &format!(
+            "Layer 1 ({dotfile_dir}) also has {dotfile_dir}/.gitconfig, not used (conflicts: \
+             last-wins)."
+        ),
+    )?;
+    // Explaining doesn't link anything.
+    ensure_utils::link(
+        &home_dir.join(".gitconfig"),
+        &dotfile_dir.join(".gitconfig"),
+    )?;
+
+    let assert = run(
+        &["--explain", home_dir.join(".vimrc").as_str()],
+        LinkResult::Success,
+    )?;
+    ensure_utils::contains(
+        &String::from_utf8_lossy(&assert.get_output().stdout),
+        &format!(
+            "Layer 2 ({dotfile2_dir}) has {dotfile2_dir}/.vimrc (matched `.vimrc` in \
+             {dotfile2_dir}/.upignore)."
+        ),
+    )?;
+
+    Ok(())
+}
+
+/// Make sure `.tmpl` files are rendered (with the env and machine facts) to the path without the
+/// suffix, and only re-rendered when the output would change.
+#[test]
+fn test_link_template() -> Result<()> {
+    use testutils::AssertCmdExt;
+
+    let (home_dir, dotfile_dir, backup_dir, temp_dir) =
+        get_home_dotfile_dirs(testutils::function_path!())?;
+    let link_with_email = |email: &str| -> Result<String> {
+        let mut cmd = testutils::crate_binary_cmd("up", &temp_dir)?;
+        cmd.args([
+            "link",
+            "--from",
+            dotfile_dir.as_str(),
+            "--to",
+            home_dir.as_str(),
+        ])
+        .env("GIT_EMAIL", email)
+        .env("SAFE_DIRS", "/src/a:/src/b")
+        .env_remove("GIT_SIGNING_KEY");
+        let assert = cmd.assert().eprint_stdout_stderr().try_success()?;
+        Ok(String::from_utf8_lossy(&assert.get_output().stderr).into_owned())
+    };
+    let rendered = |email: &str| {
+        format!(
+            "# Rendered for {os}.\n[user]\n  email = {email}\n\n[safe]\n  directory = \
+             /src/a\n[safe]\n  directory = /src/b\n",
+            os = std::env::consts::OS
+        )
+    };
+
+    // The rendered file gets the template's mode.
+    fs::set_permissions(
+        dotfile_dir.join(".gitconfig.tmpl"),
+        fs::Permissions::from_mode(0o600),
+    )?;
+    let stderr = link_with_email("me@example.com")?;
+    ensure_utils::contains(&stderr, "Linking (template)")?;
+    ensure_utils::file(&home_dir.join(".gitconfig"), &rendered("me@example.com"))?;
+    ensure_eq!(
+        0o600,
+        fs::metadata(home_dir.join(".gitconfig"))?
+            .permissions()
+            .mode()
+            & 0o7777
+    );
+    ensure_utils::nothing_at(&home_dir.join(".gitconfig.tmpl"))?;
+    ensure_utils::link(&home_dir.join("plain"), &dotfile_dir.join("plain"))?;
+
+    // Nothing should be re-rendered if the output wouldn't change.
+    let stderr = link_with_email("me@example.com")?;
+    ensure!(
+        !stderr.contains("Linking (template)"),
+        "Template shouldn't have been re-rendered."
+    );
+
+    // The output should be updated (without a backup) when the env changes.
+    link_with_email("me@work.example.com")?;
+    ensure_utils::file(
+        &home_dir.join(".gitconfig"),
+        &rendered("me@work.example.com"),
+    )?;
+    ensure_utils::nothing_at(&backup_dir)?;
+
+    // A file and a template for the same path is an error, rather than each replacing the other.
+    fs::write(dotfile_dir.join(".gitconfig"), "[user]\n")?;
+    let mut cmd = testutils::crate_binary_cmd("up", &temp_dir)?;
+    cmd.args([
+        "link",
+        "--from",
+        dotfile_dir.as_str(),
+        "--to",
+        home_dir.as_str(),
+    ]);
+    let assert = cmd.assert().eprint_stdout_stderr().try_failure()?;
+    ensure_utils::contains(
+        &String::from_utf8_lossy(&assert.get_output().stderr),
+        "would be linked to .gitconfig, remove (or ignore) one of them.",
+    )?;
+
+    Ok(())
+}
+
+/// Make sure `up unlink` removes our links (optionally only some of them), and restores the
+/// files that were backed up when linking.
+#[test]
+fn test_unlink() -> Result<()> {
+    use testutils::AssertCmdExt;
+
+    let (home_dir, dotfile_dir, backup_dir, temp_dir) =
+        get_home_dotfile_dirs(testutils::function_path!())?;
+    let unlink = |args: &[&str]| -> Result<String> {
+        let mut cmd = testutils::crate_binary_cmd("up", &temp_dir)?;
+        cmd.args([
+            "unlink",
+            "--from",
+            dotfile_dir.as_str(),
+            "--to",
+            home_dir.as_str(),
+        ])
+        .args(args);
+        let assert = cmd.assert().eprint_stdout_stderr().try_success()?;
+        Ok(String::from_utf8_lossy(&assert.get_output().stderr).into_owned())
+    };
+    run_link_cmd(&dotfile_dir, &home_dir, &temp_dir, &[], LinkResult::Success)?;
+    ensure_utils::file(
+        &run_backup_dir(&backup_dir)?.join(".bashrc"),
+        "old bashrc\n",
+    )?;
+
+    // A dry run shouldn't change anything.
+    let stderr = unlink(&["--dry-run"])?;
+    ensure_utils::contains_all(
+        &stderr,
+        &[
+            "Would change symlink ",
+            "/home_dir/.bashrc: ",
+            "-> restored from ",
+            "Would change directory ",
+        ],
+    )?;
+    ensure_utils::link(&home_dir.join(".bashrc"), &dotfile_dir.join(".bashrc"))?;
+
+    // Only links inside the paths passed should be removed.
+    unlink(&[".config"])?;
+    ensure_utils::nothing_at(&home_dir.join(".config/nvim/init.lua"))?;
+    ensure_utils::link(&home_dir.join(".bashrc"), &dotfile_dir.join(".bashrc"))?;
+
+    // Links to dotfiles that have since been removed should still be removed.
+    fs::remove_file(dotfile_dir.join("file_to_dir/file2"))?;
+    let stderr = unlink(&["--dry-run"])?;
+    ensure_utils::contains(&stderr, "/home_dir/file_to_dir/file2: ")?;
+    unlink(&[])?;
+    ensure_utils::nothing_at(&home_dir.join("file_to_dir/file2"))?;
+    ensure_utils::file(&home_dir.join(".bashrc"), "old bashrc\n")?;
+    ensure_utils::file(&home_dir.join("file_to_dir"), "file_to_dir original file\n")?;
+    ensure_utils::file(&home_dir.join("existing_file"), "existing file\n")?;
+    ensure_utils::nothing_at(&backup_dir)?;
 
     Ok(())
 }
@@ -181,6 +896,7 @@ fn test_missing_from_dir() -> Result<()> {
         &temp_dir.join("dotfile_dir"),
         &temp_dir.join("home_dir"),
         &temp_dir,
+        &[],
         LinkResult::Failure,
     )?;
     ensure_utils::contains_all(
@@ -204,6 +920,7 @@ fn test_missing_to_dir() -> Result<()> {
         &temp_dir.join("dotfile_dir"),
         &temp_dir.join("home_dir"),
         &temp_dir,
+        &[],
         LinkResult::Failure,
     )?;
     ensure_utils::contains_all(
@@ -231,6 +948,7 @@ fn test_uncreateable_backup_dir() -> Result<()> {
         &temp_dir.join("dotfile_dir"),
         &temp_dir.join("home_dir"),
         &temp_dir,
+        &[],
         LinkResult::Failure,
     )?;
     ensure_utils::contains_all(
@@ -263,6 +981,22 @@ fn get_home_dotfile_dirs(
     ))
 }
 
+/// The backup directory of the latest `up link` run that backed something up.
+fn run_backup_dir(backup_dir: &Utf8Path) -> Result<Utf8PathBuf> {
+    let mut run_dirs = backup_dir
+        .read_dir_utf8()?
+        .map(|entry| Ok(entry?.into_path()))
+        .filter(|path: &Result<Utf8PathBuf>| {
+            path.as_ref()
+                .is_ok_and(|path| path.join("manifest.json").is_file())
+        })
+        .collect::<Result<Vec<_>>>()?;
+    run_dirs.sort();
+    run_dirs
+        .pop()
+        .ok_or_else(|| eyre!("No link backup runs in {backup_dir}"))
+}
+
 /// Enum to capture whether we expected the link command to return success or
 /// failure?
 #[derive(Debug, PartialEq)]
@@ -282,12 +1016,14 @@ impl LinkResult {
     }
 }
 
-/// Helper function to run ./up link <`home_dir`> <`dotfile_dir`> <`home_dir>/backup`.
+/// Helper function to run ./up link <`home_dir`> <`dotfile_dir`> <`home_dir>/backup`, with
+/// extra `args`.
 #[cfg(test)]
 fn run_link_cmd(
     dotfile_dir: &Utf8Path,
     home_dir: &Utf8Path,
     temp_dir: &Utf8Path,
+    args: &[&str],
     result: LinkResult,
 ) -> Result<Assert> {
     use testutils::AssertCmdExt;
@@ -303,7 +1039,8 @@ fn run_link_cmd(
             home_dir.as_str(),
         ]
         .iter(),
-    );
+    )
+    .args(args);
 
     if result.to_bool() {
         Ok(cmd.assert().eprint_stdout_stderr().try_success()?)
@@ -311,3 +1048,15 @@ fn run_link_cmd(
         Ok(cmd.assert().eprint_stdout_stderr().try_failure()?)
     }
 }
+
+/// Run a `git` command in `dir`.
+fn run_git_cmd(dir: &Utf8Path, args: &[&str]) -> Result<Assert> {
+    use testutils::AssertCmdExt;
+
+    Ok(assert_cmd::Command::new("git")
+        .args(["-C", dir.as_str()])
+        .args(args)
+        .assert()
+        .eprint_stdout_stderr()
+        .try_success()?)
+}
@@ -48,6 +48,14 @@ fn test_up_list_passing() -> Result<()> {
         .collect_vec(),
     );
 
+    // Only tasks using the run libraries passed.
+    ensure_eq!(
+        vec!["link"],
+        check_list(&["--run-libs=link,git"], &envs, &temp_dir)?
+            .split_whitespace()
+            .collect_vec(),
+    );
+
     Ok(())
 }
 
@@ -0,0 +1,95 @@
+use camino::Utf8Path;
+use color_eyre::Result;
+use std::time::Duration;
+use testutils::AssertCmdExt;
+use testutils::ensure_eq;
+use testutils::ensure_utils;
+
+/// Check that `up logs` lists the tasks in a run and shows their output.
+#[test]
+fn test_up_logs() -> Result<()> {
+    let temp_dir = testutils::temp_dir("up", testutils::function_path!()).unwrap();
+    testutils::copy_all(
+        &testutils::fixtures_subdir(testutils::function_path!())?,
+        &temp_dir,
+    )
+    .unwrap();
+
+    let mut cmd = testutils::crate_binary_cmd("up", &temp_dir)?;
+    cmd.args([
+        "--config",
+        temp_dir.join("up_config_dir/up.yaml").as_str(),
+        "run",
+    ]);
+    let assert = cmd.assert().eprint_stdout_stderr().try_failure()?;
+    let stderr = String::from_utf8(assert.get_output().stderr.clone())?;
+    ensure_utils::contains(&stderr, "view with `up logs broken`")?;
+
+    // With no task, list the tasks in the run.
+    let stdout = up_logs(&temp_dir, &[], true)?;
+    ensure_utils::contains_all(
+        &stdout,
+        &["Run ", "broken  failed   21 B", "hello   passed   36 B"],
+    )?;
+
+    let stdout = up_logs(&temp_dir, &["hello"], true)?;
+    ensure_eq!("hello from stdout\nhello from stderr\n", stdout);
+
+    // Following a task that has finished prints its output and exits.
+    let stdout = up_logs(&temp_dir, &["--follow", "broken"], true)?;
+    ensure_eq!("something went wrong\n", stdout);
+
+    // Runs can be chosen by a prefix of their ID.
+    let run_id = temp_dir
+        .join("up/runs")
+        .read_dir_utf8()?
+        .next()
+        .ok_or_else(|| color_eyre::eyre::eyre!("Expected a run directory."))??
+        .file_name()
+        .to_owned();
+    let stdout = up_logs(&temp_dir, &["--run", &run_id[..10], "hello"], true)?;
+    ensure_eq!("hello from stdout\nhello from stderr\n", stdout);
+
+    up_logs(&temp_dir, &["--run", "1999", "hello"], false)?;
+    up_logs(&temp_dir, &["no_such_task"], false)?;
+    // Nothing is running, so there's nothing to follow.
+    up_logs(&temp_dir, &["--follow"], false)?;
+
+    Ok(())
+}
+
+/// Check that following a task recorded as running stops once its run has ended without
+/// recording that (e.g. because `up` was killed), rather than waiting forever.
+#[test]
+fn test_up_logs_follow_crashed() -> Result<()> {
+    let temp_dir = testutils::temp_dir("up", testutils::function_path!())?;
+    testutils::copy_all(
+        &testutils::fixtures_subdir(testutils::function_path!())?,
+        &temp_dir,
+    )?;
+
+    let mut cmd = testutils::crate_binary_cmd("up", &temp_dir)?;
+    cmd.args(["logs", "--follow"])
+        .timeout(Duration::from_secs(30));
+    let assert = cmd.assert().eprint_stdout_stderr().try_success()?;
+    ensure_eq!(
+        "output before the crash\n",
+        String::from_utf8(assert.get_output().stdout.clone())?
+    );
+
+    Ok(())
+}
+
+/// Run `up logs` with some args, returning stdout.
+fn up_logs(temp_dir: &Utf8Path, args: &[&str], success: bool) -> Result<String> {
+    let mut cmd = testutils::crate_binary_cmd("up", temp_dir)?;
+    cmd.arg("logs");
+    cmd.args(args);
+    let assert = cmd.assert().eprint_stdout_stderr();
+    let assert = if success {
+        assert.try_success()?
+    } else {
+        assert.try_failure()?
+    };
+    Ok(String::from_utf8(assert.get_output().stdout.clone())?)
+}
@@ -1,15 +1,17 @@
 use assert_cmd::cargo::cargo_bin;
+use camino::Utf8Path;
 use camino::Utf8PathBuf;
 use color_eyre::Result;
 #[cfg(target_os = "macos")]
 use duct::Expression;
 use std::collections::HashMap;
+use std::fs;
 use testutils::AssertCmdExt;
-#[cfg(target_os = "macos")]
 use testutils::ensure_eq;
 use testutils::ensure_utils;
 #[cfg(target_os = "macos")]
 use up::exec::UpDuct;
+use up::tasks::changes::Change;
 
 #[cfg(target_os = "macos")]
 const EXPECTED_DEFAULTS_VALUE: &str = r#"{
@@ -146,3 +148,331 @@ fn test_up_run_passing() -> Result<()> {
 
     Ok(())
 }
+
+/// Check that `--failed` and `--last-failed-and-dependents` only rerun the right tasks.
+#[test]
+fn test_up_run_failed() -> Result<()> {
+    let temp_dir = testutils::temp_dir("up", testutils::function_path!()).unwrap();
+    testutils::copy_all(
+        &testutils::fixtures_subdir(testutils::function_path!())?,
+        &temp_dir,
+    )
+    .unwrap();
+    let marker_dir = temp_dir.join("markers");
+    fs::create_dir(&marker_dir)?;
+
+    // First run: everything runs, the flaky task fails.
+    run_failed_cmd(&temp_dir, &[], false)?;
+    ensure_eq!((1, 1, 1), run_counts(&marker_dir)?);
+
+    // A dry run doesn't run any commands (not even run_if commands), and isn't recorded, so the
+    // next --failed run still reruns the flaky task.
+    run_failed_cmd(&temp_dir, &["--dry-run"], true)?;
+    ensure_eq!((1, 1, 1), run_counts(&marker_dir)?);
+    ensure_eq!(
+        "checked\n",
+        fs::read_to_string(marker_dir.join("pass_check"))?
+    );
+
+    // Only the flaky task is rerun, and it fails again.
+    run_failed_cmd(&temp_dir, &["--failed"], false)?;
+    ensure_eq!((1, 2, 1), run_counts(&marker_dir)?);
+
+    // Fix the flaky task, then rerun it along with the task that requires it.
+    fs::write(marker_dir.join("fixed"), "")?;
+    run_failed_cmd(&temp_dir, &["--last-failed-and-dependents"], true)?;
+    ensure_eq!((1, 3, 2), run_counts(&marker_dir)?);
+
+    // Nothing failed last time, so nothing should run.
+    run_failed_cmd(&temp_dir, &["--failed"], true)?;
+    ensure_eq!((1, 3, 2), run_counts(&marker_dir)?);
+
+    // Durations of passing tasks are remembered for progress estimates.
+    let durations = fs::read_to_string(temp_dir.join("up/task_durations.json"))?;
+    ensure_utils::contains_all(&durations, &["\"pass\"", "\"flaky\"", "\"dependent\""])?;
+
+    Ok(())
+}
+
+/// Run tasks using external run libraries.
+#[test]
+fn test_up_run_external_lib() -> Result<()> {
+    let temp_dir = testutils::temp_dir("up", testutils::function_path!()).unwrap();
+    testutils::copy_all(
+        &testutils::fixtures_subdir(testutils::function_path!())?,
+        &temp_dir,
+    )
+    .unwrap();
+    let marker_dir = temp_dir.join("markers");
+    fs::create_dir(&marker_dir)?;
+
+    // A dry run asks the library to report changes, and skips shell commands.
+    let stderr = run_external_lib_cmd(&temp_dir, &["--dry-run", EXCLUDE_FAILING], true)?;
+    ensure_utils::contains(
+        &stderr,
+        &format!("Would change: file {marker_dir}/greeting: hello"),
+    )?;
+    ensure_utils::contains(
+        &stderr,
+        &format!("Changes that would be made:\n  greet: file {marker_dir}/greeting: hello"),
+    )?;
+    ensure_utils::nothing_at(&marker_dir.join("greeting"))?;
+    ensure_utils::nothing_at(&marker_dir.join("shell"))?;
+
+    let stderr = run_external_lib_cmd(&temp_dir, &[EXCLUDE_FAILING], true)?;
+    // Changes from run libraries and from shell tasks' $UP_CHANGES files are listed.
+    ensure_utils::contains(
+        &stderr,
+        &format!(
+            "Changes made:\n  greet: file {marker_dir}/greeting: hello\n  shell: file \
+             {marker_dir}/shell: ran"
+        ),
+    )?;
+    ensure_utils::file(&marker_dir.join("greeting"), "hello\n")?;
+    ensure_utils::file(&marker_dir.join("shell"), "ran\n")?;
+
+    // The library gets the resolved data, and its stderr is captured in the task output.
+    let (run_dir, results) = up::tasks::runs::latest_run(&temp_dir.join("up"), None)?
+        .ok_or_else(|| color_eyre::eyre::eyre!("Expected a run."))?;
+    ensure_eq!(
+        Some(vec![
+            Change::new("file", format!("{marker_dir}/shell")).after("ran")
+        ]),
+        results
+            .tasks
+            .get("shell")
+            .map(|result| result.changes.clone())
+    );
+    let request = fs::read_to_string(run_dir.join("greet/request.json"))?;
+    ensure_utils::contains_all(
+        &request,
+        &[
+            r#""protocol_version":1"#,
+            &format!(r#""data":{{"path":"{marker_dir}/greeting"}}"#),
+            r#""dry_run":false"#,
+            &format!(r#""temp_dir":"{run_dir}/greet""#),
+        ],
+    )?;
+    ensure_utils::file(
+        &run_dir.join("greet/task_stdout_stderr.txt"),
+        &format!("Greeting {marker_dir}/greeting\n"),
+    )?;
+
+    let stderr = run_external_lib_cmd(&temp_dir, &["--tasks=fail"], false)?;
+    ensure_utils::contains(&stderr, "The fail library always fails.")?;
+
+    let stderr = run_external_lib_cmd(&temp_dir, &["--tasks=missing"], false)?;
+    ensure_utils::contains(&stderr, "no `up-lib-missing` executable was found")?;
+
+    Ok(())
+}
+
+/// Run tasks with `tty: true` in a pseudo-terminal, saving (and optionally stripping) their
+/// output, and recording and replaying their sessions.
+#[test]
+fn test_up_run_tty() -> Result<()> {
+    let temp_dir = testutils::temp_dir("up", testutils::function_path!()).unwrap();
+    testutils::copy_all(
+        &testutils::fixtures_subdir(testutils::function_path!())?,
+        &temp_dir,
+    )
+    .unwrap();
+
+    let mut cmd = testutils::crate_binary_cmd("up", &temp_dir)?;
+    cmd.args([
+        "--config",
+        temp_dir.join("up_config_dir/up.yaml").as_str(),
+        "run",
+    ]);
+    cmd.assert().eprint_stdout_stderr().try_success()?;
+
+    let (run_dir, results) = up::tasks::runs::latest_run(&temp_dir.join("up"), None)?
+        .ok_or_else(|| color_eyre::eyre::eyre!("Expected a run."))?;
+    ensure_eq!(
+        vec!["passed", "passed", "passed"],
+        results
+            .tasks
+            .values()
+            .map(|result| result.status.as_str())
+            .collect::<Vec<_>>()
+    );
+    ensure_utils::file(
+        &run_dir.join("tty/task_stdout_stderr.txt"),
+        "\x1b[32mhello\x1b[0m\ndone\n",
+    )?;
+    ensure_utils::file(
+        &run_dir.join("tty_stripped/task_stdout_stderr.txt"),
+        "hello\ndone\n",
+    )?;
+    ensure_utils::file(&run_dir.join("no_tty/task_stdout_stderr.txt"), "no tty\n")?;
+    ensure_utils::nothing_at(&run_dir.join("tty_stripped/task_session.cast"))?;
+
+    // Tasks with `record: true` save their unstripped sessions (one per command), which can be
+    // replayed.
+    let recording = fs::read_to_string(run_dir.join("tty/task_session.cast"))?;
+    ensure_utils::contains_all(
+        &recording,
+        &[r#""version":2"#, r#""title":"tty run command""#, r#""o","#],
+    )?;
+    ensure_utils::contains(
+        &fs::read_to_string(run_dir.join("tty/task_run_if_session.cast"))?,
+        r#""title":"tty run_if command""#,
+    )?;
+    let mut cmd = testutils::crate_binary_cmd("up", &temp_dir)?;
+    cmd.args(["logs", "--replay", "tty"]);
+    let assert = cmd.assert().eprint_stdout_stderr().try_success()?;
+    ensure_eq!(
+        "\x1b[32mhello\x1b[0m\r\ndone\r\n",
+        String::from_utf8(assert.get_output().stdout.clone())?
+    );
+
+    // `--record` runs every task in a terminal, and records it.
+    let mut cmd = testutils::crate_binary_cmd("up", &temp_dir)?;
+    cmd.args([
+        "--config",
+        temp_dir.join("up_config_dir/up.yaml").as_str(),
+        "run",
+        "--record",
+        "--tasks=no_tty",
+        // Running a single task defaults to --console, which isn't recorded.
+        "--console=false",
+    ]);
+    cmd.assert().eprint_stdout_stderr().try_success()?;
+    let (run_dir, _results) = up::tasks::runs::latest_run(&temp_dir.join("up"), None)?
+        .ok_or_else(|| color_eyre::eyre::eyre!("Expected a run."))?;
+    ensure_utils::file(&run_dir.join("no_tty/task_stdout_stderr.txt"), "tty\n")?;
+    ensure_utils::contains(
+        &fs::read_to_string(run_dir.join("no_tty/task_session.cast"))?,
+        r#""o","tty\r\n"]"#,
+    )?;
+
+    Ok(())
+}
+
+/// Check that `--no-sudo` skips tasks that need privileges, and lists them.
+#[test]
+fn test_up_run_no_sudo() -> Result<()> {
+    let temp_dir = testutils::temp_dir("up", testutils::function_path!()).unwrap();
+    testutils::copy_all(
+        &testutils::fixtures_subdir(testutils::function_path!())?,
+        &temp_dir,
+    )
+    .unwrap();
+
+    // `run_as` only needs privileges for another user, so leave that task out if there's no
+    // `nobody` user (or we're running as it).
+    let tasks_dir = temp_dir.join("up_config_dir/tasks");
+    let other_user = uzers::get_user_by_name("nobody")
+        .is_some_and(|user| user.uid() != uzers::get_current_uid());
+    if !other_user {
+        fs::remove_file(tasks_dir.join("run_as.yaml"))?;
+    }
+
+    let mut cmd = testutils::crate_binary_cmd("up", &temp_dir)?;
+    cmd.args([
+        "--config",
+        temp_dir.join("up_config_dir/up.yaml").as_str(),
+        "run",
+        "--no-sudo",
+    ]);
+    let assert = cmd.assert().eprint_stdout_stderr().try_success()?;
+    let stderr = String::from_utf8(assert.get_output().stderr.clone())?;
+    let skipped = if other_user {
+        r#"["needs_sudo", "run_as"]"#
+    } else {
+        r#"["needs_sudo"]"#
+    };
+    ensure_utils::contains(
+        &stderr,
+        &format!("Tasks skipped as they need privileges (--no-sudo): {skipped}"),
+    )?;
+
+    let (_run_dir, results) = up::tasks::runs::latest_run(&temp_dir.join("up"), None)?
+        .ok_or_else(|| color_eyre::eyre::eyre!("Expected a run."))?;
+    let mut expected = vec![("needs_sudo", "skipped"), ("plain", "passed")];
+    if other_user {
+        expected.push(("run_as", "skipped"));
+    }
+    ensure_eq!(
+        expected,
+        results
+            .tasks
+            .iter()
+            .map(|(name, result)| (name.as_str(), result.status.as_str()))
+            .collect::<Vec<_>>()
+    );
+
+    // `run_as` can't be combined with `run_lib`, which runs inside up.
+    fs::write(
+        tasks_dir.join("run_as_lib.yaml"),
+        "run_as: root\nrun_lib: defaults\ndata: {}\n",
+    )?;
+    let mut cmd = testutils::crate_binary_cmd("up", &temp_dir)?;
+    cmd.args([
+        "--config",
+        temp_dir.join("up_config_dir/up.yaml").as_str(),
+        "run",
+        "--no-sudo",
+    ]);
+    let assert = cmd.assert().eprint_stdout_stderr().try_failure()?;
+    ensure_utils::contains(
+        &String::from_utf8(assert.get_output().stderr.clone())?,
+        "Task `run_as_lib` sets both `run_as` and `run_lib`",
+    )?;
+
+    Ok(())
+}
+
+/// Skips the `test_up_run_external_lib` tasks that are expected to fail.
+const EXCLUDE_FAILING: &str = "--exclude-tasks=fail,missing";
+
+/// Run `up run` with the `test_up_run_external_lib` fixtures, returning stderr.
+fn run_external_lib_cmd(temp_dir: &Utf8Path, args: &[&str], success: bool) -> Result<String> {
+    let mut cmd = testutils::crate_binary_cmd("up", temp_dir)?;
+    cmd.env("marker_dir", temp_dir.join("markers"));
+    cmd.args([
+        "--config",
+        temp_dir.join("up_config_dir/up.yaml").as_str(),
+        "run",
+    ]);
+    cmd.args(args);
+    let assert = cmd.assert().eprint_stdout_stderr();
+    let assert = if success {
+        assert.try_success()?
+    } else {
+        assert.try_failure()?
+    };
+    Ok(String::from_utf8(assert.get_output().stderr.clone())?)
+}
+
+/// Run `up run` with the `test_up_run_failed` fixtures.
+fn run_failed_cmd(temp_dir: &Utf8Path, args: &[&str], success: bool) -> Result<()> {
+    let mut cmd = testutils::crate_binary_cmd("up", temp_dir)?;
+    cmd.env("marker_dir", temp_dir.join("markers"));
+    cmd.args([
+        "--config",
+        temp_dir.join("up_config_dir/up.yaml").as_str(),
+        "run",
+    ]);
+    cmd.args(args);
+    let assert = cmd.assert().eprint_stdout_stderr();
+    if success {
+        assert.try_success()?;
+    } else {
+        assert.try_failure()?;
+    }
+    Ok(())
+}
+
+/// Number of times the (pass, flaky, dependent) tasks have run.
+fn run_counts(marker_dir: &Utf8Path) -> Result<(usize, usize, usize)> {
+    let count = |name: &str| -> Result<usize> {
+        let path = marker_dir.join(name);
+        Ok(if path.exists() {
+            fs::read_to_string(path)?.lines().count()
+        } else {
+            0
+        })
+    };
+    Ok((count("pass")?, count("flaky")?, count("dependent")?))
+}
@@ -0,0 +1,132 @@
+use color_eyre::Result;
+use color_eyre::eyre::eyre;
+use serde_derive::Deserialize;
+use std::fs;
+use std::sync::Arc;
+use std::sync::Mutex;
+use testutils::ensure_eq;
+use testutils::ensure_utils;
+use up::runner::Runner;
+use up::tasks::ResolveEnv;
+use up::tasks::TaskError;
+use up::tasks::run_lib::RunContext;
+use up::tasks::run_lib::RunLib;
+use up::tasks::runs::TaskResultStatus;
+use up::tasks::task::TaskStatus;
+
+/// Data for the `record` library.
+#[derive(Debug, Default, Deserialize)]
+struct RecordData {
+    /// Message to record.
+    message: String,
+}
+
+impl ResolveEnv for RecordData {
+    fn resolve_env<F>(&mut self, env_fn: F) -> Result<(), TaskError>
+    where
+        F: Fn(&str) -> Result<String, TaskError>,
+    {
+        self.message = env_fn(&self.message)?;
+        Ok(())
+    }
+}
+
+/// Library that records the messages it is run with.
+struct Record {
+    /// Messages seen so far.
+    messages: Arc<Mutex<Vec<String>>>,
+}
+
+impl RunLib for Record {
+    type Data = RecordData;
+
+    fn name(&self) -> &'static str {
+        "record"
+    }
+
+    fn run(&self, data: RecordData, _context: &RunContext) -> Result<TaskStatus> {
+        self.messages
+            .lock()
+            .map_err(|e| eyre!("{e}"))?
+            .push(data.message);
+        Ok(TaskStatus::Passed)
+    }
+}
+
+/// Check that the runner API runs registered libraries and returns each task's result.
+#[test]
+fn test_runner_custom_lib() -> Result<()> {
+    let temp_dir = testutils::temp_dir("up", testutils::function_path!()).unwrap();
+    testutils::copy_all(
+        &testutils::fixtures_subdir(testutils::function_path!())?,
+        &temp_dir,
+    )
+    .unwrap();
+    let config_path = temp_dir.join("up_config_dir/up.yaml");
+    let messages = Arc::new(Mutex::new(Vec::new()));
+
+    let results = Runner::new(&config_path)
+        .temp_dir(temp_dir.join("up"))
+        .exclude_tasks(["excluded"])
+        .register_lib(Record {
+            messages: Arc::clone(&messages),
+        })
+        .run()?;
+    ensure_eq!(
+        vec!["custom", "failing"],
+        results.tasks.keys().collect::<Vec<_>>()
+    );
+    let custom = results
+        .tasks
+        .get("custom")
+        .ok_or_else(|| eyre!("No custom"))?;
+    ensure_eq!(TaskResultStatus::Passed, custom.status);
+    let failing = results
+        .tasks
+        .get("failing")
+        .ok_or_else(|| eyre!("No failing"))?;
+    ensure_eq!(TaskResultStatus::Failed, failing.status);
+    let error = failing.error.as_deref().unwrap_or_default();
+    ensure_utils::contains(error, "exit code 3")?;
+    ensure_eq!(
+        vec!["hello world".to_owned()],
+        *messages.lock().map_err(|e| eyre!("{e}"))?
+    );
+
+    // Libraries that don't support dry runs are skipped in them.
+    let results = Runner::new(&config_path)
+        .temp_dir(temp_dir.join("up"))
+        .tasks(["custom"])
+        .dry_run(true)
+        .register_lib(Record {
+            messages: Arc::clone(&messages),
+        })
+        .run()?;
+    ensure_eq!(
+        Some(TaskResultStatus::Skipped),
+        results.tasks.get("custom").map(|result| result.status)
+    );
+    ensure_eq!(1, messages.lock().map_err(|e| eyre!("{e}"))?.len());
+
+    // Without the library registered, the task fails.
+    let results = Runner::new(&config_path)
+        .temp_dir(temp_dir.join("up"))
+        .tasks(["custom"])
+        .run()?;
+    ensure_eq!(
+        Some(TaskResultStatus::Failed),
+        results.tasks.get("custom").map(|result| result.status)
+    );
+
+    // Old runs are only removed if asked for (keeping the current run and the one before).
+    let runs_dir = temp_dir.join("up/runs");
+    ensure_eq!(3, fs::read_dir(&runs_dir)?.count());
+    Runner::new(&config_path)
+        .temp_dir(temp_dir.join("up"))
+        .tasks(["custom"])
+        .apply_retention(true)
+        .run()?;
+    ensure_eq!(2, fs::read_dir(&runs_dir)?.count());
+
+    Ok(())
+}
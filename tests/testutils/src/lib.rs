@@ -97,7 +97,7 @@ pub fn fixtures_subdir(function_path: &str) -> Result<Utf8PathBuf> {
 ///
 /// ```rust
 /// # fn test_requiring_tempdir() -> color_eyre::Result<()> {
-/// let temp_dir = testutils::temp_dir(testutils::function_path!())?;
+/// let temp_dir = testutils::temp_dir("up", testutils::function_path!())?;
 /// # Ok(())
 /// # }
 /// ```
@@ -0,0 +1,90 @@
+use assert_cmd::cargo::cargo_bin;
+use camino::Utf8Path;
+use color_eyre::Result;
+use color_eyre::eyre::bail;
+use std::fs;
+use std::fs::File;
+use std::process::Child;
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+use testutils::ensure_eq;
+
+/// Check that `up watch` re-runs only the tasks whose inputs changed.
+#[test]
+fn test_up_watch() -> Result<()> {
+    let temp_dir = testutils::temp_dir("up", testutils::function_path!()).unwrap();
+    testutils::copy_all(
+        &testutils::fixtures_subdir(testutils::function_path!())?,
+        &temp_dir,
+    )
+    .unwrap();
+    let marker_dir = temp_dir.join("markers");
+    fs::create_dir(&marker_dir)?;
+
+    let mut child = WatchProcess(spawn_watch(&temp_dir)?);
+
+    // Changing a path in the build task's `watch` list only re-runs the build task.
+    wait_for_run(&marker_dir.join("build"), || {
+        fs::write(temp_dir.join("src/input.txt"), "changed")
+    })?;
+    ensure_eq!(false, marker_dir.join("other").exists());
+
+    // Changing a task's config re-runs that task.
+    wait_for_run(&marker_dir.join("other"), || {
+        fs::write(
+            temp_dir.join("up_config_dir/tasks/other.yaml"),
+            "run_cmd: [\"/bin/sh\", \"-c\", \"echo ran >> $marker_dir/other\"]\n",
+        )
+    })?;
+
+    child.0.kill()?;
+    Ok(())
+}
+
+/// Kills the watch process if the test fails before it does.
+struct WatchProcess(Child);
+
+impl Drop for WatchProcess {
+    fn drop(&mut self) {
+        _ = self.0.kill();
+        _ = self.0.wait();
+    }
+}
+
+/// Start `up watch` in the background, logging to files in the temp dir.
+fn spawn_watch(temp_dir: &Utf8Path) -> Result<Child> {
+    let mut cmd = Command::new(cargo_bin("up"));
+    cmd.env("TMPDIR", temp_dir.join("up_temp_dir"));
+    cmd.env("marker_dir", temp_dir.join("markers"));
+    cmd.args([
+        "--log-level=trace",
+        "--up-dir",
+        temp_dir.join("up").as_str(),
+        "--config",
+        temp_dir.join("up_config_dir/up.yaml").as_str(),
+        "watch",
+        "--debounce-ms=100",
+    ]);
+    cmd.stdout(File::create(temp_dir.join("watch_stdout.txt"))?);
+    cmd.stderr(File::create(temp_dir.join("watch_stderr.txt"))?);
+    Ok(cmd.spawn()?)
+}
+
+/**
+Keep making a change until the marker file shows that the task ran.
+
+The watcher may not have started when we first make the change, so retry until it notices.
+*/
+fn wait_for_run(marker: &Utf8Path, mut change: impl FnMut() -> std::io::Result<()>) -> Result<()> {
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(30) {
+        change()?;
+        thread::sleep(Duration::from_millis(500));
+        if marker.exists() {
+            return Ok(());
+        }
+    }
+    bail!("Timed out waiting for {marker} to be created.");
+}